@@ -0,0 +1,1111 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+const ROM_ADDRESS: usize = 0x200; // where the programs are loaded
+const MAX_ADDRESS: usize = 0xFFFF; // the end of the XO-CHIP memory
+const MAX_MACRO_DEPTH: usize = 256; // stops macros that expand into themselves
+
+// where in the source the problem is, lines and columns start at 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+// the source lines of the instructions, for debugging a program in its source
+// the instructions of a macro belong to the lines of its body
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    lines: BTreeMap<usize, usize>, // the address of an instruction and its line
+}
+
+impl SourceMap {
+    // the line of the instruction at the address
+    pub fn line_of(&self, address: usize) -> Option<usize> {
+        self.lines.get(&address).copied()
+    }
+
+    // the first instruction of the line, or of the next line with instructions,
+    // returns its address and its line
+    pub fn address_of(&self, line: usize) -> Option<(usize, usize)> {
+        self.lines
+            .iter()
+            .filter(|(_, &instruction_line)| instruction_line >= line)
+            .min_by_key(|(&address, &instruction_line)| (instruction_line, address))
+            .map(|(&address, &instruction_line)| (address, instruction_line))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+}
+
+impl Token {
+    fn error(&self, message: String) -> AssemblerError {
+        AssemblerError {
+            line: self.line,
+            column: self.column,
+            message,
+        }
+    }
+}
+
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+// an address that has to be filled in once the label is defined
+struct Fixup {
+    address: usize,
+    label: Token,
+    long: bool, // the 16 bit operand of i := long, otherwise NNN of the opcode
+}
+
+// the open structured blocks, the addresses are the jumps to fill in at their end
+enum Block {
+    If {
+        token: Token,
+        jump: usize,
+    },
+    Else {
+        token: Token,
+        jump: usize,
+    },
+    Loop {
+        token: Token,
+        start: usize,
+        breaks: Vec<usize>,
+    },
+}
+
+// the conditions of if and while
+#[derive(Clone, Copy)]
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::Equal(x, operand) => Condition::NotEqual(x, operand),
+            Condition::NotEqual(x, operand) => Condition::Equal(x, operand),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    // the instruction that skips the next one unless the condition holds
+    fn skip_unless(self) -> u16 {
+        match self {
+            Condition::Equal(x, Operand::Byte(byte)) => 0x4000 | x16(x) | byte as u16,
+            Condition::NotEqual(x, Operand::Byte(byte)) => 0x3000 | x16(x) | byte as u16,
+            Condition::Equal(x, Operand::Register(y)) => 0x9000 | x16(x) | y16(y),
+            Condition::NotEqual(x, Operand::Register(y)) => 0x5000 | x16(x) | y16(y),
+            Condition::Key(x) => 0xE0A1 | x16(x),
+            Condition::NotKey(x) => 0xE09E | x16(x),
+        }
+    }
+}
+
+fn x16(x: u8) -> u16 {
+    (x as u16) << 8
+}
+
+fn y16(y: u8) -> u16 {
+    (y as u16) << 4
+}
+
+// the jumps of the control structures, the blocks can be past 0xFFF with :org on XO-CHIP
+fn jump_opcode(token: &Token, target: usize) -> Result<u16, AssemblerError> {
+    if target > 0xFFF {
+        return Err(token.error(format!("{} doesn't fit into 12 bits", target)));
+    }
+    Ok(0x1000 | target as u16)
+}
+
+// compiles Octo source into a ROM that is loaded at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    assemble_with_source_map(source).map(|(rom, _)| rom)
+}
+
+pub fn assemble_with_source_map(source: &str) -> Result<(Vec<u8>, SourceMap), AssemblerError> {
+    let mut assembler = Assembler::new(tokenize(source));
+    while assembler.position < assembler.tokens.len() {
+        assembler.statement()?;
+    }
+    assembler.finish()
+}
+
+// splits the source on whitespace, # starts a comment
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let mut start = None;
+        for (column, character) in line.char_indices().chain([(line.len(), ' ')]) {
+            if character == '#' && start.is_none() {
+                break;
+            }
+            match (character.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(token_start)) => {
+                    tokens.push(Token {
+                        text: line[token_start..column].to_string(),
+                        line: line_index + 1,
+                        column: token_start + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse::<i64>().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or_else(|| text.strip_prefix('V'))?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+struct Assembler {
+    tokens: Vec<Token>,
+    position: usize,
+    rom: Vec<u8>,
+    address: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    macro_ends: Vec<usize>, // where the expansions being assembled end, the innermost last
+    source_map: SourceMap,
+}
+
+impl Assembler {
+    fn new(tokens: Vec<Token>) -> Assembler {
+        Assembler {
+            tokens,
+            position: 0,
+            rom: Vec::new(),
+            address: ROM_ADDRESS,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            macro_ends: Vec::new(),
+            source_map: SourceMap::default(),
+        }
+    }
+
+    fn finish(mut self) -> Result<(Vec<u8>, SourceMap), AssemblerError> {
+        if let Some(block) = self.blocks.last() {
+            let (token, message) = match block {
+                Block::If { token, .. } | Block::Else { token, .. } => (token, "if without end"),
+                Block::Loop { token, .. } => (token, "loop without again"),
+            };
+            return Err(token.error(message.to_string()));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(&fixup.label.text) {
+                Some(address) => *address,
+                None => {
+                    return Err(fixup
+                        .label
+                        .error(format!("Undefined name {}", fixup.label.text)))
+                }
+            };
+            if fixup.long {
+                self.write_word(fixup.address, address as u16);
+            } else if address > 0xFFF {
+                return Err(fixup.label.error(format!(
+                    "Address of {} doesn't fit into 12 bits",
+                    fixup.label.text
+                )));
+            } else {
+                let opcode = self.read_word(fixup.address) | address as u16;
+                self.write_word(fixup.address, opcode);
+            }
+        }
+        Ok((self.rom, self.source_map))
+    }
+
+    fn next(&mut self) -> Result<Token, AssemblerError> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            }
+            None => {
+                let last = self.tokens.last().cloned().unwrap_or(Token {
+                    text: String::new(),
+                    line: 1,
+                    column: 1,
+                });
+                Err(last.error("Unexpected end of the source".to_string()))
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens
+            .get(self.position)
+            .map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, text: &str) -> Result<Token, AssemblerError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(token.error(format!("Expected {}, found {}", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn write_word(&mut self, address: usize, word: u16) {
+        self.write_byte(address, (word >> 8) as u8);
+        self.write_byte(address + 1, word as u8);
+    }
+
+    fn write_byte(&mut self, address: usize, byte: u8) {
+        let index = address - ROM_ADDRESS;
+        if index >= self.rom.len() {
+            self.rom.resize(index + 1, 0);
+        }
+        self.rom[index] = byte;
+    }
+
+    fn read_word(&self, address: usize) -> u16 {
+        let index = address - ROM_ADDRESS;
+        (self.rom[index] as u16) << 8 | self.rom[index + 1] as u16
+    }
+
+    fn emit_byte(&mut self, token: &Token, byte: u8) -> Result<(), AssemblerError> {
+        if self.address > MAX_ADDRESS {
+            return Err(token.error("The program doesn't fit into the memory".to_string()));
+        }
+        self.write_byte(self.address, byte);
+        self.address += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, token: &Token, opcode: u16) -> Result<(), AssemblerError> {
+        self.source_map.lines.insert(self.address, token.line);
+        self.emit_byte(token, (opcode >> 8) as u8)?;
+        self.emit_byte(token, opcode as u8)
+    }
+
+    // the second word of F000 NNNN, it's not an instruction of its own
+    fn emit_operand(&mut self, token: &Token, word: u16) -> Result<(), AssemblerError> {
+        self.emit_byte(token, (word >> 8) as u8)?;
+        self.emit_byte(token, word as u8)
+    }
+
+    fn register(&mut self) -> Result<u8, AssemblerError> {
+        let token = self.next()?;
+        match parse_register(&token.text).or_else(|| self.aliases.get(&token.text).copied()) {
+            Some(register) => Ok(register),
+            None => Err(token.error(format!("Expected a register, found {}", token.text))),
+        }
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        parse_register(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    // a number or a constant
+    fn value(&mut self) -> Result<(Token, i64), AssemblerError> {
+        let token = self.next()?;
+        if let Some(value) = parse_number(&token.text) {
+            return Ok((token, value));
+        }
+        if let Some(value) = self.constants.get(&token.text) {
+            return Ok((token.clone(), *value));
+        }
+        if let Some(address) = self.labels.get(&token.text) {
+            return Ok((token.clone(), *address as i64));
+        }
+        Err(token.error(format!("Expected a number, found {}", token.text)))
+    }
+
+    fn byte(&mut self) -> Result<u8, AssemblerError> {
+        let (token, value) = self.value()?;
+        if !(-128..=255).contains(&value) {
+            return Err(token.error(format!("{} doesn't fit into a byte", value)));
+        }
+        Ok(value as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AssemblerError> {
+        let (token, value) = self.value()?;
+        if !(0..=15).contains(&value) {
+            return Err(token.error(format!("{} doesn't fit into a nibble", value)));
+        }
+        Ok(value as u8)
+    }
+
+    // emits an instruction with a 12 bit address, labels can be defined later
+    fn emit_with_address(&mut self, token: &Token, opcode: u16) -> Result<(), AssemblerError> {
+        let target = self.next()?;
+        let value = parse_number(&target.text)
+            .or_else(|| self.constants.get(&target.text).copied())
+            .or_else(|| self.labels.get(&target.text).map(|address| *address as i64));
+        match value {
+            Some(address) if (0..=0xFFF).contains(&address) => {
+                self.emit(token, opcode | address as u16)
+            }
+            Some(address) => Err(target.error(format!("{} doesn't fit into 12 bits", address))),
+            None => {
+                self.fixups.push(Fixup {
+                    address: self.address,
+                    label: target,
+                    long: false,
+                });
+                self.emit(token, opcode)
+            }
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), AssemblerError> {
+        let token = self.next()?;
+        let text = token.text.as_str();
+        if let Some(value) = parse_number(text) {
+            // byte literals go to the output as they are
+            if !(-128..=255).contains(&value) {
+                return Err(token.error(format!("{} doesn't fit into a byte", value)));
+            }
+            return self.emit_byte(&token, value as u8);
+        }
+        if self.is_register(text) {
+            self.position -= 1;
+            return self.register_statement();
+        }
+        if self.macros.contains_key(text) {
+            return self.expand_macro(&token);
+        }
+        match text {
+            ":" => {
+                let name = self.next()?;
+                if self.labels.contains_key(&name.text) {
+                    return Err(name.error(format!("{} is already defined", name.text)));
+                }
+                self.labels.insert(name.text, self.address);
+            }
+            ":const" => {
+                let name = self.next()?;
+                let (_, value) = self.value()?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name.text, register);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    self.calc()?
+                } else {
+                    self.value()?.1
+                };
+                self.emit_byte(&token, value as u8)?;
+            }
+            ":org" => {
+                let (value_token, address) = self.value()?;
+                if !(ROM_ADDRESS as i64..=MAX_ADDRESS as i64).contains(&address) {
+                    return Err(value_token
+                        .error(format!("{:#X} is outside of the program memory", address)));
+                }
+                self.address = address as usize;
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => self.emit_with_address(&token, 0x2000)?,
+            ":breakpoint" | ":monitor" => {
+                // debugger hints of Octo, there is nothing to emit
+                self.next()?;
+                if text == ":monitor" {
+                    self.next()?;
+                }
+            }
+            "clear" => self.emit(&token, 0x00E0)?,
+            "return" | ";" => self.emit(&token, 0x00EE)?,
+            "exit" => self.emit(&token, 0x00FD)?,
+            "lores" => self.emit(&token, 0x00FE)?,
+            "hires" => self.emit(&token, 0x00FF)?,
+            "scroll-down" => {
+                let rows = self.nibble()?;
+                self.emit(&token, 0x00C0 | rows as u16)?;
+            }
+            "scroll-up" => {
+                let rows = self.nibble()?;
+                self.emit(&token, 0x00D0 | rows as u16)?;
+            }
+            "scroll-right" => self.emit(&token, 0x00FB)?,
+            "scroll-left" => self.emit(&token, 0x00FC)?,
+            "audio" => self.emit(&token, 0xF002)?,
+            "plane" => {
+                let planes = self.nibble()?;
+                self.emit(&token, 0xF001 | x16(planes))?;
+            }
+            "jump" => self.emit_with_address(&token, 0x1000)?,
+            "jump0" => self.emit_with_address(&token, 0xB000)?,
+            "native" => self.emit_with_address(&token, 0x0000)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(&token, 0xF033 | x16(x))?;
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let opcode = if text == "save" { 0x5002 } else { 0x5003 };
+                    self.emit(&token, opcode | x16(x) | y16(y))?;
+                } else {
+                    let opcode = if text == "save" { 0xF055 } else { 0xF065 };
+                    self.emit(&token, opcode | x16(x))?;
+                }
+            }
+            "saveflags" => {
+                let x = self.register()?;
+                self.emit(&token, 0xF075 | x16(x))?;
+            }
+            "loadflags" => {
+                let x = self.register()?;
+                self.emit(&token, 0xF085 | x16(x))?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let height = self.nibble()?;
+                self.emit(&token, 0xD000 | x16(x) | y16(y) | height as u16)?;
+            }
+            "i" => self.index_statement()?,
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let opcode = match text {
+                    "delay" => 0xF015,
+                    "buzzer" => 0xF018,
+                    _ => 0xF03A,
+                };
+                self.emit(&token, opcode | x16(x))?;
+            }
+            "if" => self.if_statement(&token)?,
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => {
+                    let end_jump = self.address;
+                    self.emit(&token, 0x1000)?;
+                    self.patch_jump(&token, jump, self.address)?;
+                    self.blocks.push(Block::Else {
+                        token: token.clone(),
+                        jump: end_jump,
+                    });
+                }
+                _ => return Err(token.error("else without if".to_string())),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch_jump(&token, jump, self.address)?
+                }
+                _ => return Err(token.error("end without if".to_string())),
+            },
+            "loop" => self.blocks.push(Block::Loop {
+                token: token.clone(),
+                start: self.address,
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                let condition = self.condition()?;
+                let jump = self.address + 2;
+                self.emit(&token, condition.negate().skip_unless())?;
+                self.emit(&token, 0x1000)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return Err(token.error("while outside of a loop".to_string())),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    let opcode = jump_opcode(&token, start)?;
+                    self.emit(&token, opcode)?;
+                    for jump in breaks {
+                        self.patch_jump(&token, jump, self.address)?;
+                    }
+                }
+                _ => return Err(token.error("again without loop".to_string())),
+            },
+            _ if text.starts_with(':') => {
+                return Err(token.error(format!("Unknown directive {}", text)))
+            }
+            _ => {
+                // calling a subroutine by its name
+                self.position -= 1;
+                self.emit_with_address(&token, 0x2000)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn patch_jump(
+        &mut self,
+        token: &Token,
+        jump: usize,
+        target: usize,
+    ) -> Result<(), AssemblerError> {
+        let opcode = jump_opcode(token, target)?;
+        self.write_word(jump, opcode);
+        Ok(())
+    }
+
+    fn register_statement(&mut self) -> Result<(), AssemblerError> {
+        let token = self.tokens[self.position].clone();
+        let x = self.register()?;
+        let operator = self.next()?;
+        let operand_is_register = self.peek().map(|text| self.is_register(text)) == Some(true);
+        let register_opcode = match operator.text.as_str() {
+            ":=" => 0x8000,
+            "|=" => 0x8001,
+            "&=" => 0x8002,
+            "^=" => 0x8003,
+            "+=" => 0x8004,
+            "-=" => 0x8005,
+            ">>=" => 0x8006,
+            "=-" => 0x8007,
+            "<<=" => 0x800E,
+            _ => {
+                return Err(operator.error(format!("Unknown operator {}", operator.text)));
+            }
+        };
+        if operand_is_register {
+            let y = self.register()?;
+            return self.emit(&token, register_opcode | x16(x) | y16(y));
+        }
+        match (operator.text.as_str(), self.peek()) {
+            (":=", Some("random")) => {
+                self.next()?;
+                let byte = self.byte()?;
+                self.emit(&token, 0xC000 | x16(x) | byte as u16)
+            }
+            (":=", Some("key")) => {
+                self.next()?;
+                self.emit(&token, 0xF00A | x16(x))
+            }
+            (":=", Some("delay")) => {
+                self.next()?;
+                self.emit(&token, 0xF007 | x16(x))
+            }
+            (":=", _) => {
+                let byte = self.byte()?;
+                self.emit(&token, 0x6000 | x16(x) | byte as u16)
+            }
+            ("+=", _) => {
+                let byte = self.byte()?;
+                self.emit(&token, 0x7000 | x16(x) | byte as u16)
+            }
+            ("-=", _) => {
+                let byte = self.byte()?;
+                self.emit(&token, 0x7000 | x16(x) | byte.wrapping_neg() as u16)
+            }
+            _ => Err(operator.error(format!("{} needs a register", operator.text))),
+        }
+    }
+
+    fn index_statement(&mut self) -> Result<(), AssemblerError> {
+        let token = self.tokens[self.position - 1].clone();
+        let operator = self.next()?;
+        match (operator.text.as_str(), self.peek()) {
+            ("+=", _) => {
+                let x = self.register()?;
+                self.emit(&token, 0xF01E | x16(x))
+            }
+            (":=", Some("hex")) => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(&token, 0xF029 | x16(x))
+            }
+            (":=", Some("bighex")) => {
+                self.next()?;
+                let x = self.register()?;
+                self.emit(&token, 0xF030 | x16(x))
+            }
+            (":=", Some("long")) => {
+                self.next()?;
+                let target = self.next()?;
+                let value = parse_number(&target.text)
+                    .or_else(|| self.constants.get(&target.text).copied())
+                    .or_else(|| self.labels.get(&target.text).map(|address| *address as i64));
+                self.emit(&token, 0xF000)?;
+                match value {
+                    Some(address) if (0..=0xFFFF).contains(&address) => {
+                        self.emit_operand(&token, address as u16)
+                    }
+                    Some(address) => {
+                        Err(target.error(format!("{} doesn't fit into 16 bits", address)))
+                    }
+                    None => {
+                        self.fixups.push(Fixup {
+                            address: self.address,
+                            label: target,
+                            long: true,
+                        });
+                        self.emit_operand(&token, 0)
+                    }
+                }
+            }
+            (":=", _) => self.emit_with_address(&token, 0xA000),
+            _ => Err(operator.error(format!("Unknown operator {}", operator.text))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssemblerError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        match operator.text.as_str() {
+            "key" => return Ok(Condition::Key(x)),
+            "-key" => return Ok(Condition::NotKey(x)),
+            "==" | "!=" => {}
+            _ => {
+                return Err(operator.error(format!("Unknown comparison {}", operator.text)));
+            }
+        }
+        let operand = if self.peek().map(|text| self.is_register(text)) == Some(true) {
+            Operand::Register(self.register()?)
+        } else {
+            Operand::Byte(self.byte()?)
+        };
+        if operator.text == "==" {
+            Ok(Condition::Equal(x, operand))
+        } else {
+            Ok(Condition::NotEqual(x, operand))
+        }
+    }
+
+    fn if_statement(&mut self, token: &Token) -> Result<(), AssemblerError> {
+        let condition = self.condition()?;
+        let form = self.next()?;
+        match form.text.as_str() {
+            // the next statement is skipped unless the condition holds
+            "then" => self.emit(token, condition.skip_unless()),
+            // a jump to else or end that is skipped when the condition holds
+            "begin" => {
+                self.emit(token, condition.negate().skip_unless())?;
+                self.blocks.push(Block::If {
+                    token: token.clone(),
+                    jump: self.address,
+                });
+                self.emit(token, 0x1000)
+            }
+            _ => Err(form.error(format!("Expected then or begin, found {}", form.text))),
+        }
+    }
+
+    // :macro name parameters { body }
+    fn define_macro(&mut self) -> Result<(), AssemblerError> {
+        let name = self.next()?;
+        let mut parameters = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            parameters.push(token.text);
+        }
+        let body = self.braced_tokens()?;
+        self.macros.insert(name.text, Macro { parameters, body });
+        Ok(())
+    }
+
+    // the tokens up to the matching closing brace, the opening one is already read
+    fn braced_tokens(&mut self) -> Result<Vec<Token>, AssemblerError> {
+        let mut depth = 1;
+        let mut tokens = Vec::new();
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(tokens);
+                    }
+                }
+                _ => {}
+            }
+            tokens.push(token);
+        }
+    }
+
+    // replaces the macro call with its body, the arguments are single tokens
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AssemblerError> {
+        // the call is inside of the expansions that end after it
+        let call = self.position - 1;
+        while self.macro_ends.last().is_some_and(|&end| end <= call) {
+            self.macro_ends.pop();
+        }
+        if self.macro_ends.len() >= MAX_MACRO_DEPTH {
+            return Err(token.error("Macros nested too deeply".to_string()));
+        }
+        let parameter_count = self.macros[&token.text].parameters.len();
+        let mut arguments = HashMap::new();
+        for index in 0..parameter_count {
+            let argument = self.next()?;
+            let parameter = self.macros[&token.text].parameters[index].clone();
+            arguments.insert(parameter, argument.text);
+        }
+        let expansion: Vec<Token> = self.macros[&token.text]
+            .body
+            .iter()
+            .map(|body_token| Token {
+                text: arguments
+                    .get(&body_token.text)
+                    .cloned()
+                    .unwrap_or_else(|| body_token.text.clone()),
+                line: body_token.line,
+                column: body_token.column,
+            })
+            .collect();
+        let length = expansion.len();
+        self.tokens.splice(self.position..self.position, expansion);
+        for end in &mut self.macro_ends {
+            *end += length;
+        }
+        self.macro_ends.push(self.position + length);
+        Ok(())
+    }
+
+    // { expression }, evaluated right to left without operator precedence like in Octo
+    fn calc(&mut self) -> Result<i64, AssemblerError> {
+        let open = self.expect("{")?;
+        let tokens = self.braced_tokens()?;
+        let (value, position) = self.expression(&tokens, 0, &open)?;
+        if let Some(token) = tokens.get(position) {
+            return Err(token.error(format!("Unexpected {}", token.text)));
+        }
+        Ok(value)
+    }
+
+    fn expression(
+        &self,
+        tokens: &[Token],
+        position: usize,
+        open: &Token,
+    ) -> Result<(i64, usize), AssemblerError> {
+        let (left, position) = self.term(tokens, position, open)?;
+        let operator = match tokens.get(position) {
+            Some(token) if token.text != ")" => token,
+            _ => return Ok((left, position)),
+        };
+        let (right, next) = self.expression(tokens, position + 1, open)?;
+        let value = match operator.text.as_str() {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => {
+                return Err(operator.error("Division by zero".to_string()));
+            }
+            "/" => left / right,
+            "%" => left % right,
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            _ => return Err(operator.error(format!("Unknown operator {}", operator.text))),
+        };
+        Ok((value, next))
+    }
+
+    fn term(
+        &self,
+        tokens: &[Token],
+        position: usize,
+        open: &Token,
+    ) -> Result<(i64, usize), AssemblerError> {
+        let token = match tokens.get(position) {
+            Some(token) => token,
+            None => return Err(open.error("Unfinished expression".to_string())),
+        };
+        match token.text.as_str() {
+            "(" => {
+                let (value, position) = self.expression(tokens, position + 1, open)?;
+                match tokens.get(position) {
+                    Some(close) if close.text == ")" => Ok((value, position + 1)),
+                    _ => Err(token.error("( without )".to_string())),
+                }
+            }
+            "-" => {
+                let (value, position) = self.term(tokens, position + 1, open)?;
+                Ok((-value, position))
+            }
+            "~" => {
+                let (value, position) = self.term(tokens, position + 1, open)?;
+                Ok((!value, position))
+            }
+            "HERE" => Ok((self.address as i64, position + 1)),
+            text => {
+                let value = parse_number(text)
+                    .or_else(|| self.constants.get(text).copied())
+                    .or_else(|| self.labels.get(text).map(|address| *address as i64));
+                match value {
+                    Some(value) => Ok((value, position + 1)),
+                    None => Err(token.error(format!("Undefined name {}", text))),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::CHIP8;
+    use crate::chip8_audio::DummyCHIP8Audio;
+    use crate::chip8_display::DummyCHIP8Display;
+    use crate::chip8_error::Chip8Error;
+    use crate::chip8_keypad::DummyCHIP8Keypad;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_instructions() {
+        let source = "
+            : main
+                clear
+                v0 := 5       # 6005
+                v1 += v0
+                v2 -= 1
+                v3 := random 0xFF
+                i := sprite
+                sprite v0 v1 5
+                i := long sprite
+                save v2 - v4
+                jump main
+            : sprite
+                0xF0 0x90 0b11110000
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                0x00, 0xE0, 0x60, 0x05, 0x81, 0x04, 0x72, 0xFF, 0xC3, 0xFF, 0xA2, 0x16, 0xD0, 0x15,
+                0xF0, 0x00, 0x02, 0x16, 0x52, 0x42, 0x12, 0x00, 0xF0, 0x90, 0xF0,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_directives() {
+        let source = "
+            :const SPEED 3
+            :alias counter v4
+            :calc DOUBLE { SPEED * 2 + 1 }
+            :macro add-twice register amount {
+                register += amount
+                register += amount
+            }
+            add-twice counter DOUBLE
+            :org 0x208
+            :byte { SPEED << 4 }
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![0x74, 0x09, 0x74, 0x09, 0x00, 0x00, 0x00, 0x00, 0x30])
+        );
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let source = "
+            loop
+                if v0 == 3 then v1 := 1
+                if v0 != v1 begin
+                    v2 := 2
+                else
+                    v2 := 3
+                end
+                while v0 key
+                v0 += 1
+            again
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                0x40, 0x03, // 200: skip unless v0 == 3
+                0x61, 0x01, // 202: v1 := 1
+                0x90, 0x10, // 204: skip if v0 != v1
+                0x12, 0x0C, // 206: jump to else
+                0x62, 0x02, // 208: v2 := 2
+                0x12, 0x0E, // 20A: jump to end
+                0x62, 0x03, // 20C: v2 := 3
+                0xE0, 0x9E, // 20E: skip if v0 key is pressed
+                0x12, 0x16, // 210: jump out of the loop
+                0x70, 0x01, // 212: v0 += 1
+                0x12, 0x00, // 214: again
+            ])
+        );
+    }
+
+    #[test]
+    fn test_source_map() {
+        let source = "
+            : main
+                v0 := 1  # 200
+
+                i := long data  # 202
+                loop  # no code
+                    v0 += 1  # 206
+                again  # 208
+            : data
+                0xFF
+        ";
+        let (rom, source_map) = assemble_with_source_map(source).unwrap();
+        assert_eq!(rom.len(), 11);
+        assert_eq!(source_map.line_of(0x200), Some(3));
+        assert_eq!(source_map.line_of(0x202), Some(5));
+        assert_eq!(source_map.line_of(0x204), None);
+        assert_eq!(source_map.line_of(0x206), Some(7));
+        assert_eq!(source_map.line_of(0x20A), None);
+        assert_eq!(source_map.address_of(3), Some((0x200, 3)));
+        assert_eq!(source_map.address_of(4), Some((0x202, 5)));
+        assert_eq!(source_map.address_of(6), Some((0x206, 7)));
+        assert_eq!(source_map.address_of(9), None);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            assemble("v0 := 5\n  jump nowhere"),
+            Err(AssemblerError {
+                line: 2,
+                column: 8,
+                message: "Undefined name nowhere".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("v0 := 300").unwrap_err().to_string(),
+            "1:7: 300 doesn't fit into a byte"
+        );
+        assert_eq!(
+            assemble("loop v0 += 1").unwrap_err().to_string(),
+            "1:1: loop without again"
+        );
+        assert_eq!(
+            assemble("va <- 1").unwrap_err().to_string(),
+            "1:4: Unknown operator <-"
+        );
+        assert_eq!(
+            assemble(":org 0x1000\nloop\n  v0 += 1\nagain")
+                .unwrap_err()
+                .to_string(),
+            "4:1: 4096 doesn't fit into 12 bits"
+        );
+        assert_eq!(
+            assemble(":org 0xFFC\nif v0 == 1 begin\n  v0 := 2\nend")
+                .unwrap_err()
+                .to_string(),
+            "4:1: 4098 doesn't fit into 12 bits"
+        );
+        assert_eq!(
+            assemble(":macro forever { v0 += 1 forever }\nforever")
+                .unwrap_err()
+                .to_string(),
+            "1:26: Macros nested too deeply"
+        );
+    }
+
+    #[test]
+    fn test_many_macros() {
+        // the calls follow each other, only the nested ones count
+        let source = ":macro twice x { x x }\n:macro add { v0 += 1 }\n".to_string()
+            + &"twice add\n".repeat(8000);
+        assert_eq!(assemble(&source).unwrap().len(), 2 * 2 * 8000);
+        let nested = ":macro a { v0 += 1 }\n:macro b { a a }\n:macro c { b b }\nc c";
+        assert_eq!(assemble(nested).unwrap(), [0x70, 0x01].repeat(8));
+    }
+
+    #[test]
+    fn test_run_assembled_program() {
+        let rom = assemble(
+            "
+            :const COUNT 10
+            : main
+                v0 := 0
+                loop
+                    v0 += 1
+                    while v0 != COUNT
+                again
+                v1 := v0
+            : done
+                jump done
+            ",
+        )
+        .unwrap();
+
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        chip8.load_from_memory(&rom);
+        let result = chip8.run_cycles(100);
+        assert!(matches!(result, Err(Chip8Error::EndlessLoop { .. })));
+        assert_eq!(chip8.registers()[1], 10);
+    }
+}
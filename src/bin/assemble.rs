@@ -0,0 +1,27 @@
+use old_rusty_platforms::assembler::assemble;
+
+fn main() {
+    let usage = "Usage: assemble <SOURCE> <ROM>";
+    let mut args = std::env::args().skip(1);
+    let source_path = args.next().expect(usage);
+    let rom_path = args.next().expect(usage);
+
+    let source = match std::fs::read_to_string(&source_path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("Can't load {}: {}", source_path, error);
+            std::process::exit(1);
+        }
+    };
+    let rom = match assemble(&source) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("{}:{}", source_path, error);
+            std::process::exit(1);
+        }
+    };
+    if let Err(error) = std::fs::write(&rom_path, rom) {
+        eprintln!("Can't write {}: {}", rom_path, error);
+        std::process::exit(1);
+    }
+}
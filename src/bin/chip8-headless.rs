@@ -0,0 +1,119 @@
+use std::path::Path;
+
+use old_rusty_platforms::chip8::CHIP8;
+use old_rusty_platforms::chip8_audio::DummyCHIP8Audio;
+use old_rusty_platforms::chip8_display::DummyCHIP8Display;
+use old_rusty_platforms::chip8_keypad::SharedCHIP8Keypad;
+use old_rusty_platforms::fault_policy::FaultPolicy;
+use old_rusty_platforms::headless::{HeadlessRun, StopReason};
+use old_rusty_platforms::key_schedule::KeySchedule;
+use old_rusty_platforms::platform::Platform;
+use old_rusty_platforms::quirks::Quirks;
+use old_rusty_platforms::random::RandomPreset;
+use old_rusty_platforms::screenshot::{encode_image, ImageFormat};
+
+const DEFAULT_FRAMES: u64 = 600; // 10 seconds
+
+fn write_image(chip: &CHIP8, format: ImageFormat, path: &str) {
+    if let Err(error) = std::fs::write(path, encode_image(chip.video_memory(), format)) {
+        eprintln!("Can't write {}: {}", path, error);
+        std::process::exit(1);
+    }
+}
+
+fn main() {
+    let usage = "Usage: chip8-headless [--platform chip8|schip|xochip] [--quirks default|vip|chip48|schip|xochip] [--faults strict|lenient] [--random xorshift|timing] [--seed N] [--frames N] [--until loop|key]... [--keys SCHEDULE] [--dump-every K] [--format pbm|png] [--output PREFIX] <ROM>";
+    let mut args = std::env::args().skip(1);
+    let mut platform = Platform::Chip8;
+    let mut quirks = None;
+    let mut fault_policy = FaultPolicy::default();
+    let mut random = RandomPreset::Xorshift;
+    let mut seed = rand::random::<u64>();
+    let mut headless = HeadlessRun::new(DEFAULT_FRAMES);
+    let mut dump_every = None;
+    let mut format = ImageFormat::Pbm;
+    let mut output = None;
+    let mut rom_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                platform = Platform::from_name(&args.next().expect(usage)).expect(usage)
+            }
+            "--quirks" => {
+                quirks = Some(Quirks::from_name(&args.next().expect(usage)).expect(usage))
+            }
+            "--faults" => {
+                fault_policy = FaultPolicy::from_name(&args.next().expect(usage)).expect(usage)
+            }
+            "--random" => {
+                random = RandomPreset::from_name(&args.next().expect(usage)).expect(usage)
+            }
+            "--seed" => seed = args.next().expect(usage).parse().expect(usage),
+            "--frames" => headless.frames = args.next().expect(usage).parse().expect(usage),
+            "--until" => headless
+                .until
+                .push(StopReason::from_name(&args.next().expect(usage)).expect(usage)),
+            "--keys" => {
+                let path = args.next().expect(usage);
+                let schedule = std::fs::read_to_string(&path)
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| KeySchedule::parse(&text));
+                headless.schedule = match schedule {
+                    Ok(schedule) => schedule,
+                    Err(error) => {
+                        eprintln!("Can't load {}: {}", path, error);
+                        std::process::exit(1);
+                    }
+                };
+            }
+            "--dump-every" => {
+                dump_every = Some(args.next().expect(usage).parse::<u64>().expect(usage))
+            }
+            "--format" => format = ImageFormat::from_name(&args.next().expect(usage)).expect(usage),
+            "--output" => output = Some(args.next().expect(usage)),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let rom_path = rom_path.expect(usage);
+    let quirks = quirks.unwrap_or(platform.default_quirks());
+    // the images go next to the ROM by default
+    let output = output.unwrap_or_else(|| {
+        Path::new(&rom_path)
+            .with_extension("")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let mut display = DummyCHIP8Display::new();
+    let keypad = SharedCHIP8Keypad::new();
+    let mut chip_keypad = keypad.clone();
+    let mut audio = DummyCHIP8Audio::new();
+    let mut chip = CHIP8::new(&mut display, &mut chip_keypad, &mut audio, platform, quirks);
+    chip.set_fault_policy(fault_policy);
+    chip.set_random_generator(random.generator(seed));
+    if let Err(error) = chip.load_from_file(&rom_path) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+
+    let result = headless.run(&mut chip, &keypad, |frame, chip| {
+        if dump_every.is_some_and(|every| every > 0 && frame % every == 0) {
+            let path = format!("{}-{:06}.{}", output, frame, format.extension());
+            write_image(chip, format, &path);
+        }
+    });
+    write_image(&chip, format, &format!("{}.{}", output, format.extension()));
+    for fault in chip.take_faults() {
+        eprintln!("{}", fault);
+    }
+    match result {
+        Ok(outcome) => println!(
+            "Stopped after {} frames: {:?}",
+            outcome.frames, outcome.reason
+        ),
+        Err(error) => {
+            eprintln!("Stopped after {} cycles: {}", chip.cycles(), error);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use old_rusty_platforms::chip8::CHIP8;
+use old_rusty_platforms::chip8_audio::DummyCHIP8Audio;
+use old_rusty_platforms::chip8_display::DummyCHIP8Display;
+use old_rusty_platforms::chip8_keypad::SharedCHIP8Keypad;
+use old_rusty_platforms::movie::Movie;
+use old_rusty_platforms::screenshot::{encode_image, ImageFormat};
+
+fn load_error(path: &str, error: impl std::fmt::Display) -> ! {
+    eprintln!("Can't load {}: {}", path, error);
+    std::process::exit(2);
+}
+
+// wrong arguments exit with the usage like the other errors
+fn usage_error(usage: &str) -> ! {
+    eprintln!("{}", usage);
+    std::process::exit(2);
+}
+
+// plays a movie recorded in the window without one, as fast as possible,
+// exits with 1 when the replay doesn't match the recording
+fn main() {
+    let usage = "Usage: chip8-replay [--screenshot IMAGE.pbm|png] <MOVIE> <ROM>";
+    let mut args = std::env::args().skip(1);
+    let mut screenshot = None;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--screenshot" => {
+                let path = args.next().unwrap_or_else(|| usage_error(usage));
+                let extension = path.rsplit('.').next().unwrap_or_default();
+                let format =
+                    ImageFormat::from_name(extension).unwrap_or_else(|| usage_error(usage));
+                screenshot = Some((format, path));
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let [movie_path, rom_path] = paths.as_slice() else {
+        usage_error(usage);
+    };
+
+    let movie = std::fs::read_to_string(movie_path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| Movie::parse(&text))
+        .unwrap_or_else(|error| load_error(movie_path, error));
+    let rom = std::fs::read(rom_path).unwrap_or_else(|error| load_error(rom_path, error));
+    if rom.len() > movie.platform.memory_size() - 0x200 {
+        load_error(rom_path, "the ROM doesn't fit into the memory");
+    }
+    if let Err(error) = movie.check_rom(&rom) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+
+    let mut display = DummyCHIP8Display::new();
+    let keypad = SharedCHIP8Keypad::new();
+    let mut chip_keypad = keypad.clone();
+    let mut audio = DummyCHIP8Audio::new();
+    let mut chip = CHIP8::new(
+        &mut display,
+        &mut chip_keypad,
+        &mut audio,
+        movie.platform,
+        movie.quirks,
+    );
+    movie.start(&mut chip);
+    chip.load_from_memory(&rom);
+
+    let result = movie.replay(&mut chip, &keypad);
+    if let Some((format, path)) = screenshot {
+        if let Err(error) = std::fs::write(&path, encode_image(chip.video_memory(), format)) {
+            eprintln!("Can't write {}: {}", path, error);
+        }
+    }
+    match result {
+        Ok(()) => println!("Replayed {} frames", movie.frames),
+        Err(error) => {
+            println!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use std::io::BufReader;
+
+use old_rusty_platforms::dap_server::DapServer;
+
+// editors start the adapter and talk to it over stdin and stdout
+fn main() {
+    let mut server = DapServer::new(std::io::stdout());
+    if let Err(error) = server.serve(BufReader::new(std::io::stdin())) {
+        eprintln!("Debug adapter failed: {}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,27 @@
+use old_rusty_platforms::disassembler::{listing, Syntax};
+
+// ROMs are loaded at 0x200, like by the emulator
+const ROM_ADDRESS: usize = 0x200;
+
+fn main() {
+    let usage = "Usage: disasm [--syntax cowgod|octo] <ROM>";
+    let mut args = std::env::args().skip(1);
+    let mut syntax = Syntax::Cowgod;
+    let mut rom_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => syntax = Syntax::from_name(&args.next().expect(usage)).expect(usage),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let rom_path = rom_path.expect(usage);
+
+    let rom = match std::fs::read(&rom_path) {
+        Ok(rom) => rom,
+        Err(error) => {
+            eprintln!("Can't load {}: {}", rom_path, error);
+            std::process::exit(1);
+        }
+    };
+    print!("{}", listing(&rom, ROM_ADDRESS, syntax));
+}
@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use old_rusty_platforms::chip8::CHIP8;
+use old_rusty_platforms::chip8_audio::DummyCHIP8Audio;
+use old_rusty_platforms::chip8_display::DummyCHIP8Display;
+use old_rusty_platforms::chip8_keypad::SharedCHIP8Keypad;
+use old_rusty_platforms::key_schedule::KeySchedule;
+use old_rusty_platforms::platform::Platform;
+use old_rusty_platforms::quirks::Quirks;
+use old_rusty_platforms::random::RandomPreset;
+use old_rusty_platforms::trace_diff::{compare_traces, run_lockstep, Divergence};
+
+const DEFAULT_CONTEXT: usize = 5; // lines before the divergence
+const DEFAULT_CYCLES: u64 = 1_000_000; // about 25 minutes of the default speed
+
+// a platform with its own quirks or the given ones, like chip8 or chip8:vip
+fn parse_config(text: &str) -> Option<(Platform, Quirks)> {
+    let (platform, quirks) = match text.split_once(':') {
+        Some((platform, quirks)) => (platform, Some(quirks)),
+        None => (text, None),
+    };
+    let platform = Platform::from_name(platform)?;
+    let quirks = match quirks {
+        Some(name) => Quirks::from_name(name)?,
+        None => platform.default_quirks(),
+    };
+    Some((platform, quirks))
+}
+
+// wrong arguments exit with the usage like the other errors
+fn usage_error(usage: &str) -> ! {
+    eprintln!("{}", usage);
+    std::process::exit(2);
+}
+
+fn open(path: &str) -> BufReader<File> {
+    match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(error) => {
+            eprintln!("Can't open {}: {}", path, error);
+            std::process::exit(2);
+        }
+    }
+}
+
+// the input both machines of a lockstep run get
+struct Input {
+    schedule: KeySchedule,
+    random: RandomPreset,
+    seed: u64,
+}
+
+fn load_schedule(path: &str) -> KeySchedule {
+    let schedule = std::fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| KeySchedule::parse(&text));
+    match schedule {
+        Ok(schedule) => schedule,
+        Err(error) => {
+            eprintln!("Can't load {}: {}", path, error);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn lockstep(
+    left: (Platform, Quirks),
+    right: (Platform, Quirks),
+    rom_path: &str,
+    input: &Input,
+    cycles: u64,
+    context: usize,
+) -> Option<Divergence> {
+    let mut left_display = DummyCHIP8Display::new();
+    let left_keys = SharedCHIP8Keypad::new();
+    let mut left_keypad = left_keys.clone();
+    let mut left_audio = DummyCHIP8Audio::new();
+    let mut left = CHIP8::new(
+        &mut left_display,
+        &mut left_keypad,
+        &mut left_audio,
+        left.0,
+        left.1,
+    );
+    let mut right_display = DummyCHIP8Display::new();
+    let right_keys = SharedCHIP8Keypad::new();
+    let mut right_keypad = right_keys.clone();
+    let mut right_audio = DummyCHIP8Audio::new();
+    let mut right = CHIP8::new(
+        &mut right_display,
+        &mut right_keypad,
+        &mut right_audio,
+        right.0,
+        right.1,
+    );
+    for chip in [&mut left, &mut right] {
+        chip.set_random_generator(input.random.generator(input.seed));
+        if let Err(error) = chip.load_from_file(rom_path) {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+    }
+    let keys = (&left_keys, &right_keys);
+    match run_lockstep(
+        &mut left,
+        &mut right,
+        keys,
+        &input.schedule,
+        cycles,
+        context,
+    ) {
+        Ok(divergence) => divergence,
+        Err(error) => {
+            println!("Both stopped at cycle {}: {}", left.cycles(), error);
+            None
+        }
+    }
+}
+
+// exits with 1 when the runs diverge, like diff
+fn main() {
+    let usage = "Usage: tracediff [--context N] <LEFT-TRACE> <RIGHT-TRACE>\n       tracediff [--context N] [--cycles N] [--keys SCHEDULE] [--random xorshift|timing] [--seed N] --left PLATFORM[:QUIRKS] --right PLATFORM[:QUIRKS] <ROM>";
+    let mut args = std::env::args().skip(1);
+    let mut context = DEFAULT_CONTEXT;
+    let mut cycles = DEFAULT_CYCLES;
+    let mut input = Input {
+        schedule: KeySchedule::new(),
+        random: RandomPreset::Xorshift,
+        seed: rand::random(),
+    };
+    let mut left = None;
+    let mut right = None;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => {
+                context = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| usage_error(usage))
+            }
+            "--cycles" => {
+                cycles = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| usage_error(usage))
+            }
+            "--keys" => {
+                input.schedule = load_schedule(&args.next().unwrap_or_else(|| usage_error(usage)))
+            }
+            "--random" => {
+                input.random = args
+                    .next()
+                    .and_then(|name| RandomPreset::from_name(&name))
+                    .unwrap_or_else(|| usage_error(usage))
+            }
+            "--seed" => {
+                input.seed = args
+                    .next()
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or_else(|| usage_error(usage))
+            }
+            "--left" => {
+                left = Some(
+                    args.next()
+                        .and_then(|text| parse_config(&text))
+                        .unwrap_or_else(|| usage_error(usage)),
+                )
+            }
+            "--right" => {
+                right = Some(
+                    args.next()
+                        .and_then(|text| parse_config(&text))
+                        .unwrap_or_else(|| usage_error(usage)),
+                )
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let divergence = match (left, right, paths.as_slice()) {
+        (Some(left), Some(right), [rom_path]) => {
+            lockstep(left, right, rom_path, &input, cycles, context)
+        }
+        (None, None, [left_path, right_path]) => {
+            match compare_traces(open(left_path), open(right_path), context) {
+                Ok(divergence) => divergence,
+                Err(error) => {
+                    eprintln!("Can't compare the traces: {}", error);
+                    std::process::exit(2);
+                }
+            }
+        }
+        _ => usage_error(usage),
+    };
+    match divergence {
+        Some(divergence) => {
+            println!("{}", divergence);
+            std::process::exit(1);
+        }
+        None => println!("No divergence"),
+    }
+}
@@ -1,9 +1,82 @@
-use crate::binary_parser;
-use crate::chip8_display::CHIP8Display;
+use std::collections::BTreeSet;
 
-const MEMORY_SIZE: usize = 0xFFF; // 4KB
+use crate::binary_parser;
+use crate::chip8_audio::{CHIP8Audio, AUDIO_PATTERN_SIZE};
+use crate::chip8_display::{CHIP8Display, VideoMemory, ALL_PLANES};
+use crate::chip8_error::Chip8Error;
+use crate::chip8_keypad::CHIP8Keypad;
+use crate::fault_policy::{FaultAction, FaultClass, FaultHandler, FaultPolicy};
+use crate::instruction::{decode, Instruction, Nibble, Register};
+use crate::platform::Platform;
+use crate::quirks::{LoadStoreIncrement, Quirks};
+use crate::random::{RandomGenerator, Xorshift};
+use crate::rewind::RewindBuffer;
+use crate::save_state::{MachineState, SaveStateError};
+use crate::trace::{TraceEntry, Tracer};
+
+const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 11; // about 660 instructions per second
+pub const FRAME_RATE: u32 = 60; // timers tick once per frame
+const FONT_ADDRESS: usize = 0x50; // the font lives in the interpreter area below 0x200
+const FONT_CHARACTER_SIZE: usize = 5;
+const LARGE_FONT_ADDRESS: usize = FONT_ADDRESS + FONT.len();
+const LARGE_FONT_CHARACTER_SIZE: usize = 10;
+const RPL_FLAGS: usize = 8; // SUPER-CHIP can save V0-V7 to the HP-48 RPL user flags
+const XO_CHIP_RPL_FLAGS: usize = 16; // XO-CHIP extends them to V0-VF
+const DEFAULT_AUDIO_PITCH: u8 = 64; // 4000 bits per second
+const MAX_LOGGED_FAULTS: usize = 256; // the oldest skipped faults are kept when nobody takes them
+
+// hex digits 0-F, 4x5 pixels each
+const FONT: [u8; 16 * FONT_CHARACTER_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// SUPER-CHIP hex digits 0-F, 8x10 pixels each
+const LARGE_FONT: [u8; 16 * LARGE_FONT_CHARACTER_SIZE] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// what happened during a call of step, run_cycles, run_frame or run_until
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExecutionReport {
+    pub instructions: usize,   // instructions executed, faulting ones included
+    pub drew: bool,            // the screen was cleared, drawn to or scrolled
+    pub sound_changed: bool,   // the tone started or stopped
+    pub waiting_for_key: bool, // the last instruction is FX0A waiting for a key
+    pub faults: usize,         // faults the fault policy got past
+    pub halted: bool,          // the program has exited
+    pub breakpoint: bool,      // stopped before the instruction at a breakpoint
+}
 
-#[allow(dead_code)]
 pub struct CHIP8<'a> {
     v: [u8; 16],        // V0 - VF registers
     i: u16,             // address register
@@ -11,14 +84,47 @@ pub struct CHIP8<'a> {
     sp: usize,          // stack pointer
     delay_timer: u8,
     sound_timer: u8,
-    ram: [u8; MEMORY_SIZE], // 4 KB of ram
-    ca: usize,              // current address
-    video_memory: [[u8; 4]; 8],
+    ram: Vec<u8>, // 4 KB of ram, 64 KB on XO-CHIP
+    ca: usize,    // current address
+    video_memory: VideoMemory,
+    planes: u8, // XO-CHIP bitplanes selected for drawing
+    rpl_flags: [u8; XO_CHIP_RPL_FLAGS],
+    audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    audio_pitch: u8,
+    halted: bool,            // the program has exited with 00FD
+    pressed_key: Option<u8>, // key pressed during FX0A, waiting for it to be released
+    instructions_per_frame: usize,
+    tone: bool, // whether the audio is currently beeping
+    platform: Platform,
+    quirks: Quirks,
+    waiting_for_vblank: bool, // a sprite was drawn with the display wait quirk, the frame is over
+    random: Box<dyn RandomGenerator>, // for CXNN
+    rewind: Option<RewindBuffer>, // a snapshot after every frame when rewinding is enabled
+    breakpoints: BTreeSet<usize>,
+    at_breakpoint: bool, // stopped at the breakpoint of the current address, run past it next time
+    cycles: u64,         // instructions executed since the start, faulting ones included
+    tracer: Option<&'a mut dyn Tracer>,
+    fault_policy: FaultPolicy,
+    fault_handler: Option<&'a mut dyn FaultHandler>,
+    faults: Vec<Chip8Error>, // faults skipped by the policy
+    wrapping_faults: bool,   // the instruction runs again after a fault, wrapping around
+    report: ExecutionReport, // what the current public call has done so far
     display: &'a mut dyn CHIP8Display,
+    keypad: &'a mut dyn CHIP8Keypad,
+    audio: &'a mut dyn CHIP8Audio,
 }
 
 impl<'a> CHIP8<'a> {
-    pub fn new<T: CHIP8Display>(display: &'a mut T) -> CHIP8 {
+    pub fn new<T: CHIP8Display, K: CHIP8Keypad, A: CHIP8Audio>(
+        display: &'a mut T,
+        keypad: &'a mut K,
+        audio: &'a mut A,
+        platform: Platform,
+        quirks: Quirks,
+    ) -> CHIP8<'a> {
+        let mut ram = vec![0; platform.memory_size()];
+        ram[FONT_ADDRESS..FONT_ADDRESS + FONT.len()].copy_from_slice(&FONT);
+        ram[LARGE_FONT_ADDRESS..LARGE_FONT_ADDRESS + LARGE_FONT.len()].copy_from_slice(&LARGE_FONT);
         CHIP8 {
             v: [0; 16],
             i: 0,
@@ -26,199 +132,809 @@ impl<'a> CHIP8<'a> {
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
-            ram: [0; 0xFFF],
+            ram,
             ca: 0x200,
-            video_memory: [[0; 4]; 8],
+            video_memory: VideoMemory::new(),
+            planes: 1,
+            rpl_flags: [0; XO_CHIP_RPL_FLAGS],
+            audio_pattern: [0; AUDIO_PATTERN_SIZE],
+            audio_pitch: DEFAULT_AUDIO_PITCH,
+            halted: false,
+            pressed_key: None,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            tone: false,
+            platform,
+            quirks,
+            waiting_for_vblank: false,
+            random: Box::new(Xorshift::new(rand::random())),
+            rewind: None,
+            breakpoints: BTreeSet::new(),
+            at_breakpoint: false,
+            cycles: 0,
+            tracer: None,
+            fault_policy: FaultPolicy::default(),
+            fault_handler: None,
+            faults: Vec::new(),
+            wrapping_faults: false,
+            report: ExecutionReport::default(),
             display,
+            keypad,
+            audio,
         }
     }
-    pub fn load_from_file(&mut self, file_path: &str) {
-        binary_parser::load_binary_to_memory(file_path, &mut self.ram[0x200..]).unwrap();
+    pub fn load_from_file(&mut self, file_path: &str) -> Result<(), Chip8Error> {
+        binary_parser::load_binary_to_memory(file_path, &mut self.ram[0x200..]).map_err(
+            |error| Chip8Error::Load {
+                path: file_path.to_string(),
+                message: error.to_string(),
+            },
+        )?;
         self.display.clear();
-        self.display.update(&self.video_memory)
+        self.display.update(&self.video_memory);
+        Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn load_from_memory(&mut self, memory_slice: &[u8]) {
         for (i, byte) in memory_slice.iter().enumerate() {
             self.ram[i + 0x200] = *byte;
         }
     }
 
-    pub fn print_first_16_bytes_of_ram(&self) {
-        println!("{:?}", &self.ram[0x200..0x210]);
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: usize) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    // xorshift with a random seed by default, see RandomPreset for the others
+    pub fn set_random_generator(&mut self, random: Box<dyn RandomGenerator>) {
+        self.random = random;
+    }
+
+    // two machines with the same generator and seed draw the same random numbers
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.random.restore(seed);
+    }
+
+    pub fn set_fault_policy(&mut self, fault_policy: FaultPolicy) {
+        self.fault_policy = fault_policy;
+    }
+
+    pub fn fault_policy(&self) -> FaultPolicy {
+        self.fault_policy
+    }
+
+    // decides the faults whose policy is FaultAction::Callback
+    pub fn set_fault_handler<H: FaultHandler>(&mut self, fault_handler: &'a mut H) {
+        self.fault_handler = Some(fault_handler);
+    }
+
+    // sees every instruction before it's executed
+    pub fn set_tracer<T: Tracer>(&mut self, tracer: &'a mut T) {
+        self.tracer = Some(tracer);
+    }
+
+    // the faults skipped since the last call
+    pub fn take_faults(&mut self) -> Vec<Chip8Error> {
+        std::mem::take(&mut self.faults)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+    }
+
+    // the trace line of the next instruction, none when the program has exited
+    // or the program counter has run off the end of the memory
+    pub fn trace_entry(&self) -> Option<TraceEntry> {
+        if self.halted || self.ca + 1 >= self.ram.len() {
+            return None;
+        }
+        let size = decode(self.opcode()).size().min(self.ram.len() - self.ca);
+        Some(TraceEntry {
+            cycle: self.cycles,
+            address: self.ca,
+            bytes: self.ram[self.ca..self.ca + size].to_vec(),
+            v: self.v,
+            i: self.i,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        })
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.ca
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.v
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.i
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
     }
 
-    // extracts address NNN from instructions that look like XNNN
-    fn extract_address(&self) -> usize {
-        let second_nymble = (self.ram[self.ca] & 0xF) as usize;
-        let second_byte = self.ram[self.ca + 1] as usize;
-        second_byte + (second_nymble << 8)
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
     }
 
-    #[allow(dead_code)]
-    fn execute_opcode(&mut self) {
-        let first_nymble = self.ram[self.ca] >> 4;
+    // the addresses of the calls in progress, the innermost last
+    pub fn stack(&self) -> &[usize] {
+        &self.stack[..self.sp]
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // for debuggers, the program sees the changes right away
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    pub fn video_memory(&self) -> &VideoMemory {
+        &self.video_memory
+    }
+
+    // keeps the snapshots of the last frames in at most the given number of bytes
+    pub fn enable_rewind(&mut self, budget: usize) {
+        self.rewind = Some(RewindBuffer::new(budget));
+    }
+
+    // goes back the given number of frames, or as far as the snapshots go,
+    // and returns how many frames it went back
+    pub fn rewind(&mut self, frames: usize) -> usize {
+        let Some(mut buffer) = self.rewind.take() else {
+            return 0;
+        };
+        let before = buffer.len();
+        if let Some(state) = buffer.rewind(frames) {
+            self.restore_state(&state)
+                .expect("rewind snapshots come from this machine");
+        }
+        let rewound = before - buffer.len();
+        self.rewind = Some(buffer);
+        rewound
+    }
 
-        self.ca = match first_nymble {
-            0x0 => self.execute_0_opcode(),
-            0x1 => self.execute_1_opcode(),
-            0x2 => self.execute_2_opcode(),
-            0x3 => self.execute_3_opcode(),
-            0x4 => self.execute_4_opcode(),
-            0x5 => self.execute_5_opcode(),
-            0x6 => self.execute_6_opcode(),
-            0x7 => self.execute_7_opcode(),
-            0x8 => self.execute_8_opcode(),
-            0x9 => self.execute_9_opcode(),
-            0xA => self.execute_a_opcode(),
-            0xB => self.execute_b_opcode(),
-            0xC => self.execute_c_opcode(),
-            0xD => self.execute_d_opcode(),
-            0xE => self.execute_e_opcode(),
-            0xF => self.execute_f_opcode(),
-            _ => {
-                panic!("Unreachable");
+    // a snapshot of everything the program can see or change, see MachineState::to_bytes
+    pub fn save_state(&self) -> MachineState {
+        MachineState {
+            v: self.v,
+            i: self.i,
+            stack: self.stack,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            ram: self.ram.clone(),
+            ca: self.ca,
+            video_memory: self.video_memory.clone(),
+            planes: self.planes,
+            rpl_flags: self.rpl_flags,
+            audio_pattern: self.audio_pattern,
+            audio_pitch: self.audio_pitch,
+            halted: self.halted,
+            pressed_key: self.pressed_key,
+            instructions_per_frame: self.instructions_per_frame,
+            platform: self.platform,
+            quirks: self.quirks,
+            rng_preset: self.random.preset(),
+            rng_state: self.random.state(),
+        }
+    }
+
+    // continues from the snapshot, the platform and the quirks become the ones of the snapshot
+    // the display and the audio are brought up to date right away
+    pub fn restore_state(&mut self, state: &MachineState) -> Result<(), SaveStateError> {
+        state.validate()?;
+        self.v = state.v;
+        self.i = state.i;
+        self.stack = state.stack;
+        self.sp = state.sp;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.ram.clone_from(&state.ram);
+        self.ca = state.ca;
+        self.video_memory.clone_from(&state.video_memory);
+        self.planes = state.planes;
+        self.rpl_flags = state.rpl_flags;
+        self.audio_pattern = state.audio_pattern;
+        self.audio_pitch = state.audio_pitch;
+        self.halted = state.halted;
+        self.pressed_key = state.pressed_key;
+        self.instructions_per_frame = state.instructions_per_frame;
+        self.platform = state.platform;
+        self.quirks = state.quirks;
+        match state.rng_preset {
+            Some(preset) if self.random.preset() != state.rng_preset => {
+                self.random = preset.generator(state.rng_state)
             }
+            _ => self.random.restore(state.rng_state),
+        }
+        self.waiting_for_vblank = false;
+        self.at_breakpoint = false;
+        self.display.update(&self.video_memory);
+        if self.platform == Platform::XoChip {
+            self.update_audio_pattern();
         }
+        self.tone = self.sound_timer > 0;
+        self.audio.set_tone(self.tone);
+        Ok(())
     }
 
-    fn not_implemented(&self) {
-        println!(
-            "Not implemented {:x}{:x}",
-            self.ram[self.ca],
-            self.ram[self.ca + 1]
-        );
+    // run_cycles, run_frame and run_until stop before the instruction at a breakpoint,
+    // the next call runs past it
+    pub fn add_breakpoint(&mut self, address: usize) {
+        self.breakpoints.insert(address);
     }
 
-    fn warning(&self, message: &str) {
-        print!(
-            "Illegal instruction {:x}{:x} at {:x} skipped",
-            self.ram[self.ca],
-            self.ram[self.ca + 1],
-            self.ca
-        );
-        println!("{}", message);
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
     }
 
-    fn execute_0_opcode(&mut self) -> usize {
-        let second_nymble = self.ram[self.ca] & 0xF;
-        if second_nymble != 0x0 {
-            self.not_implemented();
-            return self.ca + 2;
+    // executes one instruction, the fault policy decides what happens on error
+    // when it returns the error, the machine stays at the failing instruction
+    pub fn step(&mut self) -> Result<ExecutionReport, Chip8Error> {
+        self.report = ExecutionReport::default();
+        self.execute_step()?;
+        Ok(self.finish_report())
+    }
+
+    // executes the given number of instructions, fewer if the program exits
+    // the timers don't tick, run_frame is for running in real time
+    pub fn run_cycles(&mut self, cycles: usize) -> Result<ExecutionReport, Chip8Error> {
+        self.report = ExecutionReport::default();
+        for _ in 0..cycles {
+            if self.halted || self.stop_at_breakpoint() {
+                break;
+            }
+            self.execute_step()?;
         }
-        let second_byte = self.ram[self.ca + 1];
-        match second_byte {
-            0xE0 => {
-                // video
-                self.not_implemented();
-                self.ca + 2
+        Ok(self.finish_report())
+    }
+
+    // runs one 1/60 s frame: a batch of instructions followed by a timer tick,
+    // so the timers run at 60 Hz no matter how many instructions a frame has
+    // an error or a breakpoint stops the frame right away, without the tick
+    pub fn run_frame(&mut self) -> Result<ExecutionReport, Chip8Error> {
+        self.report = ExecutionReport::default();
+        self.waiting_for_vblank = false;
+        for _ in 0..self.instructions_per_frame {
+            if self.stop_at_breakpoint() {
+                return Ok(self.finish_report());
             }
-            0xEE => {
-                // return from subroutine
-                self.sp -= 1;
-                // TODO check stack limits
-                // TODO check memory limits
-                self.stack[self.sp] + 2
+            self.execute_step()?;
+            if self.waiting_for_vblank || self.halted {
+                break;
             }
-            _ => {
-                self.warning("Illegal opcode");
-                self.ca + 2
+        }
+        self.end_frame();
+        Ok(self.finish_report())
+    }
+
+    // a sprite was drawn with the display wait quirk, the rest of the frame is idle
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
+    // what run_frame does after the instructions of the frame,
+    // for running frames an instruction at a time with step
+    pub fn end_frame(&mut self) {
+        // the frame sounds the way the timer was during it, the tick affects the next frame
+        self.audio.end_frame();
+        self.tick_timers();
+        self.waiting_for_vblank = false;
+        if let Some(mut buffer) = self.rewind.take() {
+            buffer.push(&self.save_state());
+            self.rewind = Some(buffer);
+        }
+    }
+
+    // executes instructions until the condition holds after one of them or the program exits,
+    // the condition also sees the report so far, e.g. to stop on a draw or a key wait
+    // the timers don't tick, like with run_cycles
+    pub fn run_until<P>(&mut self, mut condition: P) -> Result<ExecutionReport, Chip8Error>
+    where
+        P: FnMut(&CHIP8<'a>, &ExecutionReport) -> bool,
+    {
+        self.report = ExecutionReport::default();
+        while !self.halted && !self.stop_at_breakpoint() {
+            self.execute_step()?;
+            if condition(self, &self.report) {
+                break;
             }
         }
+        Ok(self.finish_report())
     }
 
-    // uncoditional jump
-    fn execute_1_opcode(&mut self) -> usize {
-        let address = self.extract_address();
-        if address >= MEMORY_SIZE {
-            self.warning("Jump ouside of the memory");
-            self.ca + 2
-        } else if address == self.ca {
-            self.warning("Endless loop");
-            self.ca + 2
+    fn stop_at_breakpoint(&mut self) -> bool {
+        if self.at_breakpoint || !self.breakpoints.contains(&self.ca) {
+            return false;
+        }
+        self.at_breakpoint = true;
+        self.report.breakpoint = true;
+        true
+    }
+
+    fn finish_report(&mut self) -> ExecutionReport {
+        self.report.halted = self.halted;
+        std::mem::take(&mut self.report)
+    }
+
+    fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.random.tick();
+        self.update_tone();
+    }
+
+    // the tone plays for as long as the sound timer is above zero
+    fn update_tone(&mut self) {
+        let tone = self.sound_timer > 0;
+        if tone != self.tone {
+            self.tone = tone;
+            self.audio.set_tone(tone);
+            self.report.sound_changed = true;
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // XO-CHIP plays the pattern at 4000 * 2 ^ ((pitch - 64) / 48) bits per second
+    fn update_audio_pattern(&mut self) {
+        let rate = 4000.0 * 2f32.powf((self.audio_pitch as f32 - 64.0) / 48.0);
+        self.audio.set_pattern(&self.audio_pattern, rate);
+    }
+
+    // the instruction at the current address
+    fn opcode(&self) -> u16 {
+        (self.ram[self.ca] as u16) << 8 | self.ram[self.ca + 1] as u16
+    }
+
+    fn execute_step(&mut self) -> Result<(), Chip8Error> {
+        if self.halted {
+            return Ok(());
+        }
+        self.trace();
+        self.cycles += 1;
+        self.report.instructions += 1;
+        self.report.waiting_for_key = false;
+        self.at_breakpoint = false;
+        let result = if self.ca + 1 >= self.ram.len() {
+            Err(Chip8Error::ProgramCounterOutOfMemory { address: self.ca })
         } else {
-            address
+            self.execute(decode(self.opcode()))
+        };
+        match result {
+            Ok(address) => {
+                self.ca = address;
+                Ok(())
+            }
+            Err(error) => self.handle_fault(error),
         }
     }
 
-    // call subroutine
-    fn execute_2_opcode(&mut self) -> usize {
-        let second_nymble = (self.ram[self.ca] & 0xF) as usize;
-        let second_byte = self.ram[self.ca + 1] as usize;
-        let address = second_byte + (second_nymble << 8);
-        self.stack[self.sp] = self.ca;
-        // TODO check stack limits
-        self.sp += 1;
-        address
+    fn trace(&mut self) {
+        if self.tracer.is_none() {
+            return;
+        }
+        if let Some(entry) = self.trace_entry() {
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.trace(&entry);
+            }
+        }
     }
 
-    // skip if equal
-    fn execute_3_opcode(&mut self) -> usize {
-        let second_nymble = self.ram[self.ca] & 0xF;
-        let second_byte = self.ram[self.ca + 1];
-        if self.v[second_nymble as usize] == second_byte {
-            self.ca + 4
+    fn handle_fault(&mut self, error: Chip8Error) -> Result<(), Chip8Error> {
+        let mut action = match FaultClass::of(&error) {
+            Some(class) => self.fault_policy.action(class),
+            None => FaultAction::Halt,
+        };
+        if action == FaultAction::Callback {
+            action = match self.fault_handler.as_mut() {
+                Some(handler) => handler.handle_fault(&error),
+                None => FaultAction::Halt,
+            };
+        }
+        match action {
+            FaultAction::Halt | FaultAction::Callback => return Err(error),
+            FaultAction::SkipAndLog => {
+                self.report.faults += 1;
+                self.ca = self.next_address();
+                if self.faults.len() < MAX_LOGGED_FAULTS {
+                    self.faults.push(error);
+                }
+            }
+            FaultAction::WrapAround => {
+                self.report.faults += 1;
+                match error {
+                    Chip8Error::IllegalOpcode { .. }
+                    | Chip8Error::UnsupportedOpcode { .. }
+                    | Chip8Error::ProgramCounterOutOfMemory { .. } => self.ca = self.next_address(),
+                    _ => {
+                        // the checks of the instruction let it through the second time
+                        self.wrapping_faults = true;
+                        let result = self.execute(decode(self.opcode()));
+                        self.wrapping_faults = false;
+                        self.ca = result?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the instruction after the current one, past the end of the memory it is the start of it
+    fn next_address(&self) -> usize {
+        if self.ca + 1 >= self.ram.len() {
+            0
         } else {
             self.ca + 2
         }
     }
 
-    // skip if not equal
-    fn execute_4_opcode(&mut self) -> usize {
-        let second_nymble = self.ram[self.ca] & 0xF;
-        let second_byte = self.ram[self.ca + 1];
-        if self.v[second_nymble as usize] != second_byte {
-            self.ca + 4
+    fn update_display(&mut self) {
+        self.display.update(&self.video_memory);
+        self.report.drew = true;
+    }
+
+    fn clear_display(&mut self) {
+        self.display.clear();
+        self.report.drew = true;
+    }
+
+    // a fault that the instruction can get past by wrapping around, when it is run again for that
+    fn fault(&self, error: Chip8Error) -> Result<(), Chip8Error> {
+        if self.wrapping_faults {
+            Ok(())
         } else {
-            self.ca + 2
+            Err(error)
+        }
+    }
+
+    // addresses past the end of the memory wrap around to its start
+    fn wrap_address(&self, address: usize) -> usize {
+        address % self.ram.len()
+    }
+
+    fn illegal_opcode(&self) -> Chip8Error {
+        Chip8Error::IllegalOpcode {
+            address: self.ca,
+            opcode: self.opcode(),
+        }
+    }
+
+    // makes sure that length bytes starting at address are inside the memory
+    fn check_memory(&self, address: usize, length: usize) -> Result<(), Chip8Error> {
+        if address + length > self.ram.len() {
+            return self.fault(Chip8Error::MemoryOutOfBounds {
+                address: self.ca,
+                opcode: self.opcode(),
+                target: address,
+            });
         }
+        Ok(())
     }
 
-    // skip if two registers are equal
-    fn execute_5_opcode(&mut self) -> usize {
-        let last_nymble = self.ram[self.ca + 1] & 0xF;
-        if last_nymble != 0 {
-            self.warning("Illegal opcode");
-            return self.ca + 2;
+    // address of the instruction after the next one,
+    // the long F000 NNNN instruction of XO-CHIP takes 4 bytes to skip
+    fn skip_address(&self) -> usize {
+        let next = self.ca + 2;
+        let long_instruction =
+            self.ram.get(next) == Some(&0xF0) && self.ram.get(next + 1) == Some(&0x00);
+        if self.platform == Platform::XoChip && long_instruction {
+            next + 4
+        } else {
+            next + 2
         }
-        let x = self.ram[self.ca] & 0xF; // second nymble
-        let y = self.ram[self.ca + 1] >> 4; // third nymble
-        if self.v[x as usize] == self.v[y as usize] {
-            self.ca + 4
+    }
+
+    fn skip_if(&self, condition: bool) -> usize {
+        if condition {
+            self.skip_address()
         } else {
             self.ca + 2
         }
     }
 
-    // store value in a register
-    fn execute_6_opcode(&mut self) -> usize {
-        let second_nymble = self.ram[self.ca] & 0xF;
-        let second_byte = self.ram[self.ca + 1];
-        self.v[second_nymble as usize] = second_byte;
-        self.ca + 2
+    // returns the address of the next instruction, the instructions check everything
+    // before changing the state of the machine, so they can be run again after a fault
+    fn execute(&mut self, instruction: Instruction) -> Result<usize, Chip8Error> {
+        if !instruction.is_available_on(self.platform) {
+            return Err(self.illegal_opcode());
+        }
+        match instruction {
+            Instruction::MachineCode { .. } => {
+                // machine code routines of the original interpreter
+                return Err(Chip8Error::UnsupportedOpcode {
+                    address: self.ca,
+                    opcode: self.opcode(),
+                });
+            }
+            Instruction::Unknown { .. } => return Err(self.illegal_opcode()),
+            Instruction::ClearScreen => {
+                // only the selected planes on XO-CHIP
+                if self.planes == ALL_PLANES || self.platform != Platform::XoChip {
+                    self.video_memory.clear();
+                    self.clear_display();
+                } else {
+                    self.video_memory.clear_planes(self.planes);
+                    self.update_display();
+                }
+            }
+            Instruction::Return => return self.return_from_subroutine(),
+            Instruction::ScrollDown { rows } => {
+                self.video_memory.scroll_down(rows.0 as usize, self.planes);
+                self.update_display();
+            }
+            Instruction::ScrollUp { rows } => {
+                self.video_memory.scroll_up(rows.0 as usize, self.planes);
+                self.update_display();
+            }
+            Instruction::ScrollRight => {
+                self.video_memory.scroll_right(4, self.planes);
+                self.update_display();
+            }
+            Instruction::ScrollLeft => {
+                self.video_memory.scroll_left(4, self.planes);
+                self.update_display();
+            }
+            Instruction::Exit => {
+                self.halted = true;
+                return Ok(self.ca);
+            }
+            Instruction::LowResolution | Instruction::HighResolution => {
+                self.video_memory
+                    .set_hires(instruction == Instruction::HighResolution);
+                self.clear_display();
+            }
+            Instruction::Jump { address } => return self.jump(address.0 as usize),
+            Instruction::Call { address } => return self.call(address.0 as usize),
+            Instruction::SkipIfEqual { x, byte } => {
+                return Ok(self.skip_if(self.v[x.index()] == byte.0))
+            }
+            Instruction::SkipIfNotEqual { x, byte } => {
+                return Ok(self.skip_if(self.v[x.index()] != byte.0))
+            }
+            Instruction::SkipIfRegistersEqual { x, y } => {
+                return Ok(self.skip_if(self.v[x.index()] == self.v[y.index()]))
+            }
+            Instruction::SaveRange { x, y } => self.copy_register_range(x, y, true)?,
+            Instruction::LoadRange { x, y } => self.copy_register_range(x, y, false)?,
+            Instruction::Load { x, byte } => self.v[x.index()] = byte.0,
+            Instruction::Add { x, byte } => {
+                self.v[x.index()] = self.v[x.index()].wrapping_add(byte.0)
+            }
+            Instruction::Move { .. }
+            | Instruction::Or { .. }
+            | Instruction::And { .. }
+            | Instruction::Xor { .. }
+            | Instruction::AddRegisters { .. }
+            | Instruction::Subtract { .. }
+            | Instruction::ShiftRight { .. }
+            | Instruction::SubtractReversed { .. }
+            | Instruction::ShiftLeft { .. } => self.execute_arithmetic(instruction),
+            Instruction::SkipIfRegistersNotEqual { x, y } => {
+                return Ok(self.skip_if(self.v[x.index()] != self.v[y.index()]))
+            }
+            Instruction::LoadIndex { address } => self.i = address.0,
+            Instruction::JumpWithOffset { address } => {
+                return self.jump_with_offset(address.0 as usize)
+            }
+            Instruction::Random { x, byte } => {
+                self.v[x.index()] = self.random.next_byte() & byte.0;
+            }
+            Instruction::Draw { x, y, height } => self.draw(x, y, height)?,
+            Instruction::SkipIfKey { x } => {
+                let pressed = self.is_key_pressed(x);
+                return Ok(self.skip_if(pressed));
+            }
+            Instruction::SkipIfNotKey { x } => {
+                let pressed = self.is_key_pressed(x);
+                return Ok(self.skip_if(!pressed));
+            }
+            Instruction::LoadLongIndex => {
+                // store the 16 bit address that follows in I
+                self.check_memory(self.ca + 2, 2)?;
+                self.i = (self.ram[self.wrap_address(self.ca + 2)] as u16) << 8
+                    | self.ram[self.wrap_address(self.ca + 3)] as u16;
+                return Ok(self.ca + 4);
+            }
+            Instruction::SelectPlanes { planes } => self.planes = planes.0 & ALL_PLANES,
+            Instruction::LoadAudioPattern => {
+                let i = self.i as usize;
+                self.check_memory(i, AUDIO_PATTERN_SIZE)?;
+                for offset in 0..AUDIO_PATTERN_SIZE {
+                    self.audio_pattern[offset] = self.ram[self.wrap_address(i + offset)];
+                }
+                self.update_audio_pattern();
+            }
+            Instruction::GetDelayTimer { x } => self.v[x.index()] = self.delay_timer,
+            Instruction::WaitForKey { x } => return Ok(self.wait_for_key(x.index())),
+            Instruction::SetDelayTimer { x } => self.delay_timer = self.v[x.index()],
+            Instruction::SetSoundTimer { x } => {
+                self.sound_timer = self.v[x.index()];
+                self.update_tone();
+            }
+            Instruction::AddIndex { x } => {
+                self.i = self.i.wrapping_add(self.v[x.index()] as u16);
+                if self.i > 0xFFF && self.platform != Platform::XoChip {
+                    self.v[0xF] = 1;
+                }
+            }
+            Instruction::LoadFont { x } => {
+                // point I to the font character for the lowest nymble of Vx
+                let character = (self.v[x.index()] & 0xF) as usize;
+                self.i = (FONT_ADDRESS + character * FONT_CHARACTER_SIZE) as u16;
+            }
+            Instruction::LoadLargeFont { x } => {
+                let character = (self.v[x.index()] & 0xF) as usize;
+                self.i = (LARGE_FONT_ADDRESS + character * LARGE_FONT_CHARACTER_SIZE) as u16;
+            }
+            Instruction::SetPitch { x } => {
+                self.audio_pitch = self.v[x.index()];
+                self.update_audio_pattern();
+            }
+            Instruction::StoreDecimal { x } => {
+                // store the decimal digits of Vx at I, I+1 and I+2
+                let value = self.v[x.index()];
+                let i = self.i as usize;
+                self.check_memory(i, 3)?;
+                let digits = [value / 100, value / 10 % 10, value % 10];
+                for (offset, digit) in digits.into_iter().enumerate() {
+                    let address = self.wrap_address(i + offset);
+                    self.ram[address] = digit;
+                }
+            }
+            Instruction::StoreRegisters { x } => {
+                let i = self.i as usize;
+                let count = x.index() + 1;
+                self.check_memory(i, count)?;
+                for register in 0..count {
+                    let address = self.wrap_address(i + register);
+                    self.ram[address] = self.v[register];
+                }
+                self.i = self.i.wrapping_add(self.load_store_increment(count));
+            }
+            Instruction::LoadRegisters { x } => {
+                let i = self.i as usize;
+                let count = x.index() + 1;
+                self.check_memory(i, count)?;
+                for register in 0..count {
+                    self.v[register] = self.ram[self.wrap_address(i + register)];
+                }
+                self.i = self.i.wrapping_add(self.load_store_increment(count));
+            }
+            Instruction::SaveFlags { x } | Instruction::LoadFlags { x } => {
+                // only V0-V7 fit into the RPL user flags before XO-CHIP
+                let count = x.index() + 1;
+                if count > RPL_FLAGS && self.platform != Platform::XoChip {
+                    return Err(self.illegal_opcode());
+                } else if let Instruction::SaveFlags { .. } = instruction {
+                    self.rpl_flags[..count].copy_from_slice(&self.v[..count]);
+                } else {
+                    self.v[..count].copy_from_slice(&self.rpl_flags[..count]);
+                }
+            }
+        }
+        Ok(self.ca + 2)
+    }
+
+    fn return_from_subroutine(&mut self) -> Result<usize, Chip8Error> {
+        if self.sp == 0 {
+            self.fault(Chip8Error::StackUnderflow {
+                address: self.ca,
+                opcode: self.opcode(),
+            })?;
+            self.sp = self.stack.len();
+        }
+        self.sp -= 1;
+        Ok(self.stack[self.sp] + 2)
+    }
+
+    fn jump(&mut self, address: usize) -> Result<usize, Chip8Error> {
+        if address >= self.ram.len() {
+            self.fault(Chip8Error::JumpOutOfMemory {
+                address: self.ca,
+                opcode: self.opcode(),
+                target: address,
+            })?;
+        } else if address == self.ca {
+            self.fault(Chip8Error::EndlessLoop {
+                address: self.ca,
+                opcode: self.opcode(),
+            })?;
+        }
+        Ok(self.wrap_address(address))
+    }
+
+    fn call(&mut self, address: usize) -> Result<usize, Chip8Error> {
+        if self.sp >= self.stack.len() {
+            self.fault(Chip8Error::StackOverflow {
+                address: self.ca,
+                opcode: self.opcode(),
+            })?;
+            self.sp = 0;
+        }
+        self.stack[self.sp] = self.ca;
+        self.sp += 1;
+        Ok(address)
+    }
+
+    // jump to address plus the value of v0, or of vX with the BXNN quirk
+    fn jump_with_offset(&mut self, address: usize) -> Result<usize, Chip8Error> {
+        let register = if self.quirks.jump_uses_vx {
+            address >> 8
+        } else {
+            0
+        };
+        let target = address + self.v[register] as usize;
+        if target + 1 >= self.ram.len() {
+            self.fault(Chip8Error::JumpOutOfMemory {
+                address: self.ca,
+                opcode: self.opcode(),
+                target,
+            })?;
+        }
+        Ok(self.wrap_address(target))
     }
 
-    // add a constant to a register
-    fn execute_7_opcode(&mut self) -> usize {
-        let register = (self.ram[self.ca] & 0xF) as usize;
-        let second_byte = self.ram[self.ca + 1];
-        let current_value = self.v[register];
-        self.v[register] = current_value.wrapping_add(second_byte);
-        self.ca + 2
+    // XO-CHIP 5XY2 saves Vx..=Vy to I and 5XY3 loads them back,
+    // the range can go backwards, I doesn't change
+    fn copy_register_range(
+        &mut self,
+        x: Register,
+        y: Register,
+        save: bool,
+    ) -> Result<(), Chip8Error> {
+        let (x, y) = (x.index(), y.index());
+        let registers: Vec<usize> = if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
+        };
+        self.check_memory(self.i as usize, registers.len())?;
+        for (offset, register) in registers.into_iter().enumerate() {
+            let address = self.wrap_address(self.i as usize + offset);
+            if save {
+                self.ram[address] = self.v[register];
+            } else {
+                self.v[register] = self.ram[address];
+            }
+        }
+        Ok(())
     }
 
     // mathematical operations
-    fn execute_8_opcode(&mut self) -> usize {
-        let last_nymble = self.ram[self.ca + 1] & 0xF;
-        let x = (self.ram[self.ca] & 0xF) as usize; // second nymble
-        let y = (self.ram[self.ca + 1] >> 4) as usize; // third nymble
-        match last_nymble {
-            0x0 => self.v[x] = self.v[y],
-            0x1 => self.v[x] |= self.v[y],
-            0x2 => self.v[x] &= self.v[y],
-            0x3 => self.v[x] ^= self.v[y],
-            0x4 => {
+    fn execute_arithmetic(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Move { x, y } => self.v[x.index()] = self.v[y.index()],
+            Instruction::Or { x, y } => {
+                self.v[x.index()] |= self.v[y.index()];
+                self.reset_vf_after_logic();
+            }
+            Instruction::And { x, y } => {
+                self.v[x.index()] &= self.v[y.index()];
+                self.reset_vf_after_logic();
+            }
+            Instruction::Xor { x, y } => {
+                self.v[x.index()] ^= self.v[y.index()];
+                self.reset_vf_after_logic();
+            }
+            Instruction::AddRegisters { x, y } => {
+                let (x, y) = (x.index(), y.index());
                 let sum: u16 = self.v[x] as u16 + self.v[y] as u16;
                 if sum & 0xFF00 != 0 {
                     self.v[0xF] = 1;
@@ -227,7 +943,8 @@ impl<'a> CHIP8<'a> {
                 }
                 self.v[x] = (sum & 0x00FF) as u8;
             }
-            0x5 => {
+            Instruction::Subtract { x, y } => {
+                let (x, y) = (x.index(), y.index());
                 if self.v[x] < self.v[y] {
                     self.v[0xF] = 0;
                 } else {
@@ -235,11 +952,13 @@ impl<'a> CHIP8<'a> {
                 }
                 self.v[x] = self.v[x].wrapping_sub(self.v[y]);
             }
-            0x6 => {
-                self.v[0xF] = self.v[y] & 0b0000_0001;
-                self.v[x] = self.v[y] >> 1;
+            Instruction::ShiftRight { x, y } => {
+                let source = self.shift_source(x.index(), y.index());
+                self.v[x.index()] = source >> 1;
+                self.v[0xF] = source & 0b0000_0001;
             }
-            0x7 => {
+            Instruction::SubtractReversed { x, y } => {
+                let (x, y) = (x.index(), y.index());
                 if self.v[y] < self.v[x] {
                     self.v[0xF] = 0;
                 } else {
@@ -247,130 +966,190 @@ impl<'a> CHIP8<'a> {
                 }
                 self.v[x] = self.v[y].wrapping_sub(self.v[x]);
             }
-            0xE => {
-                self.v[0xF] = (self.v[y] & 0b1000_0000) >> 7;
-                self.v[x] = self.v[y] << 1;
-            }
-            _ => {
-                self.warning("Illegal opcode");
+            Instruction::ShiftLeft { x, y } => {
+                let source = self.shift_source(x.index(), y.index());
+                self.v[x.index()] = source << 1;
+                self.v[0xF] = (source & 0b1000_0000) >> 7;
             }
+            _ => unreachable!("not an arithmetic instruction"),
         }
-        self.ca + 2
     }
 
-    // skip if two registers are different
-    fn execute_9_opcode(&mut self) -> usize {
-        let x = self.ram[self.ca] & 0xF; // second nymble
-        let y = self.ram[self.ca + 1] >> 4; // third nymble
-        if self.v[x as usize] != self.v[y as usize] {
-            self.ca + 4
+    fn shift_source(&self, x: usize, y: usize) -> u8 {
+        if self.quirks.shift_uses_vy {
+            self.v[y]
         } else {
-            self.ca + 2
+            self.v[x]
         }
     }
 
-    // store address to I register
-    fn execute_a_opcode(&mut self) -> usize {
-        let address = self.extract_address();
-        self.i = address as u16;
-        self.ca + 2
-    }
-
-    // jump to address plus the value of v0
-    fn execute_b_opcode(&mut self) -> usize {
-        let address = self.extract_address();
-        address + self.v[0] as usize // TODO check that the sum less than max address
-    }
-
-    fn execute_c_opcode(&mut self) -> usize {
-        let second_nymble = self.ram[self.ca] & 0xF;
-        let second_byte = self.ram[self.ca + 1];
-        let r: u8 = rand::random();
-        self.v[second_nymble as usize] = r & second_byte;
-        self.ca + 2
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
     }
 
-    fn execute_d_opcode(&mut self) -> usize {
-        // video
-        self.not_implemented();
-        self.ca + 2
+    // draw a sprite of N bytes from memory at I to the position (Vx, Vy)
+    // SUPER-CHIP draws a 16x16 sprite of 32 bytes when N is 0
+    fn draw(&mut self, x: Register, y: Register, height: Nibble) -> Result<(), Chip8Error> {
+        let mut height = height.0 as usize;
+        let mut sprite_width = 8;
+        if height == 0 && self.platform.has_superchip() {
+            height = 16;
+            sprite_width = 16;
+        }
+        let bytes_per_row = sprite_width / 8;
+        let screen_width = self.video_memory.width();
+        let screen_height = self.video_memory.height();
+
+        // with several XO-CHIP planes selected, the sprite data for the next plane follows the previous one
+        let sprite_size = height * bytes_per_row;
+        self.check_memory(
+            self.i as usize,
+            sprite_size * self.planes.count_ones() as usize,
+        )?;
+
+        // the starting position always wraps around, the sprite itself is clipped unless it is a quirk
+        let start_x = self.v[x.index()] as usize % screen_width;
+        let start_y = self.v[y.index()] as usize % screen_height;
+
+        self.v[0xF] = 0;
+        let mut address = self.i as usize;
+        for plane in [1, 2] {
+            if self.planes & plane == 0 {
+                continue;
+            }
+            for row in 0..height {
+                let mut screen_y = start_y + row;
+                if screen_y >= screen_height {
+                    if !self.quirks.wrap_sprites {
+                        break;
+                    }
+                    screen_y %= screen_height;
+                }
+                let row_address = address + row * bytes_per_row;
+                let sprite_row = if bytes_per_row == 2 {
+                    (self.ram[self.wrap_address(row_address)] as u16) << 8
+                        | self.ram[self.wrap_address(row_address + 1)] as u16
+                } else {
+                    (self.ram[self.wrap_address(row_address)] as u16) << 8
+                };
+                for column in 0..sprite_width {
+                    let mut screen_x = start_x + column;
+                    if screen_x >= screen_width {
+                        if !self.quirks.wrap_sprites {
+                            break;
+                        }
+                        screen_x %= screen_width;
+                    }
+                    let pixel = (sprite_row >> (15 - column)) & 1;
+                    if pixel == 0 {
+                        continue;
+                    }
+                    if self.video_memory[screen_y][screen_x] & plane != 0 {
+                        self.v[0xF] = 1; // collision
+                    }
+                    self.video_memory[screen_y][screen_x] ^= plane;
+                }
+            }
+            address += sprite_size;
+        }
+        self.update_display();
+        self.waiting_for_vblank = self.quirks.display_wait;
+        Ok(())
     }
 
-    fn execute_e_opcode(&mut self) -> usize {
-        // keyborad
-        self.not_implemented();
-        self.ca + 2
+    fn is_key_pressed(&mut self, x: Register) -> bool {
+        let key = self.v[x.index()] & 0xF;
+        self.keypad.pressed_keys() & (1 << key) != 0
     }
 
-    fn execute_f_opcode(&mut self) -> usize {
-        let second_byte = self.ram[self.ca + 1];
-        let second_nymble = self.ram[self.ca] & 0xF;
-        match second_byte {
-            0x07 => self.v[second_nymble as usize] = self.delay_timer,
-            0x0A => {
-                // keyboard
-                self.not_implemented();
-            }
-            0x15 => self.delay_timer = self.v[second_nymble as usize],
-            0x18 => self.sound_timer = self.v[second_nymble as usize],
-            0x1E => {
-                self.i += self.v[second_nymble as usize] as u16;
-                if self.i > 0xFFF {
-                    self.v[0xF] = 1;
-                }
-            }
-            0x29 => {
-                // font
-                self.not_implemented();
-            }
-            0x33 => {
-                self.not_implemented();
-            }
-            0x55 => {
-                // TODO check borders
-                for x in 0..second_nymble {
-                    self.ram[self.i as usize + x as usize] = self.v[x as usize];
+    // block until a key is pressed and released, then store it in Vx
+    // like on COSMAC VIP, the key is registered only when it is released
+    fn wait_for_key(&mut self, x: usize) -> usize {
+        let keys = self.keypad.pressed_keys();
+        match self.pressed_key {
+            None => {
+                if keys != 0 {
+                    self.pressed_key = Some(keys.trailing_zeros() as u8);
                 }
+                self.report.waiting_for_key = true;
+                self.ca
             }
-            0x65 => {
-                // TODO check borders
-                for x in 0..second_nymble {
-                    self.v[x as usize] = self.ram[self.i as usize + x as usize];
+            Some(key) => {
+                if keys & (1 << key) != 0 {
+                    self.report.waiting_for_key = true;
+                    return self.ca;
                 }
+                self.v[x] = key;
+                self.pressed_key = None;
+                self.ca + 2
             }
-            _ => {
-                self.warning("Illegal opcode");
-            }
         }
-        self.ca + 2
+    }
+
+    // how far FX55/FX65 move I after the given number of registers
+    fn load_store_increment(&self, count: usize) -> u16 {
+        match self.quirks.load_store_increment {
+            LoadStoreIncrement::None => 0,
+            LoadStoreIncrement::X => count as u16 - 1,
+            LoadStoreIncrement::XPlusOne => count as u16,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::chip8_audio::{DummyCHIP8Audio, SquareWave, WavCHIP8Audio, DEFAULT_SAMPLE_RATE};
     use crate::chip8_display::DummyCHIP8Display;
+    use crate::chip8_keypad::{DummyCHIP8Keypad, ScriptedCHIP8Keypad};
+    use crate::fault_policy::FaultPolicy;
+    use crate::instruction::Address;
+    use crate::quirks::Quirks;
+    use crate::random::RandomPreset;
 
     #[test]
-    fn test_extract_address() {
+    fn test_decode_current_instruction() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 0420  -- just an unused instruction for the test
         chip8.load_from_memory(&[0x04, 0x20]);
-        assert_eq!(chip8.extract_address(), 0x0420);
+        assert_eq!(
+            decode(chip8.opcode()),
+            Instruction::MachineCode {
+                address: Address(0x0420)
+            }
+        );
     }
 
     #[test]
     fn test_jump() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 1204  -- jump to the address Ox204, which is two instructions down
         // 0000  -- nothing here
         // CC00  -- we should jump here
         chip8.load_from_memory(&[0x12, 0x04, 0x00, 0x00, 0xCC, 0x00]);
-        chip8.execute_opcode();
+        chip8.step().unwrap();
         println!("{:x}", chip8.ca);
         assert_eq!(chip8.ram[chip8.ca], 0xCC);
     }
@@ -378,20 +1157,36 @@ mod tests {
     #[test]
     fn test_jump_with_offset() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 60AA  -- store AA in v0
         // B400  -- jump to the address (0x400 + v0)
         chip8.load_from_memory(&[0x60, 0xAA, 0xB4, 0x00]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // jump
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // jump
         assert_eq!(chip8.ca, 0x4AA);
     }
 
     #[test]
     fn test_store() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6001  -- store the value 1 in register 0
         // 6102  -- store the value 2 in register 1
@@ -417,7 +1212,7 @@ mod tests {
         ]);
 
         for i in 0..15 {
-            chip8.execute_opcode();
+            chip8.step().unwrap();
             assert_eq!(chip8.v[i], (i + 1) as u8);
         }
     }
@@ -425,7 +1220,15 @@ mod tests {
     #[test]
     fn test_skip3() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6001  -- store the value 1 in register 0
         // 3001  -- skip the next instruction if the value in register 0 is equal to 1
@@ -433,11 +1236,11 @@ mod tests {
         // 3000  -- we should jump here and do another check
         // 0300  -- we should end up here
         chip8.load_from_memory(&[0x60, 0x01, 0x30, 0x01, 0x00, 0x00, 0x30, 0x00, 0x03, 0x00]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // successful skip
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // successful skip
         assert_eq!(chip8.ca, 0x206);
 
-        chip8.execute_opcode(); // unsuccessful skip
+        chip8.step().unwrap(); // unsuccessful skip
         assert_eq!(chip8.ca, 0x208);
         assert_eq!(chip8.ram[chip8.ca], 0x03);
     }
@@ -445,7 +1248,15 @@ mod tests {
     #[test]
     fn test_skip4() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6D0A  -- store the value A in register D
         // 4D0A  -- skip the next instruction if the value in register D is not equal to A (this condition doesn't hold, so no skip)
@@ -453,11 +1264,11 @@ mod tests {
         // 0000  -- we should skip this instruction
         // 0400  -- we should end up here
         chip8.load_from_memory(&[0x6D, 0x0A, 0x4D, 0x0A, 0x40, 0x0A, 0x00, 0x00, 0x04, 0x00]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // unsuccessful skip
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // unsuccessful skip
         assert_eq!(chip8.ca, 0x204);
 
-        chip8.execute_opcode(); // successful skip
+        chip8.step().unwrap(); // successful skip
         assert_eq!(chip8.ca, 0x208);
         assert_eq!(chip8.ram[chip8.ca], 0x04);
     }
@@ -465,7 +1276,15 @@ mod tests {
     #[test]
     fn test_skip5() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 5AB0  -- skip the following instruction if the value in registers A and B are equal (they are)
         // 0000  -- nothing here
@@ -473,11 +1292,11 @@ mod tests {
         // 5AB0  -- skip the following instruction if the value in registers A and B are equal (they are not)
         // 0500  -- we should end up here
         chip8.load_from_memory(&[0x5A, 0xB0, 0x00, 0x00, 0x6B, 0xFF, 0x5A, 0xB0, 0x05, 0x00]);
-        chip8.execute_opcode(); // skip
+        chip8.step().unwrap(); // skip
         assert_eq!(chip8.ca, 0x204);
 
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // unsuccessful skip
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // unsuccessful skip
         assert_eq!(chip8.ca, 0x208);
         assert_eq!(chip8.ram[chip8.ca], 0x05);
     }
@@ -485,7 +1304,15 @@ mod tests {
     #[test]
     fn test_skip9() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 9AB0  -- skip the following instruction if the value in registers A and B are different (they are not)
         // 6BFF  -- store FF in register B
@@ -493,11 +1320,11 @@ mod tests {
         // 0000  -- nothing here
         // 0900  -- we should end up here
         chip8.load_from_memory(&[0x9A, 0xB0, 0x6B, 0xFF, 0x9A, 0xB0, 0x00, 0x00, 0x09, 0x00]);
-        chip8.execute_opcode(); // no skip
+        chip8.step().unwrap(); // no skip
         assert_eq!(chip8.ca, 0x202);
 
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // successful skip
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // successful skip
         assert_eq!(chip8.ca, 0x208);
         assert_eq!(chip8.ram[chip8.ca], 0x09);
     }
@@ -505,52 +1332,76 @@ mod tests {
     #[test]
     fn test_adding_constant() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6001  -- store the value 1 in register 0
         // 70F0  -- add the value 0xF0 to register 0
         // 7000  -- add 0 to register 0 (it shouldn't chage)
         // 7010  -- add 0x10 to register 0, it should wrap around
         chip8.load_from_memory(&[0x60, 0x01, 0x70, 0xF0, 0x70, 0x00, 0x70, 0x10]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // add
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // add
         assert_eq!(chip8.v[0], 0xF1);
 
-        chip8.execute_opcode(); // add 0
+        chip8.step().unwrap(); // add 0
         assert_eq!(chip8.v[0], 0xF1);
 
-        chip8.execute_opcode(); // add 0x10
+        chip8.step().unwrap(); // add 0x10
         assert_eq!(chip8.v[0], 0x01);
     }
 
     #[test]
     fn test_copy_register() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6001  -- store the value 1 in register 0
         // 8100  -- copy v0 to v1
         chip8.load_from_memory(&[0x60, 0x01, 0x81, 0x00]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // copy
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // copy
         assert_eq!(chip8.v[1], 0x01);
     }
 
     #[test]
     fn test_or() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 605F  -- store 0b0101_1111 in v0
         // 6FAA  -- store 0b1010_1010 in vF
         // 80F1  -- v0 |= vF
         chip8.load_from_memory(&[0x60, 0x5F, 0x6F, 0xAA, 0x80, 0xF1]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
         assert_eq!(chip8.v[0x0], 0b0101_1111);
         assert_eq!(chip8.v[0xF], 0b1010_1010);
 
-        chip8.execute_opcode(); // or
+        chip8.step().unwrap(); // or
         assert_eq!(chip8.v[0x0], 0b1111_1111); // changed
         assert_eq!(chip8.v[0xF], 0b1010_1010); // unchanged
     }
@@ -558,18 +1409,26 @@ mod tests {
     #[test]
     fn test_and() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6055  -- store 0b0101_0101 in v0
         // 6FAA  -- store 0b1010_1010 in vF
         // 80F2  -- v0 &= vF
         chip8.load_from_memory(&[0x60, 0x55, 0x6F, 0xAA, 0x80, 0xF2]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
         assert_eq!(chip8.v[0x0], 0b0101_0101);
         assert_eq!(chip8.v[0xF], 0b1010_1010);
 
-        chip8.execute_opcode(); // or
+        chip8.step().unwrap(); // or
         assert_eq!(chip8.v[0x0], 0b0000_0000); // changed
         assert_eq!(chip8.v[0xF], 0b1010_1010); // unchanged
     }
@@ -577,18 +1436,26 @@ mod tests {
     #[test]
     fn test_xor() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 605D  -- store 0b0101_1101 in v0
         // 6FAA  -- store 0b1010_1010 in vF
         // 80F3  -- v0 ^= vF
         chip8.load_from_memory(&[0x60, 0x5D, 0x6F, 0xAA, 0x80, 0xF3]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
         assert_eq!(chip8.v[0x0], 0b0101_1101);
         assert_eq!(chip8.v[0xF], 0b1010_1010);
 
-        chip8.execute_opcode(); // or
+        chip8.step().unwrap(); // or
         assert_eq!(chip8.v[0x0], 0b1111_0111); // changed
         assert_eq!(chip8.v[0xF], 0b1010_1010); // unchanged
     }
@@ -596,7 +1463,15 @@ mod tests {
     #[test]
     fn test_add() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6CF0  -- store the value F0 in vC
         // 6D01  -- store the value 1 to vD
@@ -607,18 +1482,18 @@ mod tests {
         chip8.load_from_memory(&[
             0x6C, 0xF0, 0x6D, 0x01, 0x6E, 0x10, 0x6F, 0x33, 0x8C, 0xD4, 0x8E, 0xC4,
         ]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
         assert_eq!(chip8.v[0xF], 0x33); // it is about to change, so let's check if our write worked
 
-        chip8.execute_opcode(); // add 1
+        chip8.step().unwrap(); // add 1
         assert_eq!(chip8.v[0xC], 0xF1); // changed
         assert_eq!(chip8.v[0xD], 0x01); // unchanged
         assert_eq!(chip8.v[0xF], 0x00); // carry is 0
 
-        chip8.execute_opcode(); // add 10
+        chip8.step().unwrap(); // add 10
         assert_eq!(chip8.v[0xC], 0xF1); // unchanged
         assert_eq!(chip8.v[0xE], 0x01); // overflow
         assert_eq!(chip8.v[0xF], 0x01); // carry is 1
@@ -627,7 +1502,15 @@ mod tests {
     #[test]
     fn test_sub() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 650A  -- store the value A in v5
         // 6609  -- store the value 9 in v6
@@ -638,18 +1521,18 @@ mod tests {
         chip8.load_from_memory(&[
             0x65, 0x0A, 0x66, 0x09, 0x67, 0x02, 0x6F, 0x66, 0x85, 0x65, 0x85, 0x75,
         ]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
         assert_eq!(chip8.v[0xF], 0x66); // it is about to change, so let's check if our write worked
 
-        chip8.execute_opcode(); // sub 9
+        chip8.step().unwrap(); // sub 9
         assert_eq!(chip8.v[0x5], 0x01); // changed
         assert_eq!(chip8.v[0x6], 0x09); // unchanged
         assert_eq!(chip8.v[0xF], 0x01); // no borrow
 
-        chip8.execute_opcode(); // sub 2
+        chip8.step().unwrap(); // sub 2
         assert_eq!(chip8.v[0x5], 0xFF); // undereflow
         assert_eq!(chip8.v[0xF], 0x00); // borrow
     }
@@ -657,7 +1540,15 @@ mod tests {
     #[test]
     fn test_other_sub() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 650A  -- store the value A in v5
         // 6609  -- store the value 9 in v6
@@ -668,18 +1559,18 @@ mod tests {
         chip8.load_from_memory(&[
             0x65, 0x0A, 0x66, 0x09, 0x67, 0x02, 0x6F, 0x66, 0x85, 0x67, 0x87, 0x57,
         ]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
         assert_eq!(chip8.v[0xF], 0x66); // it is about to change, so let's check if our write worked
 
-        chip8.execute_opcode(); // sub 9
+        chip8.step().unwrap(); // sub 9
         assert_eq!(chip8.v[0x5], 0xFF); // underflow
         assert_eq!(chip8.v[0x6], 0x09); // unchanged
         assert_eq!(chip8.v[0xF], 0x00); // borrow
 
-        chip8.execute_opcode(); // sub 2
+        chip8.step().unwrap(); // sub 2
         assert_eq!(chip8.v[0x7], 0xFD); // changed
         assert_eq!(chip8.v[0xF], 0x01); // no borrow
     }
@@ -687,19 +1578,27 @@ mod tests {
     #[test]
     fn test_shr() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6805  -- store the value 5 in v8
         // 8186  -- v1 = v8 >> 1
         // 8116  -- v1 >>= 1
         chip8.load_from_memory(&[0x68, 0x05, 0x81, 0x86, 0x81, 0x16]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // shr
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // shr
         assert_eq!(chip8.v[0x8], 0b0101); // unchanged
         assert_eq!(chip8.v[0x1], 0b0010); // result
         assert_eq!(chip8.v[0xF], 0b0001); // shifted bit
 
-        chip8.execute_opcode(); // shr
+        chip8.step().unwrap(); // shr
         assert_eq!(chip8.v[0x1], 0b0001); // result
         assert_eq!(chip8.v[0xF], 0b0000); // shifted bit
     }
@@ -707,19 +1606,27 @@ mod tests {
     #[test]
     fn test_shl() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 68A0  -- store the value A0 (0b1010_0000) in v8
         // 898E  -- v9 = v8 << 1
         // 899E  -- v9 <<= 1
         chip8.load_from_memory(&[0x68, 0xA0, 0x89, 0x8E, 0x89, 0x9E]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // shl
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // shl
         assert_eq!(chip8.v[0x8], 0b1010_0000); // unchanged
         assert_eq!(chip8.v[0x9], 0b0100_0000); // result
         assert_eq!(chip8.v[0xF], 0b0000_0001); // shifted bit
 
-        chip8.execute_opcode(); // shl
+        chip8.step().unwrap(); // shl
         assert_eq!(chip8.v[0x9], 0b1000_0000); // result
         assert_eq!(chip8.v[0xF], 0b0000_0000); // shifted bit
     }
@@ -727,46 +1634,126 @@ mod tests {
     #[test]
     fn test_store_in_i() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // A420  -- store the address 420 in i
         chip8.load_from_memory(&[0xA4, 0x20]);
-        chip8.execute_opcode(); // store
+        chip8.step().unwrap(); // store
         assert_eq!(chip8.i, 0x420);
     }
 
     #[test]
     fn test_random() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // CD0F  -- store the random number with mask 0F in vD
         chip8.load_from_memory(&[0xCD, 0x0F]);
-        chip8.execute_opcode(); // rand
+        chip8.step().unwrap(); // rand
         assert!(chip8.v[0xD] <= 0xF);
 
         // we are going to check if the number really changes
         // C7FF  -- store the random number FF in v7
-        let memory = std::iter::repeat([0xC7u8, 0xFFu8])
-            .take(0xFF)
+        let memory = std::iter::repeat_n([0xC7u8, 0xFFu8], 0xFF)
             .flatten()
             .collect::<Vec<_>>();
         chip8.load_from_memory(&memory);
-        chip8.execute_opcode(); // rand
+        chip8.step().unwrap(); // rand
         let value = chip8.v[0x7];
         for _ in 0..=0xFF {
-            chip8.execute_opcode(); // rand
+            chip8.step().unwrap(); // rand
             if chip8.v[0x7] != value {
                 break;
             }
         }
         assert_ne!(value, chip8.v[0x7]);
+
+        // the same seed gives the same numbers
+        let mut numbers = Vec::new();
+        for preset in [RandomPreset::Xorshift, RandomPreset::Timing] {
+            for _ in 0..2 {
+                chip8.ca = 0x200;
+                chip8.set_random_generator(preset.generator(1234));
+                chip8.run_cycles(4).unwrap();
+                numbers.push(chip8.v[0x7]);
+            }
+        }
+        assert_eq!(numbers[0], numbers[1]);
+        assert_eq!(numbers[2], numbers[3]);
+        chip8.ca = 0x200;
+        chip8.set_random_seed(1234);
+        chip8.run_cycles(4).unwrap();
+        assert_eq!(chip8.v[0x7], numbers[3]);
+
+        // the timing generator depends on the timing
+        chip8.ca = 0x200;
+        chip8.set_random_seed(1234);
+        chip8.run_cycles(3).unwrap();
+        chip8.tick_timers();
+        chip8.run_cycles(1).unwrap();
+        assert_ne!(chip8.v[0x7], numbers[3]);
+
+        // a save state brings its generator along
+        chip8.ca = 0x200;
+        chip8.set_random_seed(1234);
+        let state = chip8.save_state();
+        chip8.set_random_generator(RandomPreset::Xorshift.generator(1));
+        chip8.restore_state(&state).unwrap();
+        chip8.run_cycles(4).unwrap();
+        assert_eq!(chip8.v[0x7], numbers[3]);
+
+        // a generator of the program's own stays, only its state is restored
+        struct Constant(u64);
+        impl RandomGenerator for Constant {
+            fn next_byte(&mut self) -> u8 {
+                self.0 as u8
+            }
+
+            fn state(&self) -> u64 {
+                self.0
+            }
+
+            fn restore(&mut self, state: u64) {
+                self.0 = state;
+            }
+        }
+        chip8.set_random_generator(Box::new(Constant(7)));
+        let state = chip8.save_state();
+        assert_eq!(state.rng_preset, None);
+        chip8.set_random_generator(Box::new(Constant(9)));
+        chip8.restore_state(&state).unwrap();
+        chip8.step().unwrap(); // rand
+        assert_eq!(chip8.v[0x7], 7);
     }
 
     #[test]
     fn test_call() {
         let mut display = DummyCHIP8Display::new();
-        let mut chip8 = CHIP8::new(&mut display);
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
 
         // 6ABB  -- store the value BB in vA
         // 2208  -- call subroutine at 208
@@ -777,20 +1764,1440 @@ mod tests {
         chip8.load_from_memory(&[
             0x6A, 0xBB, 0x22, 0x08, 0x7A, 0x01, 0x12, 0x0C, 0x7A, 0x02, 0x00, 0xEE,
         ]);
-        chip8.execute_opcode(); // store
-        chip8.execute_opcode(); // call
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // call
         assert_eq!(chip8.ca, 0x208);
         assert_eq!(chip8.sp, 0x01);
         assert_eq!(chip8.stack[0], 0x202);
 
-        chip8.execute_opcode(); // add 1
-        chip8.execute_opcode(); // return
+        chip8.step().unwrap(); // add 1
+        chip8.step().unwrap(); // return
         assert_eq!(chip8.ca, 0x204);
 
-        chip8.execute_opcode(); // add 2
-        chip8.execute_opcode(); // jump
+        chip8.step().unwrap(); // add 2
+        chip8.step().unwrap(); // jump
 
         assert_eq!(chip8.ca, 0x20C);
         assert_eq!(chip8.v[0xA], 0xBB + 3);
     }
+
+    #[test]
+    fn test_draw() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 6002  -- store 2 in v0
+        // 6103  -- store 3 in v1
+        // A20A  -- store the address of the sprite in i
+        // D012  -- draw 2 bytes of the sprite at (v0, v1)
+        // D012  -- draw the same sprite again, erasing it
+        // F0    -- sprite: 0b1111_0000
+        // 81    -- sprite: 0b1000_0001
+        chip8.load_from_memory(&[
+            0x60, 0x02, 0x61, 0x03, 0xA2, 0x0A, 0xD0, 0x12, 0xD0, 0x12, 0xF0, 0x81,
+        ]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // draw
+        assert_eq!(chip8.video_memory[3][2..10], [1, 1, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(chip8.video_memory[4][2..10], [1, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(chip8.video_memory[5][2..10], [0; 8]);
+        assert_eq!(chip8.v[0xF], 0); // no collision
+
+        chip8.step().unwrap(); // draw again
+        assert_eq!(chip8.video_memory[3][2..10], [0; 8]);
+        assert_eq!(chip8.video_memory[4][2..10], [0; 8]);
+        assert_eq!(chip8.v[0xF], 1); // collision
+    }
+
+    #[test]
+    fn test_draw_wraps_start_and_clips_sprite() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 607C  -- store 124 in v0, it wraps around to 60
+        // 611F  -- store 31 in v1, the last row
+        // A208  -- store the address of the sprite in i
+        // D012  -- draw 2 bytes of the sprite at (v0, v1)
+        // FF    -- sprite: 0b1111_1111
+        // FF    -- sprite: 0b1111_1111
+        chip8.load_from_memory(&[0x60, 0x7C, 0x61, 0x1F, 0xA2, 0x08, 0xD0, 0x12, 0xFF, 0xFF]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // draw
+        assert_eq!(chip8.video_memory[31][60..64], [1, 1, 1, 1]);
+        assert_eq!(chip8.video_memory[31][0..4], [0, 0, 0, 0]); // clipped, not wrapped
+        assert_eq!(chip8.video_memory[0][60..64], [0, 0, 0, 0]); // clipped, not wrapped
+        assert_eq!(chip8.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_clear_screen() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // A206  -- store the address of the sprite in i
+        // D001  -- draw 1 byte of the sprite at (v0, v1)
+        // 00E0  -- clear the screen
+        // 80    -- sprite: 0b1000_0000
+        chip8.load_from_memory(&[0xA2, 0x06, 0xD0, 0x01, 0x00, 0xE0, 0x80]);
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // draw
+        assert_eq!(chip8.video_memory[0][0], 1);
+
+        chip8.step().unwrap(); // clear
+        assert_eq!(chip8.video_memory, VideoMemory::new());
+    }
+
+    #[test]
+    fn test_skip_if_key_pressed() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = ScriptedCHIP8Keypad::new(&[0b0010_0000, 0b0000_0000]);
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 6505  -- store 5 in v5
+        // E59E  -- skip the next instruction if the key 5 is pressed (it is)
+        // 0000  -- nothing here
+        // E59E  -- skip the next instruction if the key 5 is pressed (it is not)
+        // 0E00  -- we should end up here
+        chip8.load_from_memory(&[0x65, 0x05, 0xE5, 0x9E, 0x00, 0x00, 0xE5, 0x9E, 0x0E, 0x00]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // successful skip
+        assert_eq!(chip8.ca, 0x206);
+
+        chip8.step().unwrap(); // unsuccessful skip
+        assert_eq!(chip8.ca, 0x208);
+        assert_eq!(chip8.ram[chip8.ca], 0x0E);
+    }
+
+    #[test]
+    fn test_skip_if_key_not_pressed() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = ScriptedCHIP8Keypad::new(&[0b1000_0000_0000_0000, 0b0000_0000]);
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 6A0F  -- store F in vA
+        // EAA1  -- skip the next instruction if the key F is not pressed (it is)
+        // EAA1  -- skip the next instruction if the key F is not pressed (it is not)
+        // 0000  -- nothing here
+        // 0E00  -- we should end up here
+        chip8.load_from_memory(&[0x6A, 0x0F, 0xEA, 0xA1, 0xEA, 0xA1, 0x00, 0x00, 0x0E, 0x00]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // unsuccessful skip
+        assert_eq!(chip8.ca, 0x204);
+
+        chip8.step().unwrap(); // successful skip
+        assert_eq!(chip8.ca, 0x208);
+        assert_eq!(chip8.ram[chip8.ca], 0x0E);
+    }
+
+    #[test]
+    fn test_wait_for_key() {
+        let mut display = DummyCHIP8Display::new();
+        // nothing is pressed, then the key 7 is held for two polls and released
+        let mut keypad = ScriptedCHIP8Keypad::new(&[0x0000, 0x0080, 0x0080, 0x0000]);
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // F30A  -- wait for a key and store it in v3
+        chip8.load_from_memory(&[0xF3, 0x0A]);
+        chip8.step().unwrap(); // nothing pressed
+        assert_eq!(chip8.ca, 0x200);
+
+        chip8.step().unwrap(); // pressed
+        assert_eq!(chip8.ca, 0x200);
+
+        chip8.step().unwrap(); // still held
+        assert_eq!(chip8.ca, 0x200);
+        assert_eq!(chip8.v[0x3], 0x00);
+
+        chip8.step().unwrap(); // released
+        assert_eq!(chip8.ca, 0x202);
+        assert_eq!(chip8.v[0x3], 0x07);
+    }
+
+    #[test]
+    fn test_font() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 6A0B  -- store B in vA
+        // FA29  -- point i to the font character B
+        // 61F3  -- store F3 in v1
+        // F129  -- point i to the font character 3, the high nymble is ignored
+        chip8.load_from_memory(&[0x6A, 0x0B, 0xFA, 0x29, 0x61, 0xF3, 0xF1, 0x29]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // font
+        let i = chip8.i as usize;
+        assert_eq!(chip8.ram[i..i + 5], [0xE0, 0x90, 0xE0, 0x90, 0xE0]);
+
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // font
+        let i = chip8.i as usize;
+        assert_eq!(chip8.ram[i..i + 5], [0xF0, 0x10, 0xF0, 0x10, 0xF0]);
+    }
+
+    #[test]
+    fn test_bcd() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 62FE  -- store 254 in v2
+        // A300  -- store the address 300 in i
+        // F233  -- store the decimal digits of v2 at i
+        // 6209  -- store 9 in v2
+        // F233  -- store the decimal digits of v2 at i
+        chip8.load_from_memory(&[0x62, 0xFE, 0xA3, 0x00, 0xF2, 0x33, 0x62, 0x09, 0xF2, 0x33]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // bcd
+        assert_eq!(chip8.ram[0x300..0x303], [2, 5, 4]);
+
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // bcd
+        assert_eq!(chip8.ram[0x300..0x303], [0, 0, 9]);
+    }
+
+    #[test]
+    fn test_store_and_load_registers() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 6011  -- store 11 in v0
+        // 6122  -- store 22 in v1
+        // A300  -- store the address 300 in i
+        // F155  -- store v0 through v1 at i, v1 included
+        // 6000  -- store 0 in v0
+        // 6100  -- store 0 in v1
+        // F165  -- load v0 through v1 from i, v1 included
+        chip8.load_from_memory(&[
+            0x60, 0x11, 0x61, 0x22, 0xA3, 0x00, 0xF1, 0x55, 0x60, 0x00, 0x61, 0x00, 0xF1, 0x65,
+        ]);
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.ram[0x300..0x303], [0x11, 0x22, 0x00]);
+
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.v[0..2], [0x11, 0x22]);
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        chip8.set_instructions_per_frame(3);
+
+        // 6003  -- store 3 in v0
+        // F015  -- set the delay timer to v0
+        // F018  -- set the sound timer to v0
+        // F107  -- store the delay timer in v1
+        // F207  -- store the delay timer in v2
+        // F307  -- store the delay timer in v3
+        chip8.load_from_memory(&[
+            0x60, 0x03, 0xF0, 0x15, 0xF0, 0x18, 0xF1, 0x07, 0xF2, 0x07, 0xF3, 0x07,
+        ]);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.delay_timer, 2);
+        assert_eq!(chip8.sound_timer, 2);
+
+        chip8.run_frame().unwrap();
+        // the timers don't change within a frame
+        assert_eq!(chip8.v[0x1], 2);
+        assert_eq!(chip8.v[0x2], 2);
+        assert_eq!(chip8.v[0x3], 2);
+        assert_eq!(chip8.delay_timer, 1);
+
+        chip8.set_instructions_per_frame(0);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.delay_timer, 0);
+        assert_eq!(chip8.sound_timer, 0);
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.delay_timer, 0); // stays at zero
+        assert_eq!(chip8.sound_timer, 0);
+    }
+
+    #[test]
+    fn test_sound() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let wave = SquareWave::new(DEFAULT_SAMPLE_RATE);
+        let mut audio = WavCHIP8Audio::new(std::io::Cursor::new(Vec::new()), wave).unwrap();
+        {
+            let mut chip8 = CHIP8::new(
+                &mut display,
+                &mut keypad,
+                &mut audio,
+                Platform::Chip8,
+                Quirks::default(),
+            );
+            chip8.set_instructions_per_frame(2);
+
+            // 6002  -- store 2 in v0
+            // F018  -- set the sound timer to v0
+            chip8.load_from_memory(&[0x60, 0x02, 0xF0, 0x18]);
+            chip8.run_frame().unwrap(); // beeps, the timer goes down to 1
+            assert!(chip8.tone);
+
+            chip8.set_instructions_per_frame(0);
+            chip8.run_frame().unwrap(); // beeps, the timer goes down to 0
+            assert!(!chip8.tone);
+
+            chip8.run_frame().unwrap(); // silent
+        }
+        let wav = audio.finish().unwrap().into_inner();
+
+        let samples = &wav[44..];
+        let frame_size = 2 * (DEFAULT_SAMPLE_RATE / FRAME_RATE) as usize;
+        assert_eq!(samples.len(), 3 * frame_size);
+        assert!(samples[..frame_size].iter().any(|byte| *byte != 0));
+        assert!(samples[frame_size..2 * frame_size]
+            .iter()
+            .any(|byte| *byte != 0));
+        assert!(samples[2 * frame_size..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_load_and_store() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 6011  -- store 11 in v0
+        // 6122  -- store 22 in v1
+        // 6233  -- store 33 in v2
+        // A300  -- store the address 300 in i
+        // F155  -- store v0..=v1 at i
+        // F265  -- load v0..=v2 from i
+        chip8.load_from_memory(&[
+            0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0xA3, 0x00, 0xF1, 0x55, 0xF2, 0x65,
+        ]);
+        for _ in 0..5 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.ram[0x300..0x303], [0x11, 0x22, 0x00]);
+        assert_eq!(chip8.i, 0x300); // unchanged
+
+        chip8.step().unwrap(); // load
+        assert_eq!(chip8.v[0..3], [0x11, 0x22, 0x00]);
+        assert_eq!(chip8.i, 0x300); // unchanged
+    }
+
+    #[test]
+    fn test_load_and_store_increment_quirk() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::cosmac_vip(),
+        );
+
+        // A300  -- store the address 300 in i
+        // F155  -- store v0..=v1 at i
+        // F265  -- load v0..=v2 from i
+        chip8.load_from_memory(&[0xA3, 0x00, 0xF1, 0x55, 0xF2, 0x65]);
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // store
+        assert_eq!(chip8.i, 0x302);
+
+        chip8.step().unwrap(); // load
+        assert_eq!(chip8.i, 0x305);
+    }
+
+    #[test]
+    fn test_load_and_store_increment_by_x_quirk() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::chip48(),
+        );
+
+        // A300  -- store the address 300 in i
+        // F155  -- store v0..=v1 at i
+        // F065  -- load v0 from i
+        chip8.load_from_memory(&[0xA3, 0x00, 0xF1, 0x55, 0xF0, 0x65]);
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // store
+        assert_eq!(chip8.i, 0x301);
+
+        chip8.step().unwrap(); // load
+        assert_eq!(chip8.i, 0x301);
+    }
+
+    #[test]
+    fn test_shift_quirk() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::superchip(),
+        );
+
+        // 6105  -- store the value 5 in v1
+        // 6881  -- store the value 81 in v8
+        // 8186  -- v1 >>= 1, v8 is ignored
+        // 818E  -- v1 <<= 1, v8 is ignored
+        chip8.load_from_memory(&[0x61, 0x05, 0x68, 0x81, 0x81, 0x86, 0x81, 0x8E]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // shr
+        assert_eq!(chip8.v[0x1], 0b0010); // result
+        assert_eq!(chip8.v[0xF], 0b0001); // shifted bit
+
+        chip8.step().unwrap(); // shl
+        assert_eq!(chip8.v[0x1], 0b0100); // result
+        assert_eq!(chip8.v[0xF], 0b0000); // shifted bit
+    }
+
+    #[test]
+    fn test_jump_with_offset_quirk() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::chip48(),
+        );
+
+        // 60AA  -- store AA in v0
+        // 6410  -- store 10 in v4
+        // B400  -- jump to the address (0x400 + v4)
+        chip8.load_from_memory(&[0x60, 0xAA, 0x64, 0x10, 0xB4, 0x00]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // jump
+        assert_eq!(chip8.ca, 0x410);
+    }
+
+    #[test]
+    fn test_logic_resets_vf_quirk() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::cosmac_vip(),
+        );
+
+        // 6FAA  -- store AA in vF
+        // 8011  -- v0 |= v1
+        chip8.load_from_memory(&[0x6F, 0xAA, 0x80, 0x11]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // or
+        assert_eq!(chip8.v[0xF], 0);
+    }
+
+    #[test]
+    fn test_wrap_sprites_quirk() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let quirks = Quirks {
+            wrap_sprites: true,
+            ..Quirks::default()
+        };
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            quirks,
+        );
+
+        // 603C  -- store 60 in v0
+        // 611F  -- store 31 in v1, the last row
+        // A208  -- store the address of the sprite in i
+        // D012  -- draw 2 bytes of the sprite at (v0, v1)
+        // FF    -- sprite: 0b1111_1111
+        // FF    -- sprite: 0b1111_1111
+        chip8.load_from_memory(&[0x60, 0x3C, 0x61, 0x1F, 0xA2, 0x08, 0xD0, 0x12, 0xFF, 0xFF]);
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.video_memory[31][60..64], [1, 1, 1, 1]);
+        assert_eq!(chip8.video_memory[31][0..4], [1, 1, 1, 1]);
+        assert_eq!(chip8.video_memory[0][60..64], [1, 1, 1, 1]);
+        assert_eq!(chip8.video_memory[0][0..4], [1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_display_wait_quirk() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::cosmac_vip(),
+        );
+
+        // D001  -- draw 1 byte of the sprite at (v0, v1)
+        // D001  -- draw 1 byte of the sprite at (v0, v1)
+        chip8.load_from_memory(&[0xD0, 0x01, 0xD0, 0x01]);
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.ca, 0x202); // the frame ends after the first sprite
+
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.ca, 0x204);
+    }
+
+    #[test]
+    fn test_superchip_opcodes_are_illegal_on_chip8() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 00FF  -- switch to high resolution
+        // 00FD  -- exit
+        chip8.load_from_memory(&[0x00, 0xFF, 0x00, 0xFD]);
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::IllegalOpcode {
+                address: 0x200,
+                opcode: 0x00FF
+            })
+        );
+        assert!(!chip8.video_memory.is_hires());
+        assert_eq!(chip8.ca, 0x200);
+
+        chip8.ca = 0x202;
+        assert!(chip8.step().is_err());
+        assert!(!chip8.halted);
+        assert_eq!(chip8.ca, 0x202);
+    }
+
+    #[test]
+    fn test_resolution_switch() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::superchip(),
+        );
+
+        // 00FF  -- switch to high resolution
+        // 607C  -- store 124 in v0
+        // 613E  -- store 62 in v1
+        // A20C  -- store the address of the sprite in i
+        // D011  -- draw 1 byte of the sprite at (v0, v1)
+        // 00FE  -- switch to low resolution
+        // 7C    -- sprite: 0b0111_1100
+        chip8.load_from_memory(&[
+            0x00, 0xFF, 0x60, 0x7C, 0x61, 0x3E, 0xA2, 0x0C, 0xD0, 0x11, 0x00, 0xFE, 0x7C,
+        ]);
+        chip8.step().unwrap(); // hires
+        assert_eq!(chip8.video_memory.width(), 128);
+        assert_eq!(chip8.video_memory.height(), 64);
+
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // draw
+        assert_eq!(chip8.video_memory[62][124..128], [0, 1, 1, 1]); // clipped at the right edge
+
+        chip8.step().unwrap(); // lores
+        assert_eq!(chip8.video_memory.width(), 64);
+        assert_eq!(chip8.video_memory, VideoMemory::new()); // cleared
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::superchip(),
+        );
+
+        // 600A  -- store 10 in v0
+        // A20C  -- store the address of the sprite in i
+        // D001  -- draw 1 byte of the sprite at (v0, v0)
+        // 00C3  -- scroll down 3 rows
+        // 00FB  -- scroll right 4 columns
+        // 00FC  -- scroll left 4 columns
+        // 80    -- sprite: 0b1000_0000
+        chip8.load_from_memory(&[
+            0x60, 0x0A, 0xA2, 0x0C, 0xD0, 0x01, 0x00, 0xC3, 0x00, 0xFB, 0x00, 0xFC, 0x80,
+        ]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // draw
+        assert_eq!(chip8.video_memory[0][10], 0); // v0 is also used for y
+        assert_eq!(chip8.video_memory[10][10], 1);
+
+        chip8.step().unwrap(); // scroll down
+        assert_eq!(chip8.video_memory[10][10], 0);
+        assert_eq!(chip8.video_memory[13][10], 1);
+
+        chip8.step().unwrap(); // scroll right
+        assert_eq!(chip8.video_memory[13][10], 0);
+        assert_eq!(chip8.video_memory[13][14], 1);
+
+        chip8.step().unwrap(); // scroll left
+        assert_eq!(chip8.video_memory[13][14], 0);
+        assert_eq!(chip8.video_memory[13][10], 1);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::superchip(),
+        );
+
+        // 00FD  -- exit
+        // 6001  -- store 1 in v0, never executed
+        chip8.load_from_memory(&[0x00, 0xFD, 0x60, 0x01]);
+        chip8.run_frame().unwrap();
+        assert!(chip8.is_halted());
+        assert_eq!(chip8.ca, 0x200);
+        assert_eq!(chip8.v[0x0], 0);
+    }
+
+    #[test]
+    fn test_large_sprite() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::superchip(),
+        );
+
+        // 00FF  -- switch to high resolution
+        // A300  -- store the address of the sprite in i
+        // D000  -- draw the 16x16 sprite at (v0, v0)
+        chip8.load_from_memory(&[0x00, 0xFF, 0xA3, 0x00, 0xD0, 0x00]);
+        for row in 0..16 {
+            chip8.ram[0x300 + row * 2] = 0x80; // leftmost pixel
+            chip8.ram[0x300 + row * 2 + 1] = 0x01; // rightmost pixel
+        }
+        chip8.step().unwrap(); // hires
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // draw
+        for row in 0..16 {
+            assert_eq!(chip8.video_memory[row][0], 1);
+            assert_eq!(chip8.video_memory[row][1..15], [0; 14]);
+            assert_eq!(chip8.video_memory[row][15], 1);
+        }
+        assert_eq!(chip8.video_memory[16][0], 0);
+    }
+
+    #[test]
+    fn test_large_font() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::superchip(),
+        );
+
+        // 6A07  -- store 7 in vA
+        // FA30  -- point i to the large font character 7
+        chip8.load_from_memory(&[0x6A, 0x07, 0xFA, 0x30]);
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // large font
+        let i = chip8.i as usize;
+        assert_eq!(
+            chip8.ram[i..i + 10],
+            [0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18]
+        );
+    }
+
+    #[test]
+    fn test_rpl_flags() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::superchip(),
+        );
+
+        // 6011  -- store 11 in v0
+        // 6122  -- store 22 in v1
+        // F175  -- save v0..=v1 to the flags
+        // 6000  -- store 0 in v0
+        // 6100  -- store 0 in v1
+        // F085  -- load v0 from the flags
+        chip8.load_from_memory(&[
+            0x60, 0x11, 0x61, 0x22, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF0, 0x85,
+        ]);
+        for _ in 0..6 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.rpl_flags[0..3], [0x11, 0x22, 0x00]);
+        assert_eq!(chip8.v[0..2], [0x11, 0x00]);
+    }
+
+    #[test]
+    fn test_xochip_memory() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::XoChip,
+            Quirks::xochip(),
+        );
+        assert_eq!(chip8.ram.len(), 0x10000);
+
+        // F000  -- store the address that follows in i
+        // FFF0  -- the address
+        // F155  -- store v0..=v1 at i
+        chip8.load_from_memory(&[0xF0, 0x00, 0xFF, 0xF0, 0xF1, 0x55]);
+        chip8.step().unwrap(); // long store in i
+        assert_eq!(chip8.i, 0xFFF0);
+        assert_eq!(chip8.ca, 0x204);
+
+        chip8.step().unwrap(); // store
+        assert_eq!(chip8.i, 0xFFF2);
+
+        // F055  -- store v0 at i, the last byte of the memory
+        // F065  -- load v0 from i, which has wrapped around to 0
+        chip8.load_from_memory(&[0xF0, 0x55, 0xF0, 0x65]);
+        chip8.ca = 0x200;
+        chip8.i = 0xFFFF;
+        chip8.v[0] = 0x42;
+        chip8.step().unwrap(); // store
+        assert_eq!(chip8.ram[0xFFFF], 0x42);
+        assert_eq!(chip8.i, 0);
+        chip8.step().unwrap(); // load
+        assert_eq!(chip8.v[0], chip8.ram[0]);
+        assert_eq!(chip8.i, 1);
+    }
+
+    #[test]
+    fn test_skip_long_instruction() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::XoChip,
+            Quirks::xochip(),
+        );
+
+        // 3000  -- skip the next instruction if v0 is 0 (it is)
+        // F000  -- store the address that follows in i, skipped as a whole
+        // 1234  -- the address
+        // 3000  -- skip the next instruction if v0 is 0 (it is)
+        // 6001  -- store 1 in v0, skipped
+        // 0F00  -- we should end up here
+        chip8.load_from_memory(&[
+            0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x30, 0x00, 0x60, 0x01, 0x0F, 0x00,
+        ]);
+        chip8.step().unwrap(); // skip
+        assert_eq!(chip8.ca, 0x206);
+
+        chip8.step().unwrap(); // skip
+        assert_eq!(chip8.ca, 0x20A);
+        assert_eq!(chip8.i, 0);
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::XoChip,
+            Quirks::xochip(),
+        );
+
+        // F301  -- select both planes
+        // A20C  -- store the address of the sprites in i
+        // D001  -- draw 1 byte of the sprite to each plane at (v0, v0)
+        // F201  -- select the second plane
+        // 00E0  -- clear the second plane
+        // 0000  -- nothing here
+        // C0    -- sprite for the first plane: 0b1100_0000
+        // 60    -- sprite for the second plane: 0b0110_0000
+        chip8.load_from_memory(&[
+            0xF3, 0x01, 0xA2, 0x0C, 0xD0, 0x01, 0xF2, 0x01, 0x00, 0xE0, 0x00, 0x00, 0xC0, 0x60,
+        ]);
+        chip8.step().unwrap(); // select
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // draw
+        assert_eq!(chip8.video_memory[0][0..4], [1, 3, 2, 0]);
+        assert_eq!(chip8.v[0xF], 0);
+
+        chip8.step().unwrap(); // select
+        chip8.step().unwrap(); // clear
+        assert_eq!(chip8.video_memory[0][0..4], [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_register_range() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::XoChip,
+            Quirks::xochip(),
+        );
+
+        // 6211  -- store 11 in v2
+        // 6322  -- store 22 in v3
+        // 6433  -- store 33 in v4
+        // A300  -- store the address 300 in i
+        // 5242  -- save v2..=v4 at i
+        // 5A83  -- load vA down to v8 from i
+        chip8.load_from_memory(&[
+            0x62, 0x11, 0x63, 0x22, 0x64, 0x33, 0xA3, 0x00, 0x52, 0x42, 0x5A, 0x83,
+        ]);
+        for _ in 0..5 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.ram[0x300..0x303], [0x11, 0x22, 0x33]);
+        assert_eq!(chip8.i, 0x300); // unchanged
+
+        chip8.step().unwrap(); // load
+        assert_eq!(chip8.v[0x8..=0xA], [0x33, 0x22, 0x11]);
+    }
+
+    #[test]
+    fn test_audio_pattern() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::XoChip,
+            Quirks::xochip(),
+        );
+
+        // A300  -- store the address of the pattern in i
+        // F002  -- load the audio pattern from i
+        // 6070  -- store 112 in v0
+        // F03A  -- set the pitch to v0
+        chip8.load_from_memory(&[0xA3, 0x00, 0xF0, 0x02, 0x60, 0x70, 0xF0, 0x3A]);
+        chip8.ram[0x300..0x310].copy_from_slice(&[0xAA; 16]);
+        chip8.step().unwrap(); // store in i
+        chip8.step().unwrap(); // load the pattern
+        assert_eq!(chip8.audio_pattern, [0xAA; 16]);
+
+        chip8.step().unwrap(); // store
+        chip8.step().unwrap(); // pitch
+        assert_eq!(chip8.audio_pitch, 112);
+    }
+
+    #[test]
+    fn test_stack_errors() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 2200  -- call the subroutine at 0x200, which calls itself
+        chip8.load_from_memory(&[0x22, 0x00]);
+        for _ in 0..16 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::StackOverflow {
+                address: 0x200,
+                opcode: 0x2200
+            })
+        );
+        assert_eq!(chip8.sp, 16);
+
+        // 00EE  -- return with nothing on the stack
+        chip8.sp = 0;
+        chip8.load_from_memory(&[0x00, 0xEE]);
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::StackUnderflow {
+                address: 0x200,
+                opcode: 0x00EE
+            })
+        );
+    }
+
+    #[test]
+    fn test_memory_errors() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // AFFE  -- I = 0xFFE
+        // F255  -- store V0-V2 at I, the last byte doesn't fit
+        // 1204  -- jump to itself
+        chip8.load_from_memory(&[0xAF, 0xFE, 0xF2, 0x55, 0x12, 0x04]);
+        chip8.v[0] = 1;
+        chip8.step().unwrap();
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                address: 0x202,
+                opcode: 0xF255,
+                target: 0xFFE
+            })
+        );
+        assert_eq!(chip8.ram[0xFFE], 0);
+
+        chip8.ca = 0x204;
+        assert_eq!(
+            chip8.step(),
+            Err(Chip8Error::EndlessLoop {
+                address: 0x204,
+                opcode: 0x1204
+            })
+        );
+        assert_eq!(chip8.ca, 0x204);
+
+        // the error stops the frame before the timers tick
+        chip8.delay_timer = 5;
+        assert!(chip8.run_frame().is_err());
+        assert_eq!(chip8.delay_timer, 5);
+    }
+
+    #[test]
+    fn test_load_error() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        let error = chip8.load_from_file("no/such/rom.ch8").unwrap_err();
+        assert!(matches!(error, Chip8Error::Load { path, .. } if path == "no/such/rom.ch8"));
+    }
+
+    #[test]
+    fn test_skip_faults() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        chip8.set_fault_policy(FaultPolicy::all(FaultAction::SkipAndLog));
+
+        // 0123  -- machine code routine
+        // 00EE  -- return with nothing on the stack
+        // 1204  -- jump to itself
+        chip8.load_from_memory(&[0x01, 0x23, 0x00, 0xEE, 0x12, 0x04]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.ca, 0x206);
+        assert_eq!(chip8.sp, 0);
+        assert_eq!(
+            chip8.take_faults(),
+            vec![
+                Chip8Error::UnsupportedOpcode {
+                    address: 0x200,
+                    opcode: 0x0123
+                },
+                Chip8Error::StackUnderflow {
+                    address: 0x202,
+                    opcode: 0x00EE
+                },
+                Chip8Error::EndlessLoop {
+                    address: 0x204,
+                    opcode: 0x1204
+                },
+            ]
+        );
+        assert!(chip8.take_faults().is_empty());
+    }
+
+    #[test]
+    fn test_wrap_faults() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        chip8.set_fault_policy(FaultPolicy::lenient());
+
+        // AFFE  -- I = 0xFFE
+        // F255  -- store V0-V2 at I, the last byte goes to 0x000
+        // 00EE  -- return with nothing on the stack, takes the address from the top of it
+        // 1206  -- jump to itself
+        chip8.load_from_memory(&[0xAF, 0xFE, 0xF2, 0x55, 0x00, 0xEE, 0x12, 0x06]);
+        chip8.v[0] = 1;
+        chip8.v[1] = 2;
+        chip8.v[2] = 3;
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.ram[0xFFE], 1);
+        assert_eq!(chip8.ram[0xFFF], 2);
+        assert_eq!(chip8.ram[0x000], 3);
+
+        chip8.stack[15] = 0x204;
+        chip8.step().unwrap();
+        assert_eq!(chip8.ca, 0x206);
+        assert_eq!(chip8.sp, 15);
+
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.ca, 0x206);
+        assert!(chip8.take_faults().is_empty());
+    }
+
+    struct CountingFaultHandler {
+        faults: usize,
+    }
+
+    impl FaultHandler for CountingFaultHandler {
+        fn handle_fault(&mut self, _error: &Chip8Error) -> FaultAction {
+            self.faults += 1;
+            if self.faults == 1 {
+                FaultAction::SkipAndLog
+            } else {
+                FaultAction::Halt
+            }
+        }
+    }
+
+    #[test]
+    fn test_fault_handler() {
+        let mut handler = CountingFaultHandler { faults: 0 };
+        {
+            let mut display = DummyCHIP8Display::new();
+            let mut keypad = DummyCHIP8Keypad::new();
+            let mut audio = DummyCHIP8Audio::new();
+            let mut chip8 = CHIP8::new(
+                &mut display,
+                &mut keypad,
+                &mut audio,
+                Platform::Chip8,
+                Quirks::default(),
+            );
+            chip8.set_fault_policy(FaultPolicy {
+                illegal_opcode: FaultAction::Callback,
+                ..FaultPolicy::strict()
+            });
+            chip8.set_fault_handler(&mut handler);
+
+            // FFFF  -- illegal
+            // FFFF  -- illegal
+            chip8.load_from_memory(&[0xFF, 0xFF, 0xFF, 0xFF]);
+            chip8.step().unwrap();
+            assert!(chip8.step().is_err());
+            assert_eq!(chip8.ca, 0x202);
+        }
+        assert_eq!(handler.faults, 2);
+    }
+
+    #[test]
+    fn test_execution_reports() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 6005  -- store 5 in v0
+        // F018  -- set the sound timer to v0
+        // 00E0  -- clear the screen
+        // F10A  -- wait for a key
+        chip8.load_from_memory(&[0x60, 0x05, 0xF0, 0x18, 0x00, 0xE0, 0xF1, 0x0A]);
+        let report = chip8.step().unwrap();
+        assert_eq!(
+            report,
+            ExecutionReport {
+                instructions: 1,
+                ..ExecutionReport::default()
+            }
+        );
+
+        let report = chip8.run_cycles(2).unwrap();
+        assert_eq!(report.instructions, 2);
+        assert!(report.sound_changed);
+        assert!(report.drew);
+        assert!(!report.waiting_for_key);
+
+        let report = chip8.run_frame().unwrap();
+        assert_eq!(report.instructions, DEFAULT_INSTRUCTIONS_PER_FRAME);
+        assert!(report.waiting_for_key);
+        assert!(!report.drew);
+        assert!(!report.sound_changed);
+        assert_eq!(chip8.program_counter(), 0x206);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        // 7001  -- add 1 to v0
+        // 1200  -- jump back to 0x200
+        chip8.load_from_memory(&[0x70, 0x01, 0x12, 0x00]);
+        let report = chip8
+            .run_until(|chip8, _| chip8.registers()[0] == 10)
+            .unwrap();
+        assert_eq!(report.instructions, 19);
+        assert_eq!(chip8.program_counter(), 0x202);
+
+        // A206  -- I = 0x206
+        // D001  -- draw 1 byte sprite at (v0, v0)
+        // 00FD  -- exit, illegal on CHIP-8
+        // 80    -- sprite
+        chip8.load_from_memory(&[0xA2, 0x06, 0xD0, 0x01, 0x00, 0xFD, 0x80]);
+        chip8.ca = 0x200;
+        let report = chip8.run_until(|_, report| report.drew).unwrap();
+        assert_eq!(report.instructions, 2);
+        assert_eq!(chip8.video_memory()[10][10], 1);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        chip8.load_from_memory(&[
+            0x70, 0x01, // 200: v0 += 1
+            0x22, 0x06, // 202: call 206
+            0x12, 0x00, // 204: jump 200
+            0x71, 0x01, // 206: v1 += 1
+            0x00, 0xEE, // 208: return
+        ]);
+        chip8.add_breakpoint(0x206);
+        chip8.add_breakpoint(0x200);
+
+        // the machine starts at a breakpoint
+        let report = chip8.run_cycles(10).unwrap();
+        assert!(report.breakpoint);
+        assert_eq!((report.instructions, chip8.ca), (0, 0x200));
+
+        // and runs past it the next time
+        let report = chip8.run_frame().unwrap();
+        assert!(report.breakpoint);
+        assert_eq!((report.instructions, chip8.ca), (2, 0x206));
+        assert_eq!(chip8.stack(), &[0x202]);
+        assert_eq!(chip8.delay_timer, 0);
+
+        let report = chip8.run_until(|_, _| false).unwrap();
+        assert!(report.breakpoint);
+        assert_eq!((report.instructions, chip8.ca), (3, 0x200));
+
+        // stepping ignores the breakpoints
+        assert!(chip8.remove_breakpoint(0x200));
+        assert!(!chip8.remove_breakpoint(0x200));
+        assert!(!chip8.step().unwrap().breakpoint);
+        assert_eq!(chip8.breakpoints().iter().collect::<Vec<_>>(), vec![&0x206]);
+        chip8.memory_mut()[0x207] = 0x05;
+        chip8.run_cycles(10).unwrap();
+        chip8.step().unwrap();
+        assert_eq!((chip8.v[0], chip8.v[1]), (2, 6));
+    }
+
+    #[test]
+    fn test_run_until_exit() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::superchip(),
+        );
+
+        // 6001  -- store 1 in v0
+        // 00FD  -- exit
+        chip8.load_from_memory(&[0x60, 0x01, 0x00, 0xFD]);
+        let report = chip8.run_until(|_, _| false).unwrap();
+        assert_eq!(report.instructions, 2);
+        assert!(report.halted);
+        assert_eq!(chip8.run_cycles(5).unwrap().instructions, 0);
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = ScriptedCHIP8Keypad::new(&[1 << 4]);
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::cosmac_vip(),
+        );
+
+        chip8.load_from_memory(&[
+            0x6A, 0x05, // 200: vA := 5
+            0x22, 0x08, // 202: call 208
+            0xF3, 0x0A, // 204: v3 := key
+            0x12, 0x04, // 206: jump 204
+            0xC0, 0xFF, // 208: v0 := random FF
+            0xC1, 0xFF, // 20A: v1 := random FF
+            0x00, 0xEE, // 20C: return
+        ]);
+        chip8.run_cycles(2).unwrap(); // ld, call
+        let state = chip8.save_state();
+
+        // the random numbers repeat after restoring
+        chip8.run_cycles(3).unwrap(); // rand, rand, return
+        let registers = chip8.v;
+        assert_eq!(chip8.ca, 0x204);
+        chip8.restore_state(&state).unwrap();
+        assert_eq!((chip8.ca, chip8.sp, chip8.v[0xA]), (0x208, 1, 5));
+        chip8.run_cycles(3).unwrap(); // rand, rand, return
+        assert_eq!(chip8.v, registers);
+
+        // key 4 is held, the wait for its release goes to another machine through a file
+        chip8.step().unwrap(); // wait for key
+        let bytes = chip8.save_state().to_bytes();
+
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut other = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::superchip(),
+        );
+        let state = MachineState::from_bytes(&bytes).unwrap();
+        other.restore_state(&state).unwrap();
+        assert_eq!(
+            (other.platform, other.quirks),
+            (Platform::Chip8, Quirks::cosmac_vip())
+        );
+        assert_eq!(other.pressed_key, Some(4));
+        other.step().unwrap(); // the key is released
+        assert_eq!((other.v[3], other.ca), (4, 0x206));
+        assert_eq!(other.save_state().ram, chip8.save_state().ram);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+
+        chip8.load_from_memory(&[
+            0x70, 0x01, // 200: v0 += 1
+            0x12, 0x00, // 202: jump 200
+        ]);
+        chip8.set_instructions_per_frame(2);
+        assert_eq!(chip8.rewind(1), 0);
+
+        chip8.enable_rewind(1 << 20);
+        for _ in 0..10 {
+            chip8.run_frame().unwrap();
+        }
+        assert_eq!(chip8.v[0], 10);
+        assert_eq!(chip8.rewind(4), 4);
+        assert_eq!(chip8.v[0], 6);
+        // the frames after the rewind replace the ones rewound
+        chip8.run_frame().unwrap();
+        assert_eq!(chip8.rewind(20), 6);
+        assert_eq!(chip8.v[0], 1);
+    }
 }
@@ -0,0 +1,341 @@
+use std::io::{Error, Seek, SeekFrom, Write};
+#[cfg(feature = "sound")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "sound")]
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+#[cfg(feature = "sound")]
+use cpal::{FromSample, SizedSample};
+
+use crate::chip8::FRAME_RATE;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const DEFAULT_PITCH: f32 = 440.0; // Hz
+const DEFAULT_VOLUME: f32 = 0.25;
+const WAV_HEADER_SIZE: u32 = 44;
+
+pub const AUDIO_PATTERN_SIZE: usize = 16; // XO-CHIP plays 128 one bit samples in a loop
+
+pub trait CHIP8Audio {
+    // called whenever the sound timer becomes non-zero or reaches zero
+    fn set_tone(&mut self, on: bool);
+    // XO-CHIP replaces the buzzer with a pattern played at the given rate of bits per second
+    fn set_pattern(&mut self, _pattern: &[u8; AUDIO_PATTERN_SIZE], _rate: f32) {}
+    // called after every frame, that is after every 1/60 s of sound
+    fn end_frame(&mut self);
+}
+
+#[derive(Default)]
+pub struct DummyCHIP8Audio {}
+
+impl DummyCHIP8Audio {
+    pub fn new() -> DummyCHIP8Audio {
+        DummyCHIP8Audio {}
+    }
+}
+
+impl CHIP8Audio for DummyCHIP8Audio {
+    fn set_tone(&mut self, _on: bool) {}
+
+    fn end_frame(&mut self) {}
+}
+
+// the buzzer, a square wave of a single pitch,
+// or an XO-CHIP pattern once one has been set
+pub struct SquareWave {
+    sample_rate: u32,
+    pitch: f32,  // Hz
+    volume: f32, // from 0.0 to 1.0
+    phase: f32,  // position within the current period, from 0.0 to 1.0
+    pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+}
+
+impl SquareWave {
+    pub fn new(sample_rate: u32) -> SquareWave {
+        SquareWave {
+            sample_rate,
+            pitch: DEFAULT_PITCH,
+            volume: DEFAULT_VOLUME,
+            phase: 0.0,
+            pattern: None,
+        }
+    }
+
+    // the whole pattern is one period, so the pitch is the rate divided by the number of bits
+    pub fn set_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], rate: f32) {
+        self.pattern = Some(*pattern);
+        self.pitch = rate / (AUDIO_PATTERN_SIZE * 8) as f32;
+    }
+
+    pub fn set_pitch(&mut self, pitch: f32) {
+        self.pitch = pitch;
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // every wave starts at the beginning of a period when the tone is turned on
+    pub fn next_sample(&mut self, on: bool) -> i16 {
+        if !on {
+            self.phase = 0.0;
+            return 0;
+        }
+        let high = match self.pattern {
+            Some(pattern) => {
+                let bit = (self.phase * (AUDIO_PATTERN_SIZE * 8) as f32) as usize;
+                pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+            }
+            None => self.phase < 0.5,
+        };
+        let amplitude = if high { self.volume } else { -self.volume };
+        self.phase = (self.phase + self.pitch / self.sample_rate as f32).fract();
+        (amplitude * i16::MAX as f32) as i16
+    }
+}
+
+// writes the sound as 16 bit mono PCM WAV, one frame worth of samples per end_frame
+pub struct WavCHIP8Audio<W: Write + Seek> {
+    writer: W,
+    wave: SquareWave,
+    tone: bool,
+    samples_written: u32,
+    error: Option<Error>,
+}
+
+impl<W: Write + Seek> WavCHIP8Audio<W> {
+    pub fn new(mut writer: W, wave: SquareWave) -> Result<WavCHIP8Audio<W>, Error> {
+        // the sizes are not known yet, finish() fills them in
+        write_wav_header(&mut writer, wave.sample_rate(), 0)?;
+        Ok(WavCHIP8Audio {
+            writer,
+            wave,
+            tone: false,
+            samples_written: 0,
+            error: None,
+        })
+    }
+
+    // fixes up the header and returns the writer,
+    // reports the first error that happened while writing samples
+    pub fn finish(mut self) -> Result<W, Error> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.writer.seek(SeekFrom::Start(0))?;
+        write_wav_header(
+            &mut self.writer,
+            self.wave.sample_rate(),
+            self.samples_written,
+        )?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_frame(&mut self) -> Result<(), Error> {
+        // keeps the total in sync with the frame count when the rate is not a multiple of 60
+        let frames =
+            (self.samples_written as u64 * FRAME_RATE as u64) / self.wave.sample_rate() as u64 + 1;
+        let target = frames * self.wave.sample_rate() as u64 / FRAME_RATE as u64;
+        for _ in self.samples_written as u64..target {
+            let sample = self.wave.next_sample(self.tone);
+            self.writer.write_all(&sample.to_le_bytes())?;
+            self.samples_written += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> CHIP8Audio for WavCHIP8Audio<W> {
+    fn set_tone(&mut self, on: bool) {
+        self.tone = on;
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], rate: f32) {
+        self.wave.set_pattern(pattern, rate);
+    }
+
+    fn end_frame(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(error) = self.write_frame() {
+            self.error = Some(error);
+        }
+    }
+}
+
+// plays the sound on the default output device, built with the sound feature
+// the device pulls the samples from its own thread, so the wave is shared with it
+#[cfg(feature = "sound")]
+pub struct SpeakerCHIP8Audio {
+    speaker: Arc<Mutex<(SquareWave, bool)>>, // the wave and whether the tone is on
+    _stream: Option<cpal::Stream>,           // the sound stops when the stream is dropped
+}
+
+#[cfg(feature = "sound")]
+impl SpeakerCHIP8Audio {
+    pub fn open() -> Result<SpeakerCHIP8Audio, String> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no sound output device")?;
+        let config = device
+            .default_output_config()
+            .map_err(|error| error.to_string())?;
+        let speaker = Arc::new(Mutex::new((SquareWave::new(config.sample_rate().0), false)));
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => play::<f32>(&device, &config.config(), &speaker),
+            cpal::SampleFormat::I16 => play::<i16>(&device, &config.config(), &speaker),
+            cpal::SampleFormat::U16 => play::<u16>(&device, &config.config(), &speaker),
+            format => return Err(format!("unsupported sample format {}", format)),
+        }?;
+        Ok(SpeakerCHIP8Audio {
+            speaker,
+            _stream: Some(stream),
+        })
+    }
+
+    // for when there is no device to open, the sound goes nowhere
+    pub fn silent() -> SpeakerCHIP8Audio {
+        SpeakerCHIP8Audio {
+            speaker: Arc::new(Mutex::new((SquareWave::new(DEFAULT_SAMPLE_RATE), false))),
+            _stream: None,
+        }
+    }
+}
+
+// every channel gets the same sample
+#[cfg(feature = "sound")]
+fn play<T: SizedSample + FromSample<i16>>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    speaker: &Arc<Mutex<(SquareWave, bool)>>,
+) -> Result<cpal::Stream, String> {
+    let speaker = Arc::clone(speaker);
+    let channels = config.channels as usize;
+    let stream = device
+        .build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let (wave, tone) = &mut *speaker.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    frame.fill(T::from_sample(wave.next_sample(*tone)));
+                }
+            },
+            |error| eprintln!("sound output: {}", error),
+            None,
+        )
+        .map_err(|error| error.to_string())?;
+    stream.play().map_err(|error| error.to_string())?;
+    Ok(stream)
+}
+
+#[cfg(feature = "sound")]
+impl CHIP8Audio for SpeakerCHIP8Audio {
+    fn set_tone(&mut self, on: bool) {
+        self.speaker.lock().unwrap().1 = on;
+    }
+
+    fn set_pattern(&mut self, pattern: &[u8; AUDIO_PATTERN_SIZE], rate: f32) {
+        self.speaker.lock().unwrap().0.set_pattern(pattern, rate);
+    }
+
+    // the device keeps its own time
+    fn end_frame(&mut self) {}
+}
+
+fn write_wav_header<W: Write>(writer: &mut W, sample_rate: u32, samples: u32) -> Result<(), Error> {
+    let data_size = samples * 2;
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?; // size of the fmt chunk
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // mono
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * 2).to_le_bytes())?; // bytes per second
+    writer.write_all(&2u16.to_le_bytes())?; // bytes per sample
+    writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_square_wave() {
+        let mut wave = SquareWave::new(8);
+        wave.set_pitch(2.0);
+        wave.set_volume(1.0);
+
+        // four samples per period, half of them high
+        let samples: Vec<i16> = (0..8).map(|_| wave.next_sample(true)).collect();
+        assert_eq!(
+            samples,
+            [
+                i16::MAX,
+                i16::MAX,
+                -i16::MAX,
+                -i16::MAX,
+                i16::MAX,
+                i16::MAX,
+                -i16::MAX,
+                -i16::MAX
+            ]
+        );
+        assert_eq!(wave.next_sample(false), 0);
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut wave = SquareWave::new(128);
+        wave.set_volume(1.0);
+        // one bit per sample
+        let mut pattern = [0; AUDIO_PATTERN_SIZE];
+        pattern[0] = 0b1010_0000;
+        wave.set_pattern(&pattern, 128.0);
+
+        let samples: Vec<i16> = (0..5).map(|_| wave.next_sample(true)).collect();
+        assert_eq!(
+            samples,
+            [i16::MAX, -i16::MAX, i16::MAX, -i16::MAX, -i16::MAX]
+        );
+    }
+
+    #[test]
+    fn test_wav_output() {
+        let mut audio = WavCHIP8Audio::new(
+            Cursor::new(Vec::new()),
+            SquareWave::new(DEFAULT_SAMPLE_RATE),
+        )
+        .unwrap();
+        audio.end_frame(); // silent frame
+        audio.set_tone(true);
+        audio.end_frame(); // beeping frame
+        let wav = audio.finish().unwrap().into_inner();
+
+        let samples_per_frame = (DEFAULT_SAMPLE_RATE / FRAME_RATE) as usize;
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[8..12], b"WAVE");
+        assert_eq!(
+            wav.len(),
+            WAV_HEADER_SIZE as usize + 2 * 2 * samples_per_frame
+        );
+        let data_size = u32::from_le_bytes(wav[40..44].try_into().unwrap());
+        assert_eq!(data_size as usize, 2 * 2 * samples_per_frame);
+
+        let (silent, beeping) = wav[WAV_HEADER_SIZE as usize..].split_at(2 * samples_per_frame);
+        assert!(silent.iter().all(|byte| *byte == 0));
+        assert!(beeping.iter().any(|byte| *byte != 0));
+    }
+}
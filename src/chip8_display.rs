@@ -1,25 +1,142 @@
+use std::ops::{Index, IndexMut};
+
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128; // SUPER-CHIP high resolution mode
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+
+pub const ALL_PLANES: u8 = 0b11;
+
+// one byte per pixel, bit N is set when the pixel is on in the plane N,
+// only XO-CHIP has the second plane, so everything else uses just 0 and 1
+// the buffer always fits the high resolution, in low resolution only the top left part is used
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VideoMemory {
+    hires: bool,
+    pixels: [[u8; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT],
+}
+
+impl VideoMemory {
+    pub fn new() -> VideoMemory {
+        VideoMemory {
+            hires: false,
+            pixels: [[0; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // switching the resolution clears the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels = [[0; HIRES_SCREEN_WIDTH]; HIRES_SCREEN_HEIGHT];
+    }
+
+    // turns off the pixels of the given planes only
+    pub fn clear_planes(&mut self, planes: u8) {
+        for row in self.pixels.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !planes;
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, rows: usize, planes: u8) {
+        self.scroll(0, rows as isize, planes);
+    }
+
+    pub fn scroll_up(&mut self, rows: usize, planes: u8) {
+        self.scroll(0, -(rows as isize), planes);
+    }
+
+    pub fn scroll_right(&mut self, columns: usize, planes: u8) {
+        self.scroll(columns as isize, 0, planes);
+    }
+
+    pub fn scroll_left(&mut self, columns: usize, planes: u8) {
+        self.scroll(-(columns as isize), 0, planes);
+    }
+
+    // moves the pixels of the given planes, the pixels that come in from the edges are off
+    fn scroll(&mut self, dx: isize, dy: isize, planes: u8) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let source = self.pixels;
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&source_x) && (0..height).contains(&source_y) {
+                    source[source_y as usize][source_x as usize] & planes
+                } else {
+                    0
+                };
+                let pixel = &mut self.pixels[y as usize][x as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+    }
+}
+
+// video_memory[y][x] is the pixel in the column x of the row y
+impl Default for VideoMemory {
+    fn default() -> VideoMemory {
+        VideoMemory::new()
+    }
+}
+
+impl Index<usize> for VideoMemory {
+    type Output = [u8];
+
+    fn index(&self, y: usize) -> &[u8] {
+        &self.pixels[..self.height()][y][..self.width()]
+    }
+}
+
+impl IndexMut<usize> for VideoMemory {
+    fn index_mut(&mut self, y: usize) -> &mut [u8] {
+        let (width, height) = (self.width(), self.height());
+        &mut self.pixels[..height][y][..width]
+    }
+}
+
 pub trait CHIP8Display {
     fn clear(&mut self);
-    fn update(&mut self, vide_memory: &[[u8; 4]; 8]);
+    fn update(&mut self, video_memory: &VideoMemory);
 }
 
-#[allow(dead_code)]
-pub struct DummyCHIP8Display {
-    x: i8,
-}
+// for running without a window, the debuggers show the screen in their own way
+#[derive(Default)]
+pub struct DummyCHIP8Display {}
 
 impl DummyCHIP8Display {
     pub fn new() -> DummyCHIP8Display {
-        DummyCHIP8Display { x: 0 }
+        DummyCHIP8Display {}
     }
 }
 
 impl CHIP8Display for DummyCHIP8Display {
-    fn clear(&mut self) {
-        println!("Clear screen");
-    }
+    fn clear(&mut self) {}
 
-    fn update(&mut self, _vide_memory: &[[u8; 4]; 8]) {
-        println!("Update screen");
-    }
+    fn update(&mut self, _video_memory: &VideoMemory) {}
 }
@@ -0,0 +1,93 @@
+use std::fmt;
+
+// address is where the failing instruction is, opcode is the instruction itself
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Chip8Error {
+    Load {
+        path: String,
+        message: String,
+    },
+    IllegalOpcode {
+        address: usize,
+        opcode: u16,
+    },
+    UnsupportedOpcode {
+        address: usize,
+        opcode: u16,
+    }, // 0NNN machine code routines
+    StackOverflow {
+        address: usize,
+        opcode: u16,
+    },
+    StackUnderflow {
+        address: usize,
+        opcode: u16,
+    },
+    MemoryOutOfBounds {
+        address: usize,
+        opcode: u16,
+        target: usize,
+    },
+    JumpOutOfMemory {
+        address: usize,
+        opcode: u16,
+        target: usize,
+    },
+    EndlessLoop {
+        address: usize,
+        opcode: u16,
+    },
+    ProgramCounterOutOfMemory {
+        address: usize,
+    }, // there is no whole instruction to fetch
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::Load { path, message } => write!(f, "Can't load {}: {}", path, message),
+            Chip8Error::IllegalOpcode { address, opcode } => {
+                write!(f, "Illegal opcode {:04X} at {:03X}", opcode, address)
+            }
+            Chip8Error::UnsupportedOpcode { address, opcode } => {
+                write!(f, "Unsupported opcode {:04X} at {:03X}", opcode, address)
+            }
+            Chip8Error::StackOverflow { address, opcode } => {
+                write!(f, "Stack overflow by {:04X} at {:03X}", opcode, address)
+            }
+            Chip8Error::StackUnderflow { address, opcode } => {
+                write!(f, "Stack underflow by {:04X} at {:03X}", opcode, address)
+            }
+            Chip8Error::MemoryOutOfBounds {
+                address,
+                opcode,
+                target,
+            } => write!(
+                f,
+                "Memory access outside of the memory at {:03X} by {:04X} at {:03X}",
+                target, opcode, address
+            ),
+            Chip8Error::JumpOutOfMemory {
+                address,
+                opcode,
+                target,
+            } => write!(
+                f,
+                "Jump outside of the memory to {:03X} by {:04X} at {:03X}",
+                target, opcode, address
+            ),
+            Chip8Error::EndlessLoop { address, opcode } => {
+                write!(f, "Endless loop {:04X} at {:03X}", opcode, address)
+            }
+            Chip8Error::ProgramCounterOutOfMemory { address } => {
+                write!(
+                    f,
+                    "Program counter {:03X} is outside of the memory",
+                    address
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
@@ -0,0 +1,76 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+pub trait CHIP8Keypad {
+    // state of the 16 hex keys, bit N is set while the key N is held down
+    fn pressed_keys(&mut self) -> u16;
+}
+
+#[derive(Default)]
+pub struct DummyCHIP8Keypad {}
+
+impl DummyCHIP8Keypad {
+    pub fn new() -> DummyCHIP8Keypad {
+        DummyCHIP8Keypad {}
+    }
+}
+
+impl CHIP8Keypad for DummyCHIP8Keypad {
+    fn pressed_keys(&mut self) -> u16 {
+        0
+    }
+}
+
+// replays a predefined sequence of key states, one state per poll,
+// the last state is repeated once the script runs out
+pub struct ScriptedCHIP8Keypad {
+    script: Vec<u16>,
+    position: usize,
+}
+
+impl ScriptedCHIP8Keypad {
+    pub fn new(script: &[u16]) -> ScriptedCHIP8Keypad {
+        ScriptedCHIP8Keypad {
+            script: script.to_vec(),
+            position: 0,
+        }
+    }
+}
+
+impl CHIP8Keypad for ScriptedCHIP8Keypad {
+    fn pressed_keys(&mut self) -> u16 {
+        if self.script.is_empty() {
+            return 0;
+        }
+        let keys = self.script[self.position];
+        if self.position + 1 < self.script.len() {
+            self.position += 1;
+        }
+        keys
+    }
+}
+
+// the keys held down are set from outside the machine, e.g. by a key schedule once a frame,
+// the clones share the keys
+#[derive(Clone, Default)]
+pub struct SharedCHIP8Keypad {
+    keys: Rc<Cell<u16>>,
+}
+
+impl SharedCHIP8Keypad {
+    pub fn new() -> SharedCHIP8Keypad {
+        SharedCHIP8Keypad {
+            keys: Rc::new(Cell::new(0)),
+        }
+    }
+
+    pub fn set_keys(&self, keys: u16) {
+        self.keys.set(keys);
+    }
+}
+
+impl CHIP8Keypad for SharedCHIP8Keypad {
+    fn pressed_keys(&mut self) -> u16 {
+        self.keys.get()
+    }
+}
@@ -0,0 +1,793 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, ErrorKind, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::assembler::{assemble_with_source_map, SourceMap};
+use crate::chip8::{CHIP8, FRAME_RATE};
+use crate::chip8_audio::DummyCHIP8Audio;
+use crate::chip8_display::DummyCHIP8Display;
+use crate::chip8_keypad::DummyCHIP8Keypad;
+use crate::fault_policy::FaultPolicy;
+use crate::frame_clock::FrameClock;
+use crate::instruction::{decode, Instruction};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+
+const THREAD_ID: u64 = 1; // the machine is the only thread
+const REGISTERS_REFERENCE: u64 = 1;
+const MEMORY_REFERENCE: u64 = 2;
+const BYTES_PER_MEMORY_ROW: usize = 16;
+const PROGRAM_ADDRESS: usize = 0x200;
+
+// a DAP message is a JSON object after a Content-Length header
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let Some(length) = length else {
+        return Err(io::Error::new(ErrorKind::InvalidData, "No Content-Length"));
+    };
+    let mut content = vec![0; length];
+    reader.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()
+}
+
+// what the machine is doing between the requests
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum RunMode {
+    Continue,
+    // runs until the call at the depth returns, for next and stepOut
+    Return { address: usize, depth: usize },
+}
+
+// the program of launch and attach, an Octo source is assembled and debugged in its source
+struct Program {
+    rom: Vec<u8>,
+    source: Option<(String, SourceMap)>,
+    platform: Platform,
+    quirks: Quirks,
+    fault_policy: FaultPolicy,
+    stop_on_entry: bool,
+}
+
+// serves one editor session over the Debug Adapter Protocol,
+// the machine runs without a window and at the real speed, so the timers work
+pub struct DapServer<W: Write> {
+    output: W,
+    seq: u64,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(output: W) -> DapServer<W> {
+        DapServer { output, seq: 1 }
+    }
+
+    // returns when the editor disconnects
+    pub fn serve<R: BufRead + Send + 'static>(&mut self, input: R) -> io::Result<()> {
+        let requests = read_requests(input);
+        let program = loop {
+            let Ok(request) = requests.recv() else {
+                return Ok(());
+            };
+            match command(&request) {
+                "initialize" => self.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsInstructionBreakpoints": true,
+                    }),
+                )?,
+                "launch" | "attach" => match load_program(&request) {
+                    Ok(program) => {
+                        self.respond(&request, json!({}))?;
+                        break program;
+                    }
+                    Err(message) => self.fail(&request, &message)?,
+                },
+                "disconnect" | "terminate" => return self.respond(&request, json!({})),
+                _ => self.fail(&request, "Launch or attach first")?,
+            }
+        };
+
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            program.platform,
+            program.quirks,
+        );
+        chip.set_fault_policy(program.fault_policy);
+        chip.load_from_memory(&program.rom);
+        let mut session = Session {
+            server: self,
+            chip,
+            source: program.source,
+            stop_on_entry: program.stop_on_entry,
+            source_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            run_mode: None,
+            clock: FrameClock::new(FRAME_RATE),
+        };
+        // the configuration requests come after this, then configurationDone starts the program
+        session.server.event("initialized", json!({}))?;
+        session.run(&requests)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        write_message(&mut self.output, &message)
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }))
+    }
+}
+
+// the requests are read on their own thread, so they can be checked between the frames
+fn read_requests<R: BufRead + Send + 'static>(mut input: R) -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if message["type"] == "request" && sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn command(request: &Value) -> &str {
+    request["command"].as_str().unwrap_or_default()
+}
+
+fn load_program(request: &Value) -> Result<Program, String> {
+    let arguments = &request["arguments"];
+    let Some(path) = arguments["program"].as_str() else {
+        return Err("The program to debug is missing".to_string());
+    };
+    let platform = match arguments["platform"].as_str() {
+        Some(name) => {
+            Platform::from_name(name).ok_or_else(|| format!("Unknown platform {}", name))?
+        }
+        None => Platform::Chip8,
+    };
+    let quirks = match arguments["quirks"].as_str() {
+        Some(name) => Quirks::from_name(name).ok_or_else(|| format!("Unknown quirks {}", name))?,
+        None => platform.default_quirks(),
+    };
+    let fault_policy = match arguments["faults"].as_str() {
+        Some(name) => {
+            FaultPolicy::from_name(name).ok_or_else(|| format!("Unknown faults {}", name))?
+        }
+        None => FaultPolicy::default(),
+    };
+    let (rom, source) = if path.ends_with(".8o") {
+        let text = std::fs::read_to_string(path)
+            .map_err(|error| format!("Can't load {}: {}", path, error))?;
+        let (rom, source_map) =
+            assemble_with_source_map(&text).map_err(|error| format!("{}:{}", path, error))?;
+        (rom, Some((path.to_string(), source_map)))
+    } else {
+        let rom = std::fs::read(path).map_err(|error| format!("Can't load {}: {}", path, error))?;
+        (rom, None)
+    };
+    if rom.len() > platform.memory_size() - PROGRAM_ADDRESS {
+        return Err(format!("{} doesn't fit into the memory", path));
+    }
+    Ok(Program {
+        rom,
+        source,
+        platform,
+        quirks,
+        fault_policy,
+        stop_on_entry: request["command"] == "attach"
+            || arguments["stopOnEntry"].as_bool().unwrap_or(false),
+    })
+}
+
+fn same_file(path: &str, other: &str) -> bool {
+    path == other
+        || match (
+            Path::new(path).canonicalize(),
+            Path::new(other).canonicalize(),
+        ) {
+            (Ok(path), Ok(other)) => path == other,
+            _ => false,
+        }
+}
+
+struct Session<'s, 'a, W: Write> {
+    server: &'s mut DapServer<W>,
+    chip: CHIP8<'a>,
+    source: Option<(String, SourceMap)>,
+    stop_on_entry: bool,
+    source_breakpoints: BTreeSet<usize>,
+    instruction_breakpoints: BTreeSet<usize>,
+    run_mode: Option<RunMode>,
+    clock: FrameClock,
+}
+
+impl<W: Write> Session<'_, '_, W> {
+    fn run(&mut self, requests: &Receiver<Value>) -> io::Result<()> {
+        let frame_duration = Duration::from_secs(1) / FRAME_RATE;
+        loop {
+            let request = if self.run_mode.is_some() {
+                for _ in 0..self.clock.frames_due() {
+                    if self.run_mode.is_none() {
+                        break;
+                    }
+                    self.run_frame()?;
+                }
+                match requests.recv_timeout(frame_duration) {
+                    Ok(request) => request,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                }
+            };
+            if !self.handle(&request)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // returns false when the session is over
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let arguments = &request["arguments"];
+        match command(request) {
+            "configurationDone" => {
+                self.server.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    self.stop("entry", None)?;
+                } else {
+                    self.resume(RunMode::Continue);
+                }
+            }
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(arguments);
+                self.server.respond(request, body)?;
+            }
+            "setInstructionBreakpoints" => {
+                let body = self.set_instruction_breakpoints(arguments);
+                self.server.respond(request, body)?;
+            }
+            "threads" => self.server.respond(
+                request,
+                json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] }),
+            )?,
+            "stackTrace" => {
+                let frames = self.stack_frames();
+                let total = frames.len();
+                self.server.respond(
+                    request,
+                    json!({ "stackFrames": frames, "totalFrames": total }),
+                )?;
+            }
+            "scopes" => self.server.respond(
+                request,
+                json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                    { "name": "Memory", "variablesReference": MEMORY_REFERENCE, "expensive": true },
+                ] }),
+            )?,
+            "variables" => {
+                let variables = match arguments["variablesReference"].as_u64() {
+                    Some(REGISTERS_REFERENCE) => self.registers(),
+                    Some(MEMORY_REFERENCE) => self.memory(),
+                    _ => Vec::new(),
+                };
+                self.server
+                    .respond(request, json!({ "variables": variables }))?;
+            }
+            "continue" => {
+                self.server
+                    .respond(request, json!({ "allThreadsContinued": true }))?;
+                self.resume(RunMode::Continue);
+            }
+            "next" => {
+                self.server.respond(request, json!({}))?;
+                self.step_over()?;
+            }
+            "stepIn" => {
+                self.server.respond(request, json!({}))?;
+                self.step()?;
+            }
+            "stepOut" => {
+                self.server.respond(request, json!({}))?;
+                match self.chip.stack().last() {
+                    Some(&call) => self.resume(RunMode::Return {
+                        address: call + 2,
+                        depth: self.chip.stack().len() - 1,
+                    }),
+                    None => self.resume(RunMode::Continue),
+                }
+            }
+            "pause" => {
+                self.server.respond(request, json!({}))?;
+                if self.run_mode.is_some() {
+                    self.stop("pause", None)?;
+                }
+            }
+            "disconnect" | "terminate" => {
+                self.server.respond(request, json!({}))?;
+                return Ok(false);
+            }
+            _ => self.server.fail(request, "Unsupported request")?,
+        }
+        Ok(true)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Value {
+        let path = arguments["source"]["path"].as_str().unwrap_or_default();
+        let source_map = match &self.source {
+            Some((source_path, source_map)) if same_file(source_path, path) => Some(source_map),
+            _ => None,
+        };
+        let mut addresses = BTreeSet::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+            let resolved = source_map.and_then(|source_map| source_map.address_of(line));
+            breakpoints.push(match (source_map, resolved) {
+                (_, Some((address, line))) => {
+                    addresses.insert(address);
+                    json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": format!("0x{:03X}", address),
+                    })
+                }
+                (Some(_), None) => {
+                    json!({ "verified": false, "message": "No code at or after this line" })
+                }
+                (None, None) => {
+                    json!({ "verified": false, "message": "Not the source of the program" })
+                }
+            });
+        }
+        self.source_breakpoints = addresses;
+        self.update_breakpoints();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    // for ROMs without a source, the references are addresses like 0x206
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Value {
+        let mut addresses = BTreeSet::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let reference = breakpoint["instructionReference"]
+                .as_str()
+                .unwrap_or_default();
+            let offset = breakpoint["offset"].as_i64().unwrap_or_default();
+            let address = usize::from_str_radix(reference.trim_start_matches("0x"), 16)
+                .ok()
+                .map(|address| address as i64 + offset)
+                .filter(|&address| (0..self.chip.memory().len() as i64).contains(&address));
+            breakpoints.push(match address {
+                Some(address) => {
+                    addresses.insert(address as usize);
+                    json!({ "verified": true, "instructionReference": format!("0x{:03X}", address) })
+                }
+                None => json!({ "verified": false, "message": "Not an address of the memory" }),
+            });
+        }
+        self.instruction_breakpoints = addresses;
+        self.update_breakpoints();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    // the machine has the breakpoints of both kinds and the return address of a step
+    fn update_breakpoints(&mut self) {
+        let old = self.chip.breakpoints().clone();
+        for address in old {
+            self.chip.remove_breakpoint(address);
+        }
+        let mut addresses = &self.source_breakpoints | &self.instruction_breakpoints;
+        if let Some(RunMode::Return { address, .. }) = self.run_mode {
+            addresses.insert(address);
+        }
+        for address in addresses {
+            self.chip.add_breakpoint(address);
+        }
+    }
+
+    fn is_user_breakpoint(&self, address: usize) -> bool {
+        self.source_breakpoints.contains(&address)
+            || self.instruction_breakpoints.contains(&address)
+    }
+
+    fn resume(&mut self, run_mode: RunMode) {
+        self.run_mode = Some(run_mode);
+        self.update_breakpoints();
+        self.clock = FrameClock::new(FRAME_RATE);
+    }
+
+    fn stop(&mut self, reason: &str, description: Option<String>) -> io::Result<()> {
+        self.run_mode = None;
+        self.update_breakpoints();
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        self.server.event("stopped", body)
+    }
+
+    fn terminate(&mut self) -> io::Result<()> {
+        self.run_mode = None;
+        self.server.event("exited", json!({ "exitCode": 0 }))?;
+        self.server.event("terminated", json!({}))
+    }
+
+    fn run_frame(&mut self) -> io::Result<()> {
+        let report = match self.chip.run_frame() {
+            Ok(report) => report,
+            Err(error) => return self.stop("exception", Some(error.to_string())),
+        };
+        if report.halted {
+            return self.terminate();
+        }
+        if !report.breakpoint {
+            return Ok(());
+        }
+        let ca = self.chip.program_counter();
+        match self.run_mode {
+            Some(RunMode::Return { address, depth }) if ca == address => {
+                if self.chip.stack().len() == depth {
+                    self.stop("step", None)
+                } else if self.is_user_breakpoint(ca) {
+                    self.stop("breakpoint", None)
+                } else {
+                    // a deeper call of the same routine returns there, the next frame runs past it
+                    Ok(())
+                }
+            }
+            _ => self.stop("breakpoint", None),
+        }
+    }
+
+    fn step(&mut self) -> io::Result<()> {
+        match self.chip.step() {
+            Err(error) => self.stop("exception", Some(error.to_string())),
+            Ok(report) if report.halted => self.terminate(),
+            Ok(_) => self.stop("step", None),
+        }
+    }
+
+    // a call runs until it returns, anything else is a step
+    fn step_over(&mut self) -> io::Result<()> {
+        let ca = self.chip.program_counter();
+        if !matches!(self.instruction_at(ca), Some(Instruction::Call { .. })) {
+            return self.step();
+        }
+        if let Err(error) = self.chip.step() {
+            return self.stop("exception", Some(error.to_string()));
+        }
+        self.resume(RunMode::Return {
+            address: ca + 2,
+            depth: self.chip.stack().len() - 1,
+        });
+        Ok(())
+    }
+
+    fn instruction_at(&self, address: usize) -> Option<Instruction> {
+        let memory = self.chip.memory();
+        if address + 1 >= memory.len() {
+            return None;
+        }
+        Some(decode(
+            (memory[address] as u16) << 8 | memory[address + 1] as u16,
+        ))
+    }
+
+    // the current instruction and then the calls in progress, the innermost first,
+    // a frame is named after the routine it's in, the target of the call that got there
+    fn stack_frames(&self) -> Vec<Value> {
+        let stack = self.chip.stack();
+        let mut routines = vec!["main".to_string()];
+        for &call in stack {
+            routines.push(match self.instruction_at(call) {
+                Some(Instruction::Call { address }) => format!("sub_{:03X}", address),
+                _ => "unknown".to_string(),
+            });
+        }
+        let mut addresses = vec![self.chip.program_counter()];
+        addresses.extend(stack.iter().rev());
+        addresses
+            .iter()
+            .enumerate()
+            .map(|(id, &address)| self.stack_frame(id, address, &routines[stack.len() - id]))
+            .collect()
+    }
+
+    fn stack_frame(&self, id: usize, address: usize, routine: &str) -> Value {
+        let reference = format!("0x{:03X}", address);
+        let mut frame = json!({
+            "id": id,
+            "name": format!("{} ({:03X})", routine, address),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": reference,
+        });
+        if let Some((path, source_map)) = &self.source {
+            if let Some(line) = source_map.line_of(address) {
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+                frame["source"] = json!({ "path": path });
+            }
+        }
+        frame
+    }
+
+    fn registers(&self) -> Vec<Value> {
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        let mut variables: Vec<Value> = self
+            .chip
+            .registers()
+            .iter()
+            .enumerate()
+            .map(|(x, value)| variable(format!("V{:X}", x), format!("0x{:02X}", value)))
+            .collect();
+        variables.push(variable(
+            "I".to_string(),
+            format!("0x{:04X}", self.chip.index_register()),
+        ));
+        variables.push(variable(
+            "PC".to_string(),
+            format!("0x{:03X}", self.chip.program_counter()),
+        ));
+        variables.push(variable(
+            "SP".to_string(),
+            self.chip.stack().len().to_string(),
+        ));
+        variables.push(variable(
+            "DT".to_string(),
+            self.chip.delay_timer().to_string(),
+        ));
+        variables.push(variable(
+            "ST".to_string(),
+            self.chip.sound_timer().to_string(),
+        ));
+        variables
+    }
+
+    // a row of bytes per variable
+    fn memory(&self) -> Vec<Value> {
+        self.chip
+            .memory()
+            .chunks(BYTES_PER_MEMORY_ROW)
+            .enumerate()
+            .map(|(row, bytes)| {
+                let value = bytes
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                json!({
+                    "name": format!("0x{:04X}", row * BYTES_PER_MEMORY_ROW),
+                    "value": value,
+                    "variablesReference": 0,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, PipeReader, PipeWriter};
+
+    // a scripted editor
+    struct Client {
+        requests: PipeWriter,
+        messages: BufReader<PipeReader>,
+        seq: u64,
+    }
+
+    impl Client {
+        fn send(&mut self, command: &str, arguments: Value) -> u64 {
+            self.seq += 1;
+            let request = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            write_message(&mut self.requests, &request).unwrap();
+            self.seq
+        }
+
+        fn receive(&mut self) -> Value {
+            read_message(&mut self.messages).unwrap().unwrap()
+        }
+
+        // sends the request and returns the body of the successful response
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            let seq = self.send(command, arguments);
+            let response = self.receive();
+            assert_eq!(response["type"], "response");
+            assert_eq!(response["request_seq"], seq);
+            assert_eq!(response["success"], true, "{}", response);
+            response["body"].clone()
+        }
+
+        fn event(&mut self, event: &str) -> Value {
+            let message = self.receive();
+            assert_eq!(message["type"], "event");
+            assert_eq!(message["event"], event, "{}", message);
+            message["body"].clone()
+        }
+
+        fn stopped(&mut self) -> (String, u64) {
+            let stopped = self.event("stopped");
+            let frames = self.request("stackTrace", json!({ "threadId": THREAD_ID }));
+            (
+                stopped["reason"].as_str().unwrap().to_string(),
+                frames["stackFrames"][0]["line"].as_u64().unwrap(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let source = [
+            ": main",
+            "  v0 := 1", // 200
+            "  add-one", // 202
+            "  add-one", // 204
+            "  loop",
+            "    v1 += 1", // 206
+            "  again",     // 208
+            ": add-one",
+            "  v0 += 1", // 20A
+            "  return",  // 20C
+        ]
+        .join("\n");
+        let path = std::env::temp_dir().join(format!("dap_test_{}.8o", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let (input, requests) = std::io::pipe().unwrap();
+        let (messages, output) = std::io::pipe().unwrap();
+        let server = thread::spawn(move || {
+            DapServer::new(output).serve(BufReader::new(input)).unwrap();
+        });
+        let mut editor = Client {
+            requests,
+            messages: BufReader::new(messages),
+            seq: 0,
+        };
+
+        let capabilities = editor.request("initialize", json!({ "adapterID": "chip8" }));
+        assert_eq!(capabilities["supportsConfigurationDoneRequest"], true);
+        editor.request("launch", json!({ "program": path, "stopOnEntry": true }));
+        editor.event("initialized");
+        let breakpoints = editor.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 8 }, { "line": 20 }] }),
+        );
+        assert_eq!(breakpoints["breakpoints"][0]["verified"], true);
+        assert_eq!(breakpoints["breakpoints"][0]["line"], 9);
+        assert_eq!(breakpoints["breakpoints"][1]["verified"], false);
+        editor.request("configurationDone", json!({}));
+        assert_eq!(editor.stopped(), ("entry".to_string(), 2));
+
+        let threads = editor.request("threads", json!({}));
+        assert_eq!(threads["threads"][0]["id"], THREAD_ID);
+
+        editor.request("continue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(editor.stopped(), ("breakpoint".to_string(), 9));
+        let frames = editor.request("stackTrace", json!({ "threadId": THREAD_ID }));
+        assert_eq!(frames["totalFrames"], 2);
+        assert_eq!(frames["stackFrames"][0]["name"], "sub_20A (20A)");
+        assert_eq!(frames["stackFrames"][1]["name"], "main (202)");
+        assert_eq!(frames["stackFrames"][1]["line"], 3);
+        assert_eq!(frames["stackFrames"][1]["source"]["path"], path.as_str());
+
+        editor.request("next", json!({ "threadId": THREAD_ID }));
+        assert_eq!(editor.stopped(), ("step".to_string(), 10));
+        editor.request("stepOut", json!({ "threadId": THREAD_ID }));
+        assert_eq!(editor.stopped(), ("step".to_string(), 4));
+        // the breakpoint in the called routine stops stepping over the call
+        editor.request("next", json!({ "threadId": THREAD_ID }));
+        assert_eq!(editor.stopped(), ("breakpoint".to_string(), 9));
+
+        let scopes = editor.request("scopes", json!({ "frameId": 0 }));
+        assert_eq!(scopes["scopes"][0]["name"], "Registers");
+        let registers = editor.request(
+            "variables",
+            json!({ "variablesReference": scopes["scopes"][0]["variablesReference"] }),
+        );
+        assert_eq!(registers["variables"][0]["name"], "V0");
+        assert_eq!(registers["variables"][0]["value"], "0x02");
+        assert_eq!(registers["variables"][17]["value"], "0x20A");
+        let memory = editor.request(
+            "variables",
+            json!({ "variablesReference": scopes["scopes"][1]["variablesReference"] }),
+        );
+        assert_eq!(memory["variables"][0x20]["name"], "0x0200");
+        assert!(memory["variables"][0x20]["value"]
+            .as_str()
+            .unwrap()
+            .starts_with("60 01 22 0A"));
+
+        // the program runs until the editor pauses it
+        editor.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [] }),
+        );
+        editor.request("continue", json!({ "threadId": THREAD_ID }));
+        editor.request("pause", json!({ "threadId": THREAD_ID }));
+        assert_eq!(editor.stopped().0, "pause");
+
+        editor.request("disconnect", json!({}));
+        server.join().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,543 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::chip8::CHIP8;
+use crate::chip8_keypad::CHIP8Keypad;
+use crate::disassembler::{disassemble, format_instruction, ListingLine, Syntax};
+use crate::instruction::{decode, Instruction};
+
+const DEFAULT_CONTINUE_FRAMES: usize = 3600; // a minute of the program's time
+const DEFAULT_DUMP_SIZE: usize = 64;
+const DEFAULT_DISASSEMBLY_LINES: usize = 10;
+const DISASSEMBLY_LINES_BEFORE: usize = 4; // lines shown before the current one
+const BYTES_PER_DUMP_LINE: usize = 16;
+// indexed by the pixel value, the last two are only used by the XO-CHIP planes
+const FRAME_CHARACTERS: [char; 4] = ['.', '#', '+', '@'];
+
+const HELP: &str = "\
+Addresses and bytes are hex, counts are decimal, an empty line repeats the last command
+  break [ADDRESS]        set a breakpoint, list them without an address
+  delete ADDRESS         remove a breakpoint
+  step [COUNT]           execute instructions
+  next                   execute an instruction, run calls until they return
+  continue [FRAMES]      run frames until a breakpoint, a key wait or an error
+  regs                   show the registers and the timers
+  stack                  show the calls in progress
+  mem ADDRESS [COUNT]    show the memory
+  set ADDRESS BYTE...    change the memory
+  dis [ADDRESS] [COUNT]  disassemble around the current instruction or at an address
+  frame                  show the screen
+  keys [KEY...]          hold the keys down, release them all without keys
+  quit                   stop debugging";
+
+// a command line debugger for a machine, main feeds it the lines of stdin
+// the machine runs without a window, the keys command stands in for the keyboard
+pub struct Debugger {
+    syntax: Syntax,
+    keys: Rc<Cell<u16>>,
+    last_command: String,
+    finished: bool,
+}
+
+// the keys held down with the keys command
+pub struct DebuggerKeypad {
+    keys: Rc<Cell<u16>>,
+}
+
+impl CHIP8Keypad for DebuggerKeypad {
+    fn pressed_keys(&mut self) -> u16 {
+        self.keys.get()
+    }
+}
+
+impl Debugger {
+    pub fn new(syntax: Syntax) -> Debugger {
+        Debugger {
+            syntax,
+            keys: Rc::new(Cell::new(0)),
+            last_command: String::new(),
+            finished: false,
+        }
+    }
+
+    // the keypad the debugged machine has to use for the keys command to work
+    pub fn keypad(&self) -> DebuggerKeypad {
+        DebuggerKeypad {
+            keys: Rc::clone(&self.keys),
+        }
+    }
+
+    // whether the quit command has been given
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // runs a command and returns what it has to say
+    pub fn execute(&mut self, chip: &mut CHIP8, line: &str) -> String {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            line.trim().to_string()
+        };
+        self.last_command = line.clone();
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return String::new();
+        };
+        let arguments: Vec<&str> = words.collect();
+        let result = match command {
+            "help" | "h" => Ok(HELP.to_string()),
+            "break" | "b" => self.set_breakpoint(chip, &arguments),
+            "delete" => self.delete_breakpoint(chip, &arguments),
+            "step" | "s" => self.step(chip, &arguments),
+            "next" | "n" => self.next(chip),
+            "continue" | "c" => self.continue_running(chip, &arguments),
+            "regs" | "r" => Ok(registers(chip)),
+            "stack" => Ok(stack(chip)),
+            "mem" | "x" => memory(chip, &arguments),
+            "set" => set_memory(chip, &arguments),
+            "dis" | "d" => self.disassembly(chip, &arguments),
+            "frame" | "f" => Ok(frame(chip)),
+            "keys" | "k" => self.hold_keys(&arguments),
+            "quit" | "q" => {
+                self.finished = true;
+                Ok(String::new())
+            }
+            _ => Err(format!("Unknown command {}, try help", command)),
+        };
+        result.unwrap_or_else(|error| error)
+    }
+
+    fn set_breakpoint(&mut self, chip: &mut CHIP8, arguments: &[&str]) -> Result<String, String> {
+        match arguments {
+            [] if chip.breakpoints().is_empty() => Ok("No breakpoints".to_string()),
+            [] => Ok(chip
+                .breakpoints()
+                .iter()
+                .map(|&address| self.location(chip, address))
+                .collect::<Vec<String>>()
+                .join("\n")),
+            [address] => {
+                let address = parse_address(chip, address)?;
+                chip.add_breakpoint(address);
+                Ok(format!("Breakpoint at {:03X}", address))
+            }
+            _ => Err("Usage: break [ADDRESS]".to_string()),
+        }
+    }
+
+    fn delete_breakpoint(
+        &mut self,
+        chip: &mut CHIP8,
+        arguments: &[&str],
+    ) -> Result<String, String> {
+        let [address] = arguments else {
+            return Err("Usage: delete ADDRESS".to_string());
+        };
+        let address = parse_address(chip, address)?;
+        if chip.remove_breakpoint(address) {
+            Ok(format!("Deleted the breakpoint at {:03X}", address))
+        } else {
+            Err(format!("No breakpoint at {:03X}", address))
+        }
+    }
+
+    fn step(&mut self, chip: &mut CHIP8, arguments: &[&str]) -> Result<String, String> {
+        let count = match arguments {
+            [] => 1,
+            [count] => parse_count(count)?,
+            _ => return Err("Usage: step [COUNT]".to_string()),
+        };
+        for _ in 0..count {
+            if chip.is_halted() {
+                return Ok("The program has exited".to_string());
+            }
+            chip.step().map_err(|error| error.to_string())?;
+        }
+        Ok(self.location(chip, chip.program_counter()))
+    }
+
+    // a call runs like continue with a breakpoint at the return address,
+    // which only counts once the stack is back to where it was
+    fn next(&mut self, chip: &mut CHIP8) -> Result<String, String> {
+        let address = chip.program_counter();
+        let Some(Instruction::Call { .. }) = current_instruction(chip) else {
+            return self.step(chip, &[]);
+        };
+        chip.step().map_err(|error| error.to_string())?;
+        let return_address = address + 2;
+        let depth = chip.stack().len() - 1;
+        let temporary = !chip.breakpoints().contains(&return_address);
+        chip.add_breakpoint(return_address);
+        let result = run_frames(chip, DEFAULT_CONTINUE_FRAMES, |chip| {
+            !temporary || chip.program_counter() != return_address || chip.stack().len() == depth
+        });
+        if temporary {
+            chip.remove_breakpoint(return_address);
+        }
+        Ok(self.stopped(chip, result?))
+    }
+
+    fn continue_running(&mut self, chip: &mut CHIP8, arguments: &[&str]) -> Result<String, String> {
+        let frames = match arguments {
+            [] => DEFAULT_CONTINUE_FRAMES,
+            [frames] => parse_count(frames)?,
+            _ => return Err("Usage: continue [FRAMES]".to_string()),
+        };
+        let reason = run_frames(chip, frames, |_| true)?;
+        Ok(self.stopped(chip, reason))
+    }
+
+    // why the machine stopped and where
+    fn stopped(&self, chip: &CHIP8, reason: Option<String>) -> String {
+        let location = self.location(chip, chip.program_counter());
+        match reason {
+            Some(reason) => format!("{}\n{}", reason, location),
+            None => location,
+        }
+    }
+
+    fn disassembly(&mut self, chip: &mut CHIP8, arguments: &[&str]) -> Result<String, String> {
+        let ca = chip.program_counter();
+        let (start, count) = match arguments {
+            [] => (
+                ca.saturating_sub(DISASSEMBLY_LINES_BEFORE * 2),
+                DEFAULT_DISASSEMBLY_LINES,
+            ),
+            [address] => (parse_address(chip, address)?, DEFAULT_DISASSEMBLY_LINES),
+            [address, count] => (parse_address(chip, address)?, parse_count(count)?),
+            _ => return Err("Usage: dis [ADDRESS] [COUNT]".to_string()),
+        };
+        let memory = chip.memory();
+        // a huge count shows the rest of the memory
+        let end = start
+            .saturating_add(count.saturating_mul(4))
+            .min(memory.len());
+        let lines = disassemble(&memory[start..end], start);
+        Ok(lines
+            .iter()
+            .take(count)
+            .map(|line| self.format_line(chip, line))
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+
+    fn hold_keys(&mut self, arguments: &[&str]) -> Result<String, String> {
+        let mut keys = 0;
+        for key in arguments {
+            match u8::from_str_radix(key, 16) {
+                Ok(key) if key <= 0xF => keys |= 1 << key,
+                _ => return Err(format!("Unknown key {}, the keys are 0 - F", key)),
+            }
+        }
+        self.keys.set(keys);
+        if keys == 0 {
+            return Ok("No keys held".to_string());
+        }
+        let held = (0..16)
+            .filter(|key| keys & (1 << key) != 0)
+            .map(|key| format!("{:X}", key))
+            .collect::<Vec<String>>()
+            .join(" ");
+        Ok(format!("Holding {}", held))
+    }
+
+    // the instruction at the address
+    fn location(&self, chip: &CHIP8, address: usize) -> String {
+        let memory = chip.memory();
+        let end = (address + 4).min(memory.len());
+        match disassemble(&memory[address.min(end)..end], address).first() {
+            Some(line) => self.format_line(chip, line),
+            None => format!("   {:03X}: outside of the memory", address),
+        }
+    }
+
+    // the current instruction has an arrow, breakpoints have a star
+    fn format_line(&self, chip: &CHIP8, line: &ListingLine) -> String {
+        let current = if line.address == chip.program_counter() {
+            "=>"
+        } else {
+            "  "
+        };
+        let breakpoint = if chip.breakpoints().contains(&line.address) {
+            "*"
+        } else {
+            " "
+        };
+        let bytes = line
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let text = format_instruction(line, &BTreeMap::new(), self.syntax);
+        format!(
+            "{}{}{:03X}: {:<11}  {}",
+            current, breakpoint, line.address, bytes, text
+        )
+    }
+}
+
+// runs up to the given number of frames, the condition decides whether to stop at a breakpoint,
+// returns why it stopped unless it was a breakpoint
+fn run_frames<P>(chip: &mut CHIP8, frames: usize, stop: P) -> Result<Option<String>, String>
+where
+    P: Fn(&CHIP8) -> bool,
+{
+    for _ in 0..frames {
+        let report = chip.run_frame().map_err(|error| error.to_string())?;
+        if report.halted {
+            return Ok(Some("The program has exited".to_string()));
+        }
+        if report.breakpoint && stop(chip) {
+            return Ok(None);
+        }
+        if report.waiting_for_key {
+            return Ok(Some("Waiting for a key".to_string()));
+        }
+    }
+    Ok(Some(format!("Stopped after {} frames", frames)))
+}
+
+fn current_instruction(chip: &CHIP8) -> Option<Instruction> {
+    let memory = chip.memory();
+    let ca = chip.program_counter();
+    if ca + 1 >= memory.len() {
+        return None;
+    }
+    Some(decode((memory[ca] as u16) << 8 | memory[ca + 1] as u16))
+}
+
+fn registers(chip: &CHIP8) -> String {
+    let v = chip.registers();
+    let row = |registers: std::ops::Range<usize>| {
+        registers
+            .map(|x| format!("V{:X} {:02X}", x, v[x]))
+            .collect::<Vec<String>>()
+            .join("  ")
+    };
+    format!(
+        "{}\n{}\nI  {:04X}  PC {:03X}  SP {:X}  DT {:02X}  ST {:02X}",
+        row(0..8),
+        row(8..16),
+        chip.index_register(),
+        chip.program_counter(),
+        chip.stack().len(),
+        chip.delay_timer(),
+        chip.sound_timer()
+    )
+}
+
+// the innermost call first, like a backtrace
+fn stack(chip: &CHIP8) -> String {
+    if chip.stack().is_empty() {
+        return "No calls in progress".to_string();
+    }
+    chip.stack()
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(depth, address)| format!("#{} called at {:03X}", depth, address))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn memory(chip: &CHIP8, arguments: &[&str]) -> Result<String, String> {
+    let (start, count) = match arguments {
+        [address] => (parse_address(chip, address)?, DEFAULT_DUMP_SIZE),
+        [address, count] => (parse_address(chip, address)?, parse_count(count)?),
+        _ => return Err("Usage: mem ADDRESS [COUNT]".to_string()),
+    };
+    let end = start.saturating_add(count).min(chip.memory().len());
+    Ok(chip.memory()[start..end]
+        .chunks(BYTES_PER_DUMP_LINE)
+        .enumerate()
+        .map(|(n, bytes)| {
+            let bytes = bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<String>>()
+                .join(" ");
+            format!("{:04X}: {}", start + n * BYTES_PER_DUMP_LINE, bytes)
+        })
+        .collect::<Vec<String>>()
+        .join("\n"))
+}
+
+fn set_memory(chip: &mut CHIP8, arguments: &[&str]) -> Result<String, String> {
+    let [address, bytes @ ..] = arguments else {
+        return Err("Usage: set ADDRESS BYTE...".to_string());
+    };
+    if bytes.is_empty() {
+        return Err("Usage: set ADDRESS BYTE...".to_string());
+    }
+    let address = parse_address(chip, address)?;
+    let bytes = bytes
+        .iter()
+        .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("Invalid byte {}", byte)))
+        .collect::<Result<Vec<u8>, String>>()?;
+    if address + bytes.len() > chip.memory().len() {
+        return Err("The bytes don't fit into the memory".to_string());
+    }
+    chip.memory_mut()[address..address + bytes.len()].copy_from_slice(&bytes);
+    Ok(format!("Changed {} bytes at {:03X}", bytes.len(), address))
+}
+
+fn frame(chip: &CHIP8) -> String {
+    let video_memory = chip.video_memory();
+    (0..video_memory.height())
+        .map(|y| {
+            video_memory[y]
+                .iter()
+                .map(|&pixel| FRAME_CHARACTERS[pixel as usize & 0b11])
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn parse_address(chip: &CHIP8, text: &str) -> Result<usize, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('#');
+    match usize::from_str_radix(digits, 16) {
+        Ok(address) if address < chip.memory().len() => Ok(address),
+        Ok(_) => Err(format!("Address {} is outside of the memory", text)),
+        Err(_) => Err(format!("Invalid address {}", text)),
+    }
+}
+
+fn parse_count(text: &str) -> Result<usize, String> {
+    text.parse().map_err(|_| format!("Invalid count {}", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_audio::DummyCHIP8Audio;
+    use crate::chip8_display::DummyCHIP8Display;
+    use crate::fault_policy::FaultPolicy;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_commands() {
+        let mut debugger = Debugger::new(Syntax::Cowgod);
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = debugger.keypad();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        // the program ends in an endless loop
+        chip8.set_fault_policy(FaultPolicy::lenient());
+        chip8.load_from_memory(&[
+            0x60, 0x05, // 200: v0 := 5
+            0x22, 0x0A, // 202: call 20A
+            0xF1, 0x0A, // 204: v1 := key
+            0xD0, 0x01, // 206: sprite v0 v0 1
+            0x12, 0x08, // 208: jump 208
+            0x70, 0x01, // 20A: v0 += 1
+            0x00, 0xEE, // 20C: return
+        ]);
+
+        assert_eq!(
+            debugger.execute(&mut chip8, "step"),
+            "=> 202: 22 0A        CALL #20A"
+        );
+        assert_eq!(
+            debugger.execute(&mut chip8, "next"),
+            "=> 204: F1 0A        LD V1, K"
+        );
+        assert_eq!(chip8.registers()[0], 6);
+        assert_eq!(
+            debugger.execute(&mut chip8, "continue"),
+            "Waiting for a key\n=> 204: F1 0A        LD V1, K"
+        );
+        assert_eq!(debugger.execute(&mut chip8, "keys 3 a"), "Holding 3 A");
+        assert_eq!(
+            debugger.execute(&mut chip8, "c"),
+            "Waiting for a key\n=> 204: F1 0A        LD V1, K"
+        );
+        assert_eq!(debugger.execute(&mut chip8, "keys"), "No keys held");
+        assert_eq!(
+            debugger.execute(&mut chip8, "break 206"),
+            "Breakpoint at 206"
+        );
+        assert_eq!(debugger.execute(&mut chip8, ""), "Breakpoint at 206");
+        assert_eq!(
+            debugger.execute(&mut chip8, "continue 10"),
+            "=>*206: D0 01        DRW V0, V0, 1"
+        );
+        assert_eq!(
+            debugger.execute(&mut chip8, "regs"),
+            "V0 06  V1 03  V2 00  V3 00  V4 00  V5 00  V6 00  V7 00\n\
+             V8 00  V9 00  VA 00  VB 00  VC 00  VD 00  VE 00  VF 00\n\
+             I  0000  PC 206  SP 0  DT 00  ST 00"
+        );
+        // I is 0, the sprite is the first byte of the memory
+        debugger.execute(&mut chip8, "set 0 F0");
+        assert_eq!(
+            debugger.execute(&mut chip8, "c 10"),
+            "Stopped after 10 frames\n=> 208: 12 08        JP #208"
+        );
+        let frame = debugger.execute(&mut chip8, "frame");
+        assert_eq!(frame.lines().count(), 32);
+        assert_eq!(&frame.lines().nth(6).unwrap()[..12], "......####..");
+
+        assert_eq!(
+            debugger.execute(&mut chip8, "dis"),
+            [
+                "   200: 60 05        LD V0, #05",
+                "   202: 22 0A        CALL #20A",
+                "   204: F1 0A        LD V1, K",
+                "  *206: D0 01        DRW V0, V0, 1",
+                "=> 208: 12 08        JP #208",
+                "   20A: 70 01        ADD V0, #01",
+                "   20C: 00 EE        RET",
+                "   20E: 00 00        DW #0000",
+                "   210: 00 00        DW #0000",
+                "   212: 00 00        DW #0000",
+            ]
+            .join("\n")
+        );
+        assert_eq!(
+            debugger.execute(&mut chip8, "set 20B FF 01"),
+            "Changed 2 bytes at 20B"
+        );
+        assert_eq!(
+            debugger.execute(&mut chip8, "mem 200 18"),
+            "0200: 60 05 22 0A F1 0A D0 01 12 08 70 FF 01 EE 00 00\n0210: 00 00"
+        );
+        assert_eq!(
+            debugger.execute(&mut chip8, "stack"),
+            "No calls in progress"
+        );
+        assert_eq!(
+            debugger.execute(&mut chip8, "delete 206"),
+            "Deleted the breakpoint at 206"
+        );
+        assert_eq!(debugger.execute(&mut chip8, "break"), "No breakpoints");
+        assert_eq!(
+            debugger.execute(&mut chip8, "mem FFE 18446744073709551615"),
+            "0FFE: 00 00"
+        );
+        assert_eq!(
+            debugger
+                .execute(&mut chip8, "dis FFC 18446744073709551615")
+                .lines()
+                .count(),
+            2
+        );
+        assert_eq!(
+            debugger.execute(&mut chip8, "mem 10000"),
+            "Address 10000 is outside of the memory"
+        );
+        assert!(!debugger.is_finished());
+        debugger.execute(&mut chip8, "quit");
+        assert!(debugger.is_finished());
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::BTreeMap;
+
+use crate::instruction::{decode, Address, Instruction, Register};
+
+// the assembly language of the listing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Syntax {
+    Cowgod, // the mnemonics of Cowgod's Chip-8 Technical Reference, LD V0, #05
+    Octo,   // the language of the Octo assembler, v0 := 0x05
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name {
+            "cowgod" => Some(Syntax::Cowgod),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+// one instruction of the listing, or a single byte left at the end
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListingLine {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+}
+
+// decodes the memory two bytes at a time, origin is the address of its first byte
+// there is no way to tell code from data, so data is decoded as instructions too
+pub fn disassemble(memory: &[u8], origin: usize) -> Vec<ListingLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    while offset < memory.len() {
+        let rest = &memory[offset..];
+        let (instruction, size) = if rest.len() == 1 {
+            (
+                Instruction::Unknown {
+                    opcode: rest[0] as u16,
+                },
+                1,
+            )
+        } else {
+            let instruction = decode((rest[0] as u16) << 8 | rest[1] as u16);
+            if instruction.size() <= rest.len() {
+                (instruction, instruction.size())
+            } else {
+                let opcode = (rest[0] as u16) << 8 | rest[1] as u16;
+                (Instruction::Unknown { opcode }, 2)
+            }
+        };
+        lines.push(ListingLine {
+            address: origin + offset,
+            bytes: rest[..size].to_vec(),
+            instruction,
+        });
+        offset += size;
+    }
+    lines
+}
+
+// names the addresses that instructions of the listing jump to or call,
+// only the ones where a line of the listing starts
+pub fn find_labels(lines: &[ListingLine]) -> BTreeMap<usize, String> {
+    let mut labels = BTreeMap::new();
+    for line in lines {
+        let (target, prefix) = match line.instruction {
+            Instruction::Call { address } => (address.0 as usize, "sub"),
+            Instruction::Jump { address } | Instruction::JumpWithOffset { address } => {
+                (address.0 as usize, "label")
+            }
+            _ => continue,
+        };
+        if lines.iter().any(|line| line.address == target) {
+            // a subroutine name wins over a label
+            let name = format!("{}_{:03X}", prefix, target);
+            if prefix == "sub" || !labels.contains_key(&target) {
+                labels.insert(target, name);
+            }
+        }
+    }
+    labels
+}
+
+// the whole listing with the labels on their own lines,
+// in Octo syntax the addresses and bytes are comments, so the listing can be assembled again
+pub fn format_listing(lines: &[ListingLine], syntax: Syntax) -> String {
+    let labels = find_labels(lines);
+    let mut listing = String::new();
+    for line in lines {
+        if let Some(label) = labels.get(&line.address) {
+            match syntax {
+                Syntax::Cowgod => listing.push_str(&format!("{}:\n", label)),
+                Syntax::Octo => listing.push_str(&format!(": {}\n", label)),
+            }
+        }
+        let bytes = line
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let text = format_instruction(line, &labels, syntax);
+        match syntax {
+            Syntax::Cowgod => {
+                listing.push_str(&format!("{:03X}: {:<11}  {}\n", line.address, bytes, text))
+            }
+            Syntax::Octo => listing.push_str(&format!(
+                "  {:<24} # {:03X}: {}\n",
+                text, line.address, bytes
+            )),
+        }
+    }
+    listing
+}
+
+// disassembles a ROM or a part of the memory into a listing
+pub fn listing(memory: &[u8], origin: usize, syntax: Syntax) -> String {
+    format_listing(&disassemble(memory, origin), syntax)
+}
+
+pub fn format_instruction(
+    line: &ListingLine,
+    labels: &BTreeMap<usize, String>,
+    syntax: Syntax,
+) -> String {
+    match syntax {
+        Syntax::Cowgod => format_cowgod(line, labels),
+        Syntax::Octo => format_octo(line, labels),
+    }
+}
+
+// the operand of F000 NNNN
+fn long_address(line: &ListingLine) -> u16 {
+    (line.bytes[2] as u16) << 8 | line.bytes[3] as u16
+}
+
+fn target(address: Address, labels: &BTreeMap<usize, String>, number: String) -> String {
+    match labels.get(&(address.0 as usize)) {
+        Some(label) => label.clone(),
+        None => number,
+    }
+}
+
+fn format_cowgod(line: &ListingLine, labels: &BTreeMap<usize, String>) -> String {
+    let v = |register: Register| format!("V{:X}", register);
+    match line.instruction {
+        Instruction::MachineCode { address } => format!("SYS #{:03X}", address),
+        Instruction::ClearScreen => "CLS".to_string(),
+        Instruction::Return => "RET".to_string(),
+        Instruction::ScrollDown { rows } => format!("SCD {}", rows),
+        Instruction::ScrollUp { rows } => format!("SCU {}", rows),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::LowResolution => "LOW".to_string(),
+        Instruction::HighResolution => "HIGH".to_string(),
+        Instruction::Jump { address } => {
+            format!(
+                "JP {}",
+                target(address, labels, format!("#{:03X}", address))
+            )
+        }
+        Instruction::Call { address } => {
+            format!(
+                "CALL {}",
+                target(address, labels, format!("#{:03X}", address))
+            )
+        }
+        Instruction::SkipIfEqual { x, byte } => format!("SE {}, #{:02X}", v(x), byte),
+        Instruction::SkipIfNotEqual { x, byte } => format!("SNE {}, #{:02X}", v(x), byte),
+        Instruction::SkipIfRegistersEqual { x, y } => format!("SE {}, {}", v(x), v(y)),
+        Instruction::SaveRange { x, y } => format!("SAVE {} - {}", v(x), v(y)),
+        Instruction::LoadRange { x, y } => format!("LOAD {} - {}", v(x), v(y)),
+        Instruction::Load { x, byte } => format!("LD {}, #{:02X}", v(x), byte),
+        Instruction::Add { x, byte } => format!("ADD {}, #{:02X}", v(x), byte),
+        Instruction::Move { x, y } => format!("LD {}, {}", v(x), v(y)),
+        Instruction::Or { x, y } => format!("OR {}, {}", v(x), v(y)),
+        Instruction::And { x, y } => format!("AND {}, {}", v(x), v(y)),
+        Instruction::Xor { x, y } => format!("XOR {}, {}", v(x), v(y)),
+        Instruction::AddRegisters { x, y } => format!("ADD {}, {}", v(x), v(y)),
+        Instruction::Subtract { x, y } => format!("SUB {}, {}", v(x), v(y)),
+        Instruction::ShiftRight { x, y } => format!("SHR {}, {}", v(x), v(y)),
+        Instruction::SubtractReversed { x, y } => format!("SUBN {}, {}", v(x), v(y)),
+        Instruction::ShiftLeft { x, y } => format!("SHL {}, {}", v(x), v(y)),
+        Instruction::SkipIfRegistersNotEqual { x, y } => format!("SNE {}, {}", v(x), v(y)),
+        Instruction::LoadIndex { address } => format!("LD I, #{:03X}", address),
+        Instruction::JumpWithOffset { address } => format!(
+            "JP V0, {}",
+            target(address, labels, format!("#{:03X}", address))
+        ),
+        Instruction::Random { x, byte } => format!("RND {}, #{:02X}", v(x), byte),
+        Instruction::Draw { x, y, height } => format!("DRW {}, {}, {}", v(x), v(y), height),
+        Instruction::SkipIfKey { x } => format!("SKP {}", v(x)),
+        Instruction::SkipIfNotKey { x } => format!("SKNP {}", v(x)),
+        Instruction::LoadLongIndex => format!("LD I, #{:04X}", long_address(line)),
+        Instruction::SelectPlanes { planes } => format!("PLANE {}", planes),
+        Instruction::LoadAudioPattern => "AUDIO".to_string(),
+        Instruction::GetDelayTimer { x } => format!("LD {}, DT", v(x)),
+        Instruction::WaitForKey { x } => format!("LD {}, K", v(x)),
+        Instruction::SetDelayTimer { x } => format!("LD DT, {}", v(x)),
+        Instruction::SetSoundTimer { x } => format!("LD ST, {}", v(x)),
+        Instruction::AddIndex { x } => format!("ADD I, {}", v(x)),
+        Instruction::LoadFont { x } => format!("LD F, {}", v(x)),
+        Instruction::LoadLargeFont { x } => format!("LD HF, {}", v(x)),
+        Instruction::SetPitch { x } => format!("PITCH {}", v(x)),
+        Instruction::StoreDecimal { x } => format!("LD B, {}", v(x)),
+        Instruction::StoreRegisters { x } => format!("LD [I], {}", v(x)),
+        Instruction::LoadRegisters { x } => format!("LD {}, [I]", v(x)),
+        Instruction::SaveFlags { x } => format!("LD R, {}", v(x)),
+        Instruction::LoadFlags { x } => format!("LD {}, R", v(x)),
+        Instruction::Unknown { opcode } => {
+            if line.bytes.len() == 1 {
+                format!("DB #{:02X}", opcode)
+            } else {
+                format!("DW #{:04X}", opcode)
+            }
+        }
+    }
+}
+
+fn format_octo(line: &ListingLine, labels: &BTreeMap<usize, String>) -> String {
+    let v = |register: Register| format!("v{:x}", register);
+    match line.instruction {
+        // Octo has no mnemonic for them, the bytes go to the output as they are
+        Instruction::MachineCode { .. } | Instruction::Unknown { .. } => line
+            .bytes
+            .iter()
+            .map(|byte| format!("0x{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(" "),
+        Instruction::ClearScreen => "clear".to_string(),
+        Instruction::Return => "return".to_string(),
+        Instruction::ScrollDown { rows } => format!("scroll-down {}", rows),
+        Instruction::ScrollUp { rows } => format!("scroll-up {}", rows),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::LowResolution => "lores".to_string(),
+        Instruction::HighResolution => "hires".to_string(),
+        Instruction::Jump { address } => {
+            format!(
+                "jump {}",
+                target(address, labels, format!("0x{:03X}", address))
+            )
+        }
+        Instruction::Call { address } => match labels.get(&(address.0 as usize)) {
+            Some(label) => label.clone(),
+            None => format!(":call 0x{:03X}", address),
+        },
+        // the skip instructions are the opposite conditions of Octo's if ... then
+        Instruction::SkipIfEqual { x, byte } => format!("if {} != 0x{:02X} then", v(x), byte),
+        Instruction::SkipIfNotEqual { x, byte } => format!("if {} == 0x{:02X} then", v(x), byte),
+        Instruction::SkipIfRegistersEqual { x, y } => format!("if {} != {} then", v(x), v(y)),
+        Instruction::SaveRange { x, y } => format!("save {} - {}", v(x), v(y)),
+        Instruction::LoadRange { x, y } => format!("load {} - {}", v(x), v(y)),
+        Instruction::Load { x, byte } => format!("{} := 0x{:02X}", v(x), byte),
+        Instruction::Add { x, byte } => format!("{} += 0x{:02X}", v(x), byte),
+        Instruction::Move { x, y } => format!("{} := {}", v(x), v(y)),
+        Instruction::Or { x, y } => format!("{} |= {}", v(x), v(y)),
+        Instruction::And { x, y } => format!("{} &= {}", v(x), v(y)),
+        Instruction::Xor { x, y } => format!("{} ^= {}", v(x), v(y)),
+        Instruction::AddRegisters { x, y } => format!("{} += {}", v(x), v(y)),
+        Instruction::Subtract { x, y } => format!("{} -= {}", v(x), v(y)),
+        Instruction::ShiftRight { x, y } => format!("{} >>= {}", v(x), v(y)),
+        Instruction::SubtractReversed { x, y } => format!("{} =- {}", v(x), v(y)),
+        Instruction::ShiftLeft { x, y } => format!("{} <<= {}", v(x), v(y)),
+        Instruction::SkipIfRegistersNotEqual { x, y } => {
+            format!("if {} == {} then", v(x), v(y))
+        }
+        Instruction::LoadIndex { address } => format!("i := 0x{:03X}", address),
+        Instruction::JumpWithOffset { address } => format!(
+            "jump0 {}",
+            target(address, labels, format!("0x{:03X}", address))
+        ),
+        Instruction::Random { x, byte } => format!("{} := random 0x{:02X}", v(x), byte),
+        Instruction::Draw { x, y, height } => format!("sprite {} {} {}", v(x), v(y), height),
+        Instruction::SkipIfKey { x } => format!("if {} -key then", v(x)),
+        Instruction::SkipIfNotKey { x } => format!("if {} key then", v(x)),
+        Instruction::LoadLongIndex => format!("i := long 0x{:04X}", long_address(line)),
+        Instruction::SelectPlanes { planes } => format!("plane {}", planes),
+        Instruction::LoadAudioPattern => "audio".to_string(),
+        Instruction::GetDelayTimer { x } => format!("{} := delay", v(x)),
+        Instruction::WaitForKey { x } => format!("{} := key", v(x)),
+        Instruction::SetDelayTimer { x } => format!("delay := {}", v(x)),
+        Instruction::SetSoundTimer { x } => format!("buzzer := {}", v(x)),
+        Instruction::AddIndex { x } => format!("i += {}", v(x)),
+        Instruction::LoadFont { x } => format!("i := hex {}", v(x)),
+        Instruction::LoadLargeFont { x } => format!("i := bighex {}", v(x)),
+        Instruction::SetPitch { x } => format!("pitch := {}", v(x)),
+        Instruction::StoreDecimal { x } => format!("bcd {}", v(x)),
+        Instruction::StoreRegisters { x } => format!("save {}", v(x)),
+        Instruction::LoadRegisters { x } => format!("load {}", v(x)),
+        Instruction::SaveFlags { x } => format!("saveflags {}", v(x)),
+        Instruction::LoadFlags { x } => format!("loadflags {}", v(x)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 6005  -- store 5 in v0
+    // 2208  -- call the subroutine at 0x208
+    // 1202  -- jump back to 0x202
+    // 0000  -- data
+    // 8016  -- shift v1 right into v0
+    // 00EE  -- return
+    // F0    -- a byte left at the end
+    const PROGRAM: [u8; 13] = [
+        0x60, 0x05, 0x22, 0x08, 0x12, 0x02, 0x00, 0x00, 0x80, 0x16, 0x00, 0xEE, 0xF0,
+    ];
+
+    #[test]
+    fn test_disassemble() {
+        let lines = disassemble(&PROGRAM, 0x200);
+        assert_eq!(lines.len(), 7);
+        assert_eq!(lines[1].address, 0x202);
+        assert_eq!(lines[1].bytes, vec![0x22, 0x08]);
+        assert_eq!(
+            lines[1].instruction,
+            Instruction::Call {
+                address: Address(0x208)
+            }
+        );
+        assert_eq!(lines[6].bytes, vec![0xF0]);
+
+        let labels = find_labels(&lines);
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[&0x202], "label_202");
+        assert_eq!(labels[&0x208], "sub_208");
+    }
+
+    #[test]
+    fn test_long_instruction() {
+        // F000 1234  -- i := long 0x1234
+        // F000       -- not enough bytes left for the address
+        let lines = disassemble(&[0xF0, 0x00, 0x12, 0x34, 0xF0, 0x00], 0x200);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].bytes.len(), 4);
+        assert_eq!(
+            lines[1].instruction,
+            Instruction::Unknown { opcode: 0xF000 }
+        );
+        assert_eq!(
+            format_cowgod(&lines[0], &BTreeMap::new()),
+            "LD I, #1234".to_string()
+        );
+    }
+
+    #[test]
+    fn test_cowgod_listing() {
+        let expected = "\
+200: 60 05        LD V0, #05
+label_202:
+202: 22 08        CALL sub_208
+204: 12 02        JP label_202
+206: 00 00        DW #0000
+sub_208:
+208: 80 16        SHR V0, V1
+20A: 00 EE        RET
+20C: F0           DB #F0
+";
+        assert_eq!(listing(&PROGRAM, 0x200, Syntax::Cowgod), expected);
+    }
+
+    #[test]
+    fn test_octo_listing() {
+        let expected = "  v0 := 0x05               # 200: 60 05
+: label_202
+  sub_208                  # 202: 22 08
+  jump label_202           # 204: 12 02
+  0x00 0x00                # 206: 00 00
+: sub_208
+  v0 >>= v1                # 208: 80 16
+  return                   # 20A: 00 EE
+  0xF0                     # 20C: F0
+";
+        assert_eq!(listing(&PROGRAM, 0x200, Syntax::Octo), expected);
+    }
+}
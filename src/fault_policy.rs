@@ -0,0 +1,132 @@
+use crate::chip8_error::Chip8Error;
+
+// the kinds of errors a running program can cause
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultClass {
+    Stack,         // overflow and underflow of the 16 level stack
+    Memory,        // reads and writes outside of the memory
+    Jump,          // jumps outside of the memory and running off its end
+    EndlessLoop,   // 1NNN jumping to itself
+    IllegalOpcode, // unknown and unsupported opcodes
+}
+
+impl FaultClass {
+    // errors that don't come from executing an instruction have no class
+    pub fn of(error: &Chip8Error) -> Option<FaultClass> {
+        match error {
+            Chip8Error::Load { .. } => None,
+            Chip8Error::IllegalOpcode { .. } | Chip8Error::UnsupportedOpcode { .. } => {
+                Some(FaultClass::IllegalOpcode)
+            }
+            Chip8Error::StackOverflow { .. } | Chip8Error::StackUnderflow { .. } => {
+                Some(FaultClass::Stack)
+            }
+            Chip8Error::MemoryOutOfBounds { .. } => Some(FaultClass::Memory),
+            Chip8Error::JumpOutOfMemory { .. } | Chip8Error::ProgramCounterOutOfMemory { .. } => {
+                Some(FaultClass::Jump)
+            }
+            Chip8Error::EndlessLoop { .. } => Some(FaultClass::EndlessLoop),
+        }
+    }
+}
+
+// what the machine does when an instruction faults
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultAction {
+    Halt,       // return the error and stay at the faulting instruction
+    SkipAndLog, // remember the error and continue with the next instruction
+    // do what the original interpreters did: wrap the stack and the addresses around,
+    // keep looping in an endless loop, ignore unknown opcodes
+    WrapAround,
+    Callback, // ask the fault handler, halt if there is none
+}
+
+impl FaultAction {
+    pub fn from_name(name: &str) -> Option<FaultAction> {
+        match name {
+            "halt" => Some(FaultAction::Halt),
+            "skip" => Some(FaultAction::SkipAndLog),
+            "wrap" => Some(FaultAction::WrapAround),
+            "callback" => Some(FaultAction::Callback),
+            _ => None,
+        }
+    }
+
+    // the name from_name reads
+    pub fn name(&self) -> &'static str {
+        match self {
+            FaultAction::Halt => "halt",
+            FaultAction::SkipAndLog => "skip",
+            FaultAction::WrapAround => "wrap",
+            FaultAction::Callback => "callback",
+        }
+    }
+}
+
+// decides the action for the faults of the Callback class
+pub trait FaultHandler {
+    // returning Callback again halts the machine
+    fn handle_fault(&mut self, error: &Chip8Error) -> FaultAction;
+}
+
+// the action for every fault class
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FaultPolicy {
+    pub stack: FaultAction,
+    pub memory: FaultAction,
+    pub jump: FaultAction,
+    pub endless_loop: FaultAction,
+    pub illegal_opcode: FaultAction,
+}
+
+impl FaultPolicy {
+    // everything halts, for debugging programs
+    pub fn strict() -> FaultPolicy {
+        FaultPolicy::all(FaultAction::Halt)
+    }
+
+    // keeps the programs running the way the real interpreters did, for playing
+    pub fn lenient() -> FaultPolicy {
+        FaultPolicy {
+            stack: FaultAction::WrapAround,
+            memory: FaultAction::WrapAround,
+            jump: FaultAction::WrapAround,
+            endless_loop: FaultAction::WrapAround,
+            illegal_opcode: FaultAction::SkipAndLog,
+        }
+    }
+
+    pub fn all(action: FaultAction) -> FaultPolicy {
+        FaultPolicy {
+            stack: action,
+            memory: action,
+            jump: action,
+            endless_loop: action,
+            illegal_opcode: action,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<FaultPolicy> {
+        match name {
+            "strict" => Some(FaultPolicy::strict()),
+            "lenient" => Some(FaultPolicy::lenient()),
+            _ => None,
+        }
+    }
+
+    pub fn action(&self, class: FaultClass) -> FaultAction {
+        match class {
+            FaultClass::Stack => self.stack,
+            FaultClass::Memory => self.memory,
+            FaultClass::Jump => self.jump,
+            FaultClass::EndlessLoop => self.endless_loop,
+            FaultClass::IllegalOpcode => self.illegal_opcode,
+        }
+    }
+}
+
+impl Default for FaultPolicy {
+    fn default() -> FaultPolicy {
+        FaultPolicy::strict()
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::{Duration, Instant};
+
+const MAX_FRAMES_TO_CATCH_UP: u32 = 4;
+
+// tells the host how many frames are due, so the machine runs at a fixed
+// frame rate however often the event loop happens to wake up
+pub struct FrameClock {
+    frame_duration: Duration,
+    last_frame: Instant,
+}
+
+impl FrameClock {
+    pub fn new(frame_rate: u32) -> FrameClock {
+        FrameClock {
+            frame_duration: Duration::from_secs(1) / frame_rate,
+            last_frame: Instant::now(),
+        }
+    }
+
+    // number of whole frames elapsed since the last call,
+    // the remainder is carried over to the next call
+    pub fn frames_due(&mut self) -> u32 {
+        let now = Instant::now();
+        let mut frames = 0;
+        while now - self.last_frame >= self.frame_duration {
+            self.last_frame += self.frame_duration;
+            frames += 1;
+        }
+        if frames > MAX_FRAMES_TO_CATCH_UP {
+            // the host was suspended or too slow, don't try to catch up
+            self.last_frame = now;
+            frames = MAX_FRAMES_TO_CATCH_UP;
+        }
+        frames
+    }
+}
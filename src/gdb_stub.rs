@@ -0,0 +1,472 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use crate::chip8::CHIP8;
+use crate::chip8_error::Chip8Error;
+
+// the registers in the order of the g packet and the register numbers of p,
+// V0 - VF and the 8 bit ones are one byte, I and PC are two, everything is big endian like CHIP-8
+const REGISTER_COUNT: usize = 21;
+const I_REGISTER: usize = 16;
+const PC_REGISTER: usize = 17;
+const SP_REGISTER: usize = 18;
+const DT_REGISTER: usize = 19;
+const ST_REGISTER: usize = 20;
+
+const INTERRUPT: u8 = 0x03;
+const MAX_PACKET_SIZE: usize = 0x4000;
+
+// the signals of the stop replies
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+// a stream the stub can check for an interrupt without blocking while the program runs
+pub trait Connection: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixStream::set_nonblocking(self, nonblocking)
+    }
+}
+
+enum Packet {
+    Command(String),
+    Interrupt, // Ctrl-C while the program was stopped
+}
+
+// serves one GDB session over the remote serial protocol, the machine runs only when GDB says so
+pub struct GdbStub<C: Connection> {
+    connection: C,
+    acknowledge: bool, // the packets are acknowledged until GDB asks for the no ack mode
+    stop_reply: String, // the reply to ?, why the machine stopped the last time
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(connection: C) -> GdbStub<C> {
+        GdbStub {
+            connection,
+            acknowledge: true,
+            stop_reply: format!("S{:02X}", SIGTRAP),
+        }
+    }
+
+    // returns when GDB detaches, kills the program or disconnects
+    pub fn serve(&mut self, chip: &mut CHIP8) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            let command = match packet {
+                Packet::Command(command) => command,
+                Packet::Interrupt => {
+                    self.stop_reply = format!("S{:02X}", SIGINT);
+                    let reply = self.stop_reply.clone();
+                    self.send_packet(&reply)?;
+                    continue;
+                }
+            };
+            match command.as_str() {
+                "D" => return self.send_packet("OK"),
+                "k" => return Ok(()),
+                _ => {
+                    let reply = self.reply(chip, &command)?;
+                    self.send_packet(&reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // unsupported commands get an empty reply
+    fn reply(&mut self, chip: &mut CHIP8, command: &str) -> io::Result<String> {
+        let reply = match command.as_bytes()[0] {
+            b'?' => self.stop_reply.clone(),
+            b'g' => (0..REGISTER_COUNT)
+                .map(|n| register(chip, n).unwrap())
+                .collect::<String>(),
+            b'p' => usize::from_str_radix(&command[1..], 16)
+                .ok()
+                .and_then(|n| register(chip, n))
+                .unwrap_or_else(|| "E01".to_string()),
+            b'm' => read_memory(chip, &command[1..]).unwrap_or_else(|| "E01".to_string()),
+            b'M' => write_memory(chip, &command[1..]).unwrap_or_else(|| "E01".to_string()),
+            b'Z' | b'z' => breakpoint(chip, command).unwrap_or_default(),
+            b's' => self.resume(chip, true)?,
+            b'c' => self.resume(chip, false)?,
+            b'H' => "OK".to_string(),
+            b'q' | b'Q' => self.query(command),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn query(&mut self, command: &str) -> String {
+        if command.starts_with("qSupported") {
+            return format!(
+                "PacketSize={:X};qXfer:features:read+;QStartNoAckMode+",
+                MAX_PACKET_SIZE
+            );
+        }
+        if let Some(range) = command.strip_prefix("qXfer:features:read:target.xml:") {
+            return read_target_description(range).unwrap_or_else(|| "E01".to_string());
+        }
+        match command {
+            "QStartNoAckMode" => {
+                self.acknowledge = false;
+                "OK".to_string()
+            }
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+
+    // runs one instruction or until a breakpoint, an error, the exit or an interrupt from GDB
+    fn resume(&mut self, chip: &mut CHIP8, step: bool) -> io::Result<String> {
+        let reply = loop {
+            if chip.is_halted() {
+                break "W00".to_string();
+            }
+            let result = if step { chip.step() } else { chip.run_frame() };
+            match result {
+                Err(error) => break format!("S{:02X}", signal(&error)),
+                Ok(report) if report.halted => break "W00".to_string(),
+                Ok(report) if step || report.breakpoint => break format!("S{:02X}", SIGTRAP),
+                Ok(_) => {
+                    if self.interrupted()? {
+                        break format!("S{:02X}", SIGINT);
+                    }
+                }
+            }
+        };
+        self.stop_reply = reply.clone();
+        Ok(reply)
+    }
+
+    // whether GDB has sent Ctrl-C, anything else it sends while the program runs is dropped
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.connection.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.connection.read(&mut byte);
+        self.connection.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(byte[0] == INTERRUPT),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.connection.read_exact(&mut byte) {
+            Ok(()) => Ok(Some(byte[0])),
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    // $command#checksum, the acknowledgements from GDB between the packets are skipped
+    fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Packet::Interrupt)),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.connection.read_exact(&mut checksum)?;
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(checksum_of(&data));
+            if self.acknowledge {
+                self.connection.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid && !data.is_empty() {
+                return Ok(Some(Packet::Command(
+                    String::from_utf8_lossy(&data).into_owned(),
+                )));
+            }
+        }
+    }
+
+    // the replies are sent again until GDB acknowledges them
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        loop {
+            self.connection.write_all(packet.as_bytes())?;
+            self.connection.flush()?;
+            if !self.acknowledge {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &byte| sum.wrapping_add(byte))
+}
+
+fn signal(error: &Chip8Error) -> u8 {
+    match error {
+        Chip8Error::IllegalOpcode { .. } | Chip8Error::UnsupportedOpcode { .. } => SIGILL,
+        _ => SIGSEGV,
+    }
+}
+
+fn register(chip: &CHIP8, n: usize) -> Option<String> {
+    let value = match n {
+        0..=15 => return Some(format!("{:02x}", chip.registers()[n])),
+        I_REGISTER => return Some(format!("{:04x}", chip.index_register())),
+        PC_REGISTER => return Some(format!("{:04x}", chip.program_counter() as u16)),
+        SP_REGISTER => chip.stack().len() as u8,
+        DT_REGISTER => chip.delay_timer(),
+        ST_REGISTER => chip.sound_timer(),
+        _ => return None,
+    };
+    Some(format!("{:02x}", value))
+}
+
+// ADDRESS,LENGTH in hex
+fn parse_range(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16).ok()?,
+    ))
+}
+
+// the read stops at the end of the memory
+fn read_memory(chip: &CHIP8, arguments: &str) -> Option<String> {
+    let (address, length) = parse_range(arguments)?;
+    let memory = chip.memory();
+    if address >= memory.len() {
+        return None;
+    }
+    // the lengths come from the client, a huge one must not overflow
+    let end = address.checked_add(length)?.min(memory.len());
+    Some(
+        memory[address..end]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+    )
+}
+
+fn write_memory(chip: &mut CHIP8, arguments: &str) -> Option<String> {
+    let (range, data) = arguments.split_once(':')?;
+    let (address, length) = parse_range(range)?;
+    if data.len() != length.checked_mul(2)? || address.checked_add(length)? > chip.memory().len() {
+        return None;
+    }
+    for n in 0..length {
+        chip.memory_mut()[address + n] =
+            u8::from_str_radix(data.get(n * 2..n * 2 + 2)?, 16).ok()?;
+    }
+    Some("OK".to_string())
+}
+
+// Z0/z0 are software breakpoints and Z1/z1 hardware ones, there is no difference here,
+// watchpoints are not supported
+fn breakpoint(chip: &mut CHIP8, command: &str) -> Option<String> {
+    let mut parts = command[1..].split(',');
+    let kind = parts.next()?;
+    if kind != "0" && kind != "1" {
+        return None;
+    }
+    let address = usize::from_str_radix(parts.next()?, 16).ok()?;
+    if command.starts_with('Z') {
+        chip.add_breakpoint(address);
+    } else {
+        chip.remove_breakpoint(address);
+    }
+    Some("OK".to_string())
+}
+
+fn target_description() -> String {
+    let mut registers = (0..16)
+        .map(|n| format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", n))
+        .collect::<Vec<String>>();
+    registers.push("<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>".to_string());
+    registers.push("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>".to_string());
+    for name in ["sp", "dt", "st"] {
+        registers.push(format!(
+            "<reg name=\"{}\" bitsize=\"8\" type=\"uint8\"/>",
+            name
+        ));
+    }
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.chip8.core\">{}</feature></target>",
+        registers.join("")
+    )
+}
+
+// OFFSET,LENGTH of the description, m means there is more to read and l that it's the last part
+fn read_target_description(range: &str) -> Option<String> {
+    let (offset, length) = parse_range(range)?;
+    let description = target_description();
+    if offset > description.len() {
+        return None;
+    }
+    let end = offset.checked_add(length)?.min(description.len());
+    let more = if end < description.len() { "m" } else { "l" };
+    Some(format!("{}{}", more, &description[offset..end]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_audio::DummyCHIP8Audio;
+    use crate::chip8_display::DummyCHIP8Display;
+    use crate::chip8_keypad::DummyCHIP8Keypad;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // a scripted GDB, it sends a command and returns the reply
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn read_reply(&mut self) -> String {
+            let mut byte = [0];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            assert_eq!(
+                u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(),
+                checksum_of(&data)
+            );
+            self.stream.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+
+        fn request(&mut self, command: &str) -> String {
+            let packet = format!("${}#{:02x}", command, checksum_of(command.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+            let mut ack = [0];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+            self.read_reply()
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut display = DummyCHIP8Display::new();
+            let mut keypad = DummyCHIP8Keypad::new();
+            let mut audio = DummyCHIP8Audio::new();
+            let mut chip8 = CHIP8::new(
+                &mut display,
+                &mut keypad,
+                &mut audio,
+                Platform::Chip8,
+                Quirks::default(),
+            );
+            chip8.load_from_memory(&[
+                0x60, 0x05, // 200: v0 := 5
+                0xA3, 0x00, // 202: i := 300
+                0x70, 0x01, // 204: v0 += 1
+                0x12, 0x04, // 206: jump 204
+            ]);
+            GdbStub::new(stream).serve(&mut chip8).unwrap();
+            chip8.memory()[0x300..0x302].to_vec()
+        });
+
+        let mut gdb = Client {
+            stream: TcpStream::connect(address).unwrap(),
+        };
+        assert!(gdb
+            .request("qSupported:xmlRegisters=i386")
+            .contains("qXfer"));
+        assert!(gdb
+            .request("qXfer:features:read:target.xml:0,fff")
+            .starts_with("l<?xml"));
+        assert_eq!(gdb.request("?"), "S05");
+        assert_eq!(
+            gdb.request("g"),
+            format!("{}00000200000000", "00".repeat(16))
+        );
+        assert_eq!(gdb.request("s"), "S05");
+        assert_eq!(gdb.request("p0"), "05");
+        assert_eq!(gdb.request("m200,4"), "6005a300");
+        assert_eq!(gdb.request("M300,2:abcd"), "OK");
+        assert_eq!(gdb.request("m300,2"), "abcd");
+        assert_eq!(gdb.request("m10000,2"), "E01");
+        assert_eq!(gdb.request("m1,ffffffffffffffff"), "E01");
+        assert_eq!(gdb.request("M1,ffffffffffffffff:ab"), "E01");
+        assert_eq!(gdb.request("M1,8000000000000001:ab"), "E01");
+        assert_eq!(
+            gdb.request("qXfer:features:read:target.xml:1,ffffffffffffffff"),
+            "E01"
+        );
+
+        assert_eq!(gdb.request("Z0,206,2"), "OK");
+        assert_eq!(gdb.request("c"), "S05");
+        assert_eq!(gdb.request("p11"), "0206");
+        assert_eq!(gdb.request("p10"), "0300");
+        assert_eq!(gdb.request("c"), "S05");
+        assert_eq!(gdb.request("p0"), "07");
+        assert_eq!(gdb.request("z0,206,2"), "OK");
+
+        // the program loops forever until GDB interrupts it
+        assert_eq!(gdb.request("QStartNoAckMode"), "OK");
+        gdb.stream.write_all(b"$c#63").unwrap();
+        gdb.stream.write_all(&[INTERRUPT]).unwrap();
+        let mut reply = [0; 7];
+        gdb.stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"$S02#b5");
+
+        gdb.stream.write_all(b"$D#44").unwrap();
+        let mut reply = [0; 6];
+        gdb.stream.read_exact(&mut reply).unwrap();
+        assert_eq!(&reply, b"$OK#9a");
+        assert_eq!(server.join().unwrap(), vec![0xAB, 0xCD]);
+    }
+}
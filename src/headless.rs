@@ -0,0 +1,196 @@
+use crate::chip8::CHIP8;
+use crate::chip8_error::Chip8Error;
+use crate::chip8_keypad::SharedCHIP8Keypad;
+use crate::instruction::{decode, Address, Instruction};
+use crate::key_schedule::KeySchedule;
+
+// why a headless run ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Frames,      // all the frames have run
+    Exit,        // the program has exited with 00FD
+    EndlessLoop, // a jump to itself, the usual end of test ROMs
+    KeyWait,     // waiting for a key that the schedule never presses
+}
+
+impl StopReason {
+    // the reasons a run can be asked to stop for, the frames and the exit always stop it
+    pub fn from_name(name: &str) -> Option<StopReason> {
+        match name {
+            "loop" => Some(StopReason::EndlessLoop),
+            "key" => Some(StopReason::KeyWait),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RunOutcome {
+    pub frames: u64, // frames run to the end
+    pub reason: StopReason,
+}
+
+// runs a program frame by frame as fast as possible, without a window,
+// the keypad of the machine plays the key schedule
+pub struct HeadlessRun {
+    pub frames: u64, // the most frames to run
+    pub schedule: KeySchedule,
+    pub until: Vec<StopReason>, // what else ends the run
+}
+
+impl HeadlessRun {
+    pub fn new(frames: u64) -> HeadlessRun {
+        HeadlessRun {
+            frames,
+            schedule: KeySchedule::new(),
+            until: Vec::new(),
+        }
+    }
+
+    // on_frame sees the machine after every frame, with the number of frames run so far
+    pub fn run<F>(
+        &self,
+        chip: &mut CHIP8,
+        keypad: &SharedCHIP8Keypad,
+        mut on_frame: F,
+    ) -> Result<RunOutcome, Chip8Error>
+    where
+        F: FnMut(u64, &CHIP8),
+    {
+        let stop_on_loop = self.until.contains(&StopReason::EndlessLoop);
+        let stop_on_key = self.until.contains(&StopReason::KeyWait);
+        let stop = |frames, reason| Ok(RunOutcome { frames, reason });
+        for frame in 0..self.frames {
+            keypad.set_keys(self.schedule.keys_at(frame));
+            let report = match chip.run_frame() {
+                Ok(report) => report,
+                // the strict fault policy stops at the loop
+                Err(Chip8Error::EndlessLoop { .. }) if stop_on_loop => {
+                    return stop(frame, StopReason::EndlessLoop)
+                }
+                Err(error) => return Err(error),
+            };
+            on_frame(frame + 1, chip);
+            if report.halted {
+                return stop(frame + 1, StopReason::Exit);
+            }
+            if stop_on_loop && jumps_to_itself(chip) {
+                return stop(frame + 1, StopReason::EndlessLoop);
+            }
+            if stop_on_key && report.waiting_for_key && !self.schedule.changes_after(frame) {
+                return stop(frame + 1, StopReason::KeyWait);
+            }
+        }
+        stop(self.frames, StopReason::Frames)
+    }
+}
+
+fn jumps_to_itself(chip: &CHIP8) -> bool {
+    let (memory, ca) = (chip.memory(), chip.program_counter());
+    if ca + 1 >= memory.len() {
+        return false;
+    }
+    let instruction = decode((memory[ca] as u16) << 8 | memory[ca + 1] as u16);
+    instruction
+        == Instruction::Jump {
+            address: Address(ca as u16),
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_audio::DummyCHIP8Audio;
+    use crate::chip8_display::DummyCHIP8Display;
+    use crate::fault_policy::FaultPolicy;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    fn run(program: &[u8], run: &HeadlessRun, fault_policy: FaultPolicy) -> (RunOutcome, u8) {
+        let mut display = DummyCHIP8Display::new();
+        let keypad = SharedCHIP8Keypad::new();
+        let mut chip_keypad = keypad.clone();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut chip_keypad,
+            &mut audio,
+            Platform::SuperChip,
+            Quirks::default(),
+        );
+        chip8.set_fault_policy(fault_policy);
+        chip8.load_from_memory(program);
+        let mut frames = Vec::new();
+        let outcome = run
+            .run(&mut chip8, &keypad, |frame, _| frames.push(frame))
+            .unwrap();
+        assert_eq!(frames, (1..=frames.len() as u64).collect::<Vec<u64>>());
+        (outcome, chip8.registers()[0])
+    }
+
+    #[test]
+    fn test_headless_run() {
+        let program = [
+            0xF0, 0x0A, // 200: LD V0, K
+            0x30, 0x05, // 202: SE V0, #05
+            0x12, 0x00, // 204: JP #200
+            0x00, 0xFD, // 206: EXIT
+        ];
+        let mut headless = HeadlessRun::new(100);
+        headless.until.push(StopReason::KeyWait);
+        // nothing is pressed
+        let (outcome, _) = run(&program, &headless, FaultPolicy::default());
+        assert_eq!(
+            outcome,
+            RunOutcome {
+                frames: 1,
+                reason: StopReason::KeyWait
+            }
+        );
+
+        // 4 is pressed and released first, then 5
+        headless.schedule = KeySchedule::parse("10 4\n12 -\n20 5\n22 -").unwrap();
+        let (outcome, v0) = run(&program, &headless, FaultPolicy::default());
+        assert_eq!(outcome.reason, StopReason::Exit);
+        assert_eq!(outcome.frames, 23);
+        assert_eq!(v0, 5);
+
+        headless.frames = 15;
+        let (outcome, v0) = run(&program, &headless, FaultPolicy::default());
+        assert_eq!(
+            outcome,
+            RunOutcome {
+                frames: 15,
+                reason: StopReason::Frames
+            }
+        );
+        assert_eq!(v0, 4);
+    }
+
+    #[test]
+    fn test_endless_loop() {
+        let program = [
+            0x60, 0x05, // 200: LD V0, #05
+            0x12, 0x02, // 202: JP #202
+        ];
+        let mut headless = HeadlessRun::new(100);
+        headless.until.push(StopReason::from_name("loop").unwrap());
+        let (outcome, _) = run(&program, &headless, FaultPolicy::default());
+        assert_eq!(
+            outcome,
+            RunOutcome {
+                frames: 0,
+                reason: StopReason::EndlessLoop
+            }
+        );
+        // the lenient policy keeps looping to the end of the frame
+        let (outcome, _) = run(&program, &headless, FaultPolicy::lenient());
+        assert_eq!(
+            outcome,
+            RunOutcome {
+                frames: 1,
+                reason: StopReason::EndlessLoop
+            }
+        );
+    }
+}
@@ -0,0 +1,453 @@
+use std::fmt;
+
+use crate::platform::Platform;
+
+// the operands get their own types, so a register can't be passed where a byte belongs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Register(pub u8); // index of V0 - VF
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Address(pub u16); // NNN
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Byte(pub u8); // NN
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Nibble(pub u8); // N
+
+impl Register {
+    // the index into V
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// the listings print the operands like the numbers they wrap
+macro_rules! impl_operand_formatting {
+    ($($operand:ident),*) => {
+        $(
+            impl fmt::Display for $operand {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    fmt::Display::fmt(&self.0, f)
+                }
+            }
+
+            impl fmt::UpperHex for $operand {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    fmt::UpperHex::fmt(&self.0, f)
+                }
+            }
+
+            impl fmt::LowerHex for $operand {
+                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                    fmt::LowerHex::fmt(&self.0, f)
+                }
+            }
+        )*
+    };
+}
+
+impl_operand_formatting!(Register, Address, Byte, Nibble);
+
+// every instruction of CHIP-8, SUPER-CHIP 1.1 and XO-CHIP
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    // 0NNN, routines of the original interpreter
+    MachineCode {
+        address: Address,
+    },
+    // 00E0
+    ClearScreen,
+    // 00EE
+    Return,
+    // 00CN, SUPER-CHIP
+    ScrollDown {
+        rows: Nibble,
+    },
+    // 00DN, XO-CHIP
+    ScrollUp {
+        rows: Nibble,
+    },
+    // 00FB, SUPER-CHIP
+    ScrollRight,
+    // 00FC, SUPER-CHIP
+    ScrollLeft,
+    // 00FD, SUPER-CHIP
+    Exit,
+    // 00FE, SUPER-CHIP
+    LowResolution,
+    // 00FF, SUPER-CHIP
+    HighResolution,
+    // 1NNN
+    Jump {
+        address: Address,
+    },
+    // 2NNN
+    Call {
+        address: Address,
+    },
+    // 3XNN
+    SkipIfEqual {
+        x: Register,
+        byte: Byte,
+    },
+    // 4XNN
+    SkipIfNotEqual {
+        x: Register,
+        byte: Byte,
+    },
+    // 5XY0
+    SkipIfRegistersEqual {
+        x: Register,
+        y: Register,
+    },
+    // 5XY2, XO-CHIP
+    SaveRange {
+        x: Register,
+        y: Register,
+    },
+    // 5XY3, XO-CHIP
+    LoadRange {
+        x: Register,
+        y: Register,
+    },
+    // 6XNN
+    Load {
+        x: Register,
+        byte: Byte,
+    },
+    // 7XNN
+    Add {
+        x: Register,
+        byte: Byte,
+    },
+    // 8XY0
+    Move {
+        x: Register,
+        y: Register,
+    },
+    // 8XY1
+    Or {
+        x: Register,
+        y: Register,
+    },
+    // 8XY2
+    And {
+        x: Register,
+        y: Register,
+    },
+    // 8XY3
+    Xor {
+        x: Register,
+        y: Register,
+    },
+    // 8XY4
+    AddRegisters {
+        x: Register,
+        y: Register,
+    },
+    // 8XY5
+    Subtract {
+        x: Register,
+        y: Register,
+    },
+    // 8XY6
+    ShiftRight {
+        x: Register,
+        y: Register,
+    },
+    // 8XY7
+    SubtractReversed {
+        x: Register,
+        y: Register,
+    },
+    // 8XYE
+    ShiftLeft {
+        x: Register,
+        y: Register,
+    },
+    // 9XY0
+    SkipIfRegistersNotEqual {
+        x: Register,
+        y: Register,
+    },
+    // ANNN
+    LoadIndex {
+        address: Address,
+    },
+    // BNNN, BXNN with the jump quirk
+    JumpWithOffset {
+        address: Address,
+    },
+    // CXNN
+    Random {
+        x: Register,
+        byte: Byte,
+    },
+    // DXYN
+    Draw {
+        x: Register,
+        y: Register,
+        height: Nibble,
+    },
+    // EX9E
+    SkipIfKey {
+        x: Register,
+    },
+    // EXA1
+    SkipIfNotKey {
+        x: Register,
+    },
+    // F000 NNNN, XO-CHIP, the address is the next word
+    LoadLongIndex,
+    // FN01, XO-CHIP
+    SelectPlanes {
+        planes: Nibble,
+    },
+    // F002, XO-CHIP
+    LoadAudioPattern,
+    // FX07
+    GetDelayTimer {
+        x: Register,
+    },
+    // FX0A
+    WaitForKey {
+        x: Register,
+    },
+    // FX15
+    SetDelayTimer {
+        x: Register,
+    },
+    // FX18
+    SetSoundTimer {
+        x: Register,
+    },
+    // FX1E
+    AddIndex {
+        x: Register,
+    },
+    // FX29
+    LoadFont {
+        x: Register,
+    },
+    // FX30, SUPER-CHIP
+    LoadLargeFont {
+        x: Register,
+    },
+    // FX3A, XO-CHIP
+    SetPitch {
+        x: Register,
+    },
+    // FX33
+    StoreDecimal {
+        x: Register,
+    },
+    // FX55
+    StoreRegisters {
+        x: Register,
+    },
+    // FX65
+    LoadRegisters {
+        x: Register,
+    },
+    // FX75, SUPER-CHIP
+    SaveFlags {
+        x: Register,
+    },
+    // FX85, SUPER-CHIP
+    LoadFlags {
+        x: Register,
+    },
+    Unknown {
+        opcode: u16,
+    },
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+    let x = Register(((opcode >> 8) & 0xF) as u8); // second nymble
+    let y = Register(((opcode >> 4) & 0xF) as u8); // third nymble
+    let n = Nibble((opcode & 0xF) as u8); // last nymble
+    let byte = Byte((opcode & 0xFF) as u8);
+    let address = Address(opcode & 0xFFF);
+    match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => Instruction::ClearScreen,
+            0x00EE => Instruction::Return,
+            0x00C1..=0x00CF => Instruction::ScrollDown { rows: n },
+            0x00D1..=0x00DF => Instruction::ScrollUp { rows: n },
+            0x00FB => Instruction::ScrollRight,
+            0x00FC => Instruction::ScrollLeft,
+            0x00FD => Instruction::Exit,
+            0x00FE => Instruction::LowResolution,
+            0x00FF => Instruction::HighResolution,
+            0x0100..=0x0FFF => Instruction::MachineCode { address },
+            _ => Instruction::Unknown { opcode },
+        },
+        0x1 => Instruction::Jump { address },
+        0x2 => Instruction::Call { address },
+        0x3 => Instruction::SkipIfEqual { x, byte },
+        0x4 => Instruction::SkipIfNotEqual { x, byte },
+        0x5 => match n.0 {
+            0x0 => Instruction::SkipIfRegistersEqual { x, y },
+            0x2 => Instruction::SaveRange { x, y },
+            0x3 => Instruction::LoadRange { x, y },
+            _ => Instruction::Unknown { opcode },
+        },
+        0x6 => Instruction::Load { x, byte },
+        0x7 => Instruction::Add { x, byte },
+        0x8 => match n.0 {
+            0x0 => Instruction::Move { x, y },
+            0x1 => Instruction::Or { x, y },
+            0x2 => Instruction::And { x, y },
+            0x3 => Instruction::Xor { x, y },
+            0x4 => Instruction::AddRegisters { x, y },
+            0x5 => Instruction::Subtract { x, y },
+            0x6 => Instruction::ShiftRight { x, y },
+            0x7 => Instruction::SubtractReversed { x, y },
+            0xE => Instruction::ShiftLeft { x, y },
+            _ => Instruction::Unknown { opcode },
+        },
+        0x9 if n.0 == 0 => Instruction::SkipIfRegistersNotEqual { x, y },
+        0xA => Instruction::LoadIndex { address },
+        0xB => Instruction::JumpWithOffset { address },
+        0xC => Instruction::Random { x, byte },
+        0xD => Instruction::Draw { x, y, height: n },
+        0xE => match byte.0 {
+            0x9E => Instruction::SkipIfKey { x },
+            0xA1 => Instruction::SkipIfNotKey { x },
+            _ => Instruction::Unknown { opcode },
+        },
+        0xF => match byte.0 {
+            0x00 if x.0 == 0 => Instruction::LoadLongIndex,
+            0x01 => Instruction::SelectPlanes {
+                planes: Nibble(x.0),
+            },
+            0x02 if x.0 == 0 => Instruction::LoadAudioPattern,
+            0x07 => Instruction::GetDelayTimer { x },
+            0x0A => Instruction::WaitForKey { x },
+            0x15 => Instruction::SetDelayTimer { x },
+            0x18 => Instruction::SetSoundTimer { x },
+            0x1E => Instruction::AddIndex { x },
+            0x29 => Instruction::LoadFont { x },
+            0x30 => Instruction::LoadLargeFont { x },
+            0x33 => Instruction::StoreDecimal { x },
+            0x3A => Instruction::SetPitch { x },
+            0x55 => Instruction::StoreRegisters { x },
+            0x65 => Instruction::LoadRegisters { x },
+            0x75 => Instruction::SaveFlags { x },
+            0x85 => Instruction::LoadFlags { x },
+            _ => Instruction::Unknown { opcode },
+        },
+        _ => Instruction::Unknown { opcode },
+    }
+}
+
+impl Instruction {
+    // whether the platform has the instruction, the rest are illegal on it
+    pub fn is_available_on(&self, platform: Platform) -> bool {
+        match self {
+            Instruction::ScrollDown { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::LowResolution
+            | Instruction::HighResolution
+            | Instruction::LoadLargeFont { .. }
+            | Instruction::SaveFlags { .. }
+            | Instruction::LoadFlags { .. } => platform != Platform::Chip8,
+            Instruction::ScrollUp { .. }
+            | Instruction::SaveRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::LoadLongIndex
+            | Instruction::SelectPlanes { .. }
+            | Instruction::LoadAudioPattern
+            | Instruction::SetPitch { .. } => platform == Platform::XoChip,
+            Instruction::Unknown { .. } => false,
+            _ => true,
+        }
+    }
+
+    // the size in memory, only the long F000 NNNN instruction takes 4 bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::LoadLongIndex => 4,
+            _ => 2,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            decode(0x0420),
+            Instruction::MachineCode {
+                address: Address(0x420)
+            }
+        );
+        assert_eq!(decode(0x00E0), Instruction::ClearScreen);
+        assert_eq!(decode(0x00C5), Instruction::ScrollDown { rows: Nibble(5) });
+        assert_eq!(decode(0x00C0), Instruction::Unknown { opcode: 0x00C0 });
+        assert_eq!(
+            decode(0x1ABC),
+            Instruction::Jump {
+                address: Address(0xABC)
+            }
+        );
+        assert_eq!(
+            decode(0x3A42),
+            Instruction::SkipIfEqual {
+                x: Register(0xA),
+                byte: Byte(0x42)
+            }
+        );
+        assert_eq!(
+            decode(0x5123),
+            Instruction::LoadRange {
+                x: Register(1),
+                y: Register(2)
+            }
+        );
+        assert_eq!(decode(0x5124), Instruction::Unknown { opcode: 0x5124 });
+        assert_eq!(
+            decode(0x8AB6),
+            Instruction::ShiftRight {
+                x: Register(0xA),
+                y: Register(0xB)
+            }
+        );
+        assert_eq!(decode(0x9AB1), Instruction::Unknown { opcode: 0x9AB1 });
+        assert_eq!(
+            decode(0xD12F),
+            Instruction::Draw {
+                x: Register(1),
+                y: Register(2),
+                height: Nibble(0xF)
+            }
+        );
+        assert_eq!(decode(0xE3A1), Instruction::SkipIfNotKey { x: Register(3) });
+        assert_eq!(decode(0xF000), Instruction::LoadLongIndex);
+        assert_eq!(decode(0xF100), Instruction::Unknown { opcode: 0xF100 });
+        assert_eq!(
+            decode(0xF201),
+            Instruction::SelectPlanes { planes: Nibble(2) }
+        );
+        assert_eq!(
+            decode(0xF765),
+            Instruction::LoadRegisters { x: Register(7) }
+        );
+    }
+
+    #[test]
+    fn test_availability() {
+        assert!(Instruction::ClearScreen.is_available_on(Platform::Chip8));
+        assert!(!Instruction::Exit.is_available_on(Platform::Chip8));
+        assert!(Instruction::Exit.is_available_on(Platform::SuperChip));
+        assert!(!Instruction::LoadLongIndex.is_available_on(Platform::SuperChip));
+        assert!(Instruction::LoadLongIndex.is_available_on(Platform::XoChip));
+        assert!(!Instruction::Unknown { opcode: 0xFFFF }.is_available_on(Platform::XoChip));
+    }
+}
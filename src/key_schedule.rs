@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+// the keys held down from a frame on, for running programs without a keyboard
+// in the text form a line has a frame number and the hex keys held from that frame,
+// - for none, and # starts a comment:
+// 120 5     hold 5 from the frame 120
+// 125 -     release it
+// 130 4 6   hold 4 and 6
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeySchedule {
+    changes: BTreeMap<u64, u16>, // frame -> keys, bit N for the key N
+}
+
+impl KeySchedule {
+    pub fn new() -> KeySchedule {
+        KeySchedule {
+            changes: BTreeMap::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<KeySchedule, String> {
+        let mut schedule = KeySchedule::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut columns = line.split_whitespace();
+            let Some(frame) = columns.next() else {
+                continue;
+            };
+            let error = || format!("Line {}: expected a frame and keys", number + 1);
+            let frame = frame.parse().map_err(|_| error())?;
+            let mut keys = 0;
+            for column in columns {
+                if column == "-" {
+                    continue;
+                }
+                for digit in column.chars() {
+                    keys |= 1 << digit.to_digit(16).ok_or_else(error)?;
+                }
+            }
+            schedule.set_keys(frame, keys);
+        }
+        Ok(schedule)
+    }
+
+    pub fn set_keys(&mut self, frame: u64, keys: u16) {
+        self.changes.insert(frame, keys);
+    }
+
+    // nothing is held before the first change
+    pub fn keys_at(&self, frame: u64) -> u16 {
+        self.changes
+            .range(..=frame)
+            .next_back()
+            .map_or(0, |(_, &keys)| keys)
+    }
+
+    // whether the keys change after the frame
+    pub fn changes_after(&self, frame: u64) -> bool {
+        self.changes.range(frame + 1..).next().is_some()
+    }
+
+    pub fn changes(&self) -> impl Iterator<Item = (u64, u16)> + '_ {
+        self.changes.iter().map(|(&frame, &keys)| (frame, keys))
+    }
+
+    // the text form, parse reads it back
+    pub fn format(&self) -> String {
+        self.changes
+            .iter()
+            .map(|(frame, &keys)| {
+                let held = (0..16)
+                    .filter(|key| keys & 1 << key != 0)
+                    .map(|key| format!("{:X}", key))
+                    .collect::<Vec<String>>();
+                if held.is_empty() {
+                    format!("{} -\n", frame)
+                } else {
+                    format!("{} {}\n", frame, held.join(" "))
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_schedule() {
+        let schedule = KeySchedule::parse(
+            "# start the game\n\
+             120 5\n\
+             125 -   # release\n\
+             \n\
+             130 4 6\n\
+             140 AF\n",
+        )
+        .unwrap();
+        assert_eq!(schedule.keys_at(0), 0);
+        assert_eq!(schedule.keys_at(120), 1 << 5);
+        assert_eq!(schedule.keys_at(124), 1 << 5);
+        assert_eq!(schedule.keys_at(125), 0);
+        assert_eq!(schedule.keys_at(130), 1 << 4 | 1 << 6);
+        assert_eq!(schedule.keys_at(1000), 1 << 0xA | 1 << 0xF);
+        assert!(schedule.changes_after(130));
+        assert!(!schedule.changes_after(140));
+
+        assert_eq!(
+            schedule.format(),
+            "120 5\n125 -\n130 4 6\n140 A F\n".to_string()
+        );
+        assert_eq!(KeySchedule::parse(&schedule.format()), Ok(schedule));
+
+        assert_eq!(
+            KeySchedule::parse("10 5\nx 4"),
+            Err("Line 2: expected a frame and keys".to_string())
+        );
+        assert!(KeySchedule::parse("10 G").is_err());
+    }
+}
@@ -0,0 +1,25 @@
+pub mod assembler;
+mod binary_parser;
+pub mod chip8;
+pub mod chip8_audio;
+pub mod chip8_display;
+pub mod chip8_error;
+pub mod chip8_keypad;
+pub mod dap_server;
+pub mod debugger;
+pub mod disassembler;
+pub mod fault_policy;
+pub mod frame_clock;
+pub mod gdb_stub;
+pub mod headless;
+pub mod instruction;
+pub mod key_schedule;
+pub mod movie;
+pub mod platform;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+pub mod save_state;
+pub mod screenshot;
+pub mod trace;
+pub mod trace_diff;
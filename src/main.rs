@@ -1,4 +1,8 @@
-use pixels::wgpu::Color;
+use std::cell::{Cell, RefCell};
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::rc::Rc;
+
 use pixels::{Pixels, SurfaceTexture};
 use winit::dpi::LogicalSize;
 use winit::event::{Event, VirtualKeyCode};
@@ -6,24 +10,350 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 use winit_input_helper::WinitInputHelper;
 
-mod binary_parser;
-mod chip8;
-mod chip8_display;
+use old_rusty_platforms::chip8_display::{
+    CHIP8Display, DummyCHIP8Display, VideoMemory, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH,
+    SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+use old_rusty_platforms::chip8_keypad::{self, CHIP8Keypad};
+use old_rusty_platforms::debugger::Debugger;
+use old_rusty_platforms::disassembler::Syntax;
+use old_rusty_platforms::gdb_stub::GdbStub;
+use old_rusty_platforms::movie::Movie;
+use old_rusty_platforms::save_state::MachineState;
+use old_rusty_platforms::trace::{self, TraceFilter, TraceWriter};
+use old_rusty_platforms::{chip8, chip8_audio, fault_policy, frame_clock, platform, quirks};
+
+// a line at a time, the window mode only ends with the process
+type FileTracer = TraceWriter<LineWriter<File>>;
+
+const SCALE: f64 = 10.0;
+const REWIND_BUDGET: usize = 16 << 20; // bytes of snapshots, minutes of most programs
+const OFF_COLOR: [u8; 4] = [0x00, 0x00, 0x00, 0xFF];
+// indexed by the pixel value, the last two colors are only used by the XO-CHIP planes
+const PALETTE: [[u8; 4]; 4] = [
+    OFF_COLOR,
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+];
+
+// the usual layout of the hex keypad on a QWERTY keyboard
+// 1 2 3 C      1 2 3 4
+// 4 5 6 D  ->  Q W E R
+// 7 8 9 E      A S D F
+// A 0 B F      Z X C V
+const KEY_MAP: [(VirtualKeyCode, u8); 16] = [
+    (VirtualKeyCode::Key1, 0x1),
+    (VirtualKeyCode::Key2, 0x2),
+    (VirtualKeyCode::Key3, 0x3),
+    (VirtualKeyCode::Key4, 0xC),
+    (VirtualKeyCode::Q, 0x4),
+    (VirtualKeyCode::W, 0x5),
+    (VirtualKeyCode::E, 0x6),
+    (VirtualKeyCode::R, 0xD),
+    (VirtualKeyCode::A, 0x7),
+    (VirtualKeyCode::S, 0x8),
+    (VirtualKeyCode::D, 0x9),
+    (VirtualKeyCode::F, 0xE),
+    (VirtualKeyCode::Z, 0xA),
+    (VirtualKeyCode::X, 0x0),
+    (VirtualKeyCode::C, 0xB),
+    (VirtualKeyCode::V, 0xF),
+];
+
+// draws the video memory into the pixels frame, the event loop renders it
+// the frame has the high resolution size, low resolution pixels are drawn as 2x2 blocks
+struct PixelsDisplay {
+    pixels: Rc<RefCell<Pixels>>,
+}
+
+impl CHIP8Display for PixelsDisplay {
+    fn clear(&mut self) {
+        for pixel in self.pixels.borrow_mut().frame_mut().chunks_exact_mut(4) {
+            pixel.copy_from_slice(&OFF_COLOR);
+        }
+    }
+
+    fn update(&mut self, video_memory: &VideoMemory) {
+        let scale = HIRES_SCREEN_WIDTH / video_memory.width();
+        let mut pixels = self.pixels.borrow_mut();
+        for (i, pixel) in pixels.frame_mut().chunks_exact_mut(4).enumerate() {
+            let x = i % HIRES_SCREEN_WIDTH / scale;
+            let y = i / HIRES_SCREEN_WIDTH / scale;
+            pixel.copy_from_slice(&PALETTE[video_memory[y][x] as usize]);
+        }
+    }
+}
+
+// reports the keys the event loop saw held down
+struct WindowKeypad {
+    keys: Rc<Cell<u16>>,
+}
+
+impl CHIP8Keypad for WindowKeypad {
+    fn pressed_keys(&mut self) -> u16 {
+        self.keys.get()
+    }
+}
+
+fn debug(
+    platform: platform::Platform,
+    quirks: quirks::Quirks,
+    fault_policy: fault_policy::FaultPolicy,
+    tracer: Option<&mut FileTracer>,
+    rom_path: &str,
+) {
+    let mut debugger = Debugger::new(Syntax::Cowgod);
+    let mut display = DummyCHIP8Display::new();
+    let mut keypad = debugger.keypad();
+    let mut audio = chip8_audio::DummyCHIP8Audio::new();
+    let mut chip = chip8::CHIP8::new(&mut display, &mut keypad, &mut audio, platform, quirks);
+    chip.set_fault_policy(fault_policy);
+    if let Some(tracer) = tracer {
+        chip.set_tracer(tracer);
+    }
+    if let Err(error) = chip.load_from_file(rom_path) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+    println!("{}", debugger.execute(&mut chip, "dis"));
+    let mut line = String::new();
+    while !debugger.is_finished() {
+        print!("(chip8) ");
+        std::io::stdout().flush().unwrap();
+        line.clear();
+        if std::io::stdin().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        let output = debugger.execute(&mut chip, &line);
+        if !output.is_empty() {
+            println!("{}", output);
+        }
+    }
+}
+
+// a port number listens on localhost, anything else is the path of a Unix socket,
+// which only Unix has
+fn serve_gdb(
+    platform: platform::Platform,
+    quirks: quirks::Quirks,
+    fault_policy: fault_policy::FaultPolicy,
+    tracer: Option<&mut FileTracer>,
+    rom_path: &str,
+    address: &str,
+) {
+    let mut display = DummyCHIP8Display::new();
+    let mut keypad = chip8_keypad::DummyCHIP8Keypad::new();
+    let mut audio = chip8_audio::DummyCHIP8Audio::new();
+    let mut chip = chip8::CHIP8::new(&mut display, &mut keypad, &mut audio, platform, quirks);
+    chip.set_fault_policy(fault_policy);
+    if let Some(tracer) = tracer {
+        chip.set_tracer(tracer);
+    }
+    if let Err(error) = chip.load_from_file(rom_path) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+    println!("Waiting for GDB on {}", address);
+    let result = match address.parse::<u16>() {
+        Ok(port) => std::net::TcpListener::bind(("127.0.0.1", port))
+            .and_then(|listener| listener.accept())
+            .and_then(|(stream, _)| GdbStub::new(stream).serve(&mut chip)),
+        #[cfg(unix)]
+        Err(_) => std::os::unix::net::UnixListener::bind(address)
+            .and_then(|listener| listener.accept())
+            .and_then(|(stream, _)| GdbStub::new(stream).serve(&mut chip)),
+        // there are no Unix sockets elsewhere
+        #[cfg(not(unix))]
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{} is not a port", address),
+        )),
+    };
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
+
+fn held_keys(input: &WinitInputHelper) -> u16 {
+    KEY_MAP
+        .iter()
+        .filter(|(code, _)| input.key_held(*code))
+        .fold(0, |keys, (_, key)| keys | 1 << key)
+}
+
+// a movie being recorded or played in the window,
+// the rewind and F9 are off while it runs because they would change the session
+enum MovieMode {
+    Record { movie: Movie, path: String },
+    Replay { movie: Movie, frame: u64 },
+}
+
+impl MovieMode {
+    // the keys the machine sees in the next frame
+    fn keys(&self, held: u16) -> u16 {
+        match self {
+            MovieMode::Record { .. } => held,
+            MovieMode::Replay { movie, frame } => movie.keys.keys_at(*frame),
+        }
+    }
+
+    // after every frame the machine ran, false stops the machine
+    fn frame_done(&mut self, keys: u16, chip: &chip8::CHIP8) -> bool {
+        match self {
+            MovieMode::Record { movie, .. } => {
+                movie.record_frame(keys, chip);
+                true
+            }
+            MovieMode::Replay { movie, frame } => {
+                *frame += 1;
+                if let Err(error) = movie.check_frame(*frame, chip) {
+                    eprintln!("{}", error);
+                    return false;
+                }
+                if *frame == movie.frames {
+                    println!("The movie has ended after {} frames", frame);
+                    return false;
+                }
+                true
+            }
+        }
+    }
+
+    // a recording is written when the window closes
+    fn finish(&self) {
+        if let MovieMode::Record { movie, path } = self {
+            if let Err(error) = std::fs::write(path, movie.format()) {
+                eprintln!("Can't write {}: {}", path, error);
+            }
+        }
+    }
+}
+
+// the speaker with the sound feature, the window stays silent without it
+#[cfg(feature = "sound")]
+fn window_audio() -> chip8_audio::SpeakerCHIP8Audio {
+    chip8_audio::SpeakerCHIP8Audio::open().unwrap_or_else(|error| {
+        eprintln!("no sound: {}", error);
+        chip8_audio::SpeakerCHIP8Audio::silent()
+    })
+}
+
+#[cfg(not(feature = "sound"))]
+fn window_audio() -> chip8_audio::DummyCHIP8Audio {
+    chip8_audio::DummyCHIP8Audio::new()
+}
+
+fn read_rom(rom_path: &str) -> Vec<u8> {
+    std::fs::read(rom_path).unwrap_or_else(|error| {
+        eprintln!("Can't load {}: {}", rom_path, error);
+        std::process::exit(1);
+    })
+}
+
+fn load_movie(path: &str) -> Movie {
+    let movie = std::fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| Movie::parse(&text));
+    movie.unwrap_or_else(|error| {
+        eprintln!("Can't load {}: {}", path, error);
+        std::process::exit(1);
+    })
+}
 
 fn main() {
-    let mut dummy_display = chip8_display::DummyCHIP8Display::new();
-    let mut chip: chip8::CHIP8 = chip8::CHIP8::new(&mut dummy_display);
-    chip.load_from_file("Cargo.toml");
-    chip.print_first_16_bytes_of_ram();
-    let x: u8 = rand::random();
-    println!("{}", x);
+    let usage = "Usage: old_rusty_platforms [--platform chip8|schip|xochip] [--quirks default|vip|chip48|schip|xochip] [--faults strict|lenient] [--debug] [--gdb PORT|SOCKET] [--trace FILE] [--trace-addresses START-END] [--trace-cycles START-END] [--record MOVIE|--replay MOVIE] <ROM>";
+    let mut args = std::env::args().skip(1);
+    let mut platform = platform::Platform::Chip8;
+    let mut quirks = None;
+    let mut fault_policy = None;
+    let mut debug_mode = false;
+    let mut gdb_address = None;
+    let mut trace_path = None;
+    let mut trace_filter = TraceFilter::default();
+    let mut record_path = None;
+    let mut replay_path = None;
+    let mut rom_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => {
+                platform = platform::Platform::from_name(&args.next().expect(usage)).expect(usage)
+            }
+            "--quirks" => {
+                quirks = Some(quirks::Quirks::from_name(&args.next().expect(usage)).expect(usage))
+            }
+            "--faults" => {
+                fault_policy = Some(
+                    fault_policy::FaultPolicy::from_name(&args.next().expect(usage)).expect(usage),
+                )
+            }
+            "--debug" => debug_mode = true,
+            "--gdb" => gdb_address = Some(args.next().expect(usage)),
+            "--trace" => trace_path = Some(args.next().expect(usage)),
+            "--trace-addresses" => {
+                trace_filter.addresses =
+                    Some(trace::parse_address_range(&args.next().expect(usage)).expect(usage))
+            }
+            "--trace-cycles" => {
+                trace_filter.cycles =
+                    Some(trace::parse_cycle_range(&args.next().expect(usage)).expect(usage))
+            }
+            "--record" => record_path = Some(args.next().expect(usage)),
+            "--replay" => replay_path = Some(args.next().expect(usage)),
+            _ => rom_path = Some(arg),
+        }
+    }
+    let rom_path = rom_path.expect(usage);
+    let mut quirks = quirks.unwrap_or(platform.default_quirks());
+    let replay = replay_path.map(|path| {
+        let movie = load_movie(&path);
+        if let Err(error) = movie.check_rom(&read_rom(&rom_path)) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        // the machine of the recording
+        platform = movie.platform;
+        quirks = movie.quirks;
+        movie
+    });
+    let mut tracer = trace_path.map(|path| match File::create(&path) {
+        Ok(file) => TraceWriter::new(LineWriter::new(file), Syntax::Cowgod, trace_filter),
+        Err(error) => {
+            eprintln!("Can't create {}: {}", path, error);
+            std::process::exit(1);
+        }
+    });
+    // debugging stops at every fault, playing goes on like the original interpreters,
+    // which also keeps the usual 1NNN jump to itself at the end of a program from stopping it
+    let fault_policy = fault_policy.unwrap_or(if debug_mode || gdb_address.is_some() {
+        fault_policy::FaultPolicy::strict()
+    } else {
+        fault_policy::FaultPolicy::lenient()
+    });
+    if debug_mode {
+        debug(platform, quirks, fault_policy, tracer.as_mut(), &rom_path);
+        return;
+    }
+    if let Some(address) = gdb_address {
+        serve_gdb(
+            platform,
+            quirks,
+            fault_policy,
+            tracer.as_mut(),
+            &rom_path,
+            &address,
+        );
+        return;
+    }
 
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
 
     let window = {
-        let size = LogicalSize::new(400.0, 300.0);
-        let scaled_size = LogicalSize::new(400.0 * 3.0, 300.0 * 3.0);
+        let size = LogicalSize::new(SCREEN_WIDTH as f64, SCREEN_HEIGHT as f64);
+        let scaled_size =
+            LogicalSize::new(SCREEN_WIDTH as f64 * SCALE, SCREEN_HEIGHT as f64 * SCALE);
         WindowBuilder::new()
             .with_title("Rusty Platforms - CHIP-8")
             .with_inner_size(scaled_size)
@@ -32,17 +362,68 @@ fn main() {
             .unwrap()
     };
 
-    let mut pixels = {
+    let pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(400, 300, surface_texture).unwrap()
+        let pixels = Pixels::new(
+            HIRES_SCREEN_WIDTH as u32,
+            HIRES_SCREEN_HEIGHT as u32,
+            surface_texture,
+        )
+        .unwrap();
+        Rc::new(RefCell::new(pixels))
+    };
+    let keys = Rc::new(Cell::new(0));
+
+    // the machine borrows its display and keypad for as long as the event loop runs,
+    // which is the rest of the program
+    let display = Box::leak(Box::new(PixelsDisplay {
+        pixels: Rc::clone(&pixels),
+    }));
+    let keypad = Box::leak(Box::new(WindowKeypad {
+        keys: Rc::clone(&keys),
+    }));
+    let audio = Box::leak(Box::new(window_audio()));
+    let mut chip = chip8::CHIP8::new(display, keypad, audio, platform, quirks);
+    chip.set_fault_policy(fault_policy);
+    chip.enable_rewind(REWIND_BUDGET);
+    if let Some(tracer) = tracer {
+        chip.set_tracer(Box::leak(Box::new(tracer)));
+    }
+    if let Err(error) = chip.load_from_file(&rom_path) {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+    // a recording starts with the machine as it is now
+    let mut movie_mode = match (record_path, replay) {
+        (Some(path), _) => Some(MovieMode::Record {
+            movie: Movie::new(&read_rom(&rom_path), &chip),
+            path,
+        }),
+        (None, Some(movie)) => {
+            movie.start(&mut chip);
+            Some(MovieMode::Replay { movie, frame: 0 })
+        }
+        (None, None) => None,
     };
 
-    pixels.clear_color(Color::BLUE);
+    // F5 saves the machine next to the ROM, F9 continues from there
+    let state_path = format!("{}.state", rom_path);
+
+    let mut clock = frame_clock::FrameClock::new(chip8::FRAME_RATE);
+    // the machine stops on the first error the fault policy lets through,
+    // the window stays open with the last picture
+    let mut stopped = false;
 
     event_loop.run(move |event, _, control_flow| {
+        if let Event::LoopDestroyed = event {
+            if let Some(mode) = &movie_mode {
+                mode.finish();
+            }
+            return;
+        }
         if let Event::RedrawRequested(_) = event {
-            if pixels.render().is_err() {
+            if pixels.borrow().render().is_err() {
                 *control_flow = ControlFlow::Exit;
                 return;
             }
@@ -52,7 +433,60 @@ fn main() {
             // Close events
             if input.key_pressed(VirtualKeyCode::Escape) || input.close_requested() {
                 *control_flow = ControlFlow::Exit;
+                return;
+            }
+
+            if let Some(size) = input.window_resized() {
+                if pixels
+                    .borrow_mut()
+                    .resize_surface(size.width, size.height)
+                    .is_err()
+                {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+            }
+
+            if input.key_pressed(VirtualKeyCode::F5) {
+                if let Err(error) = chip.save_state().save_to_file(&state_path) {
+                    eprintln!("{}", error);
+                }
+            }
+            if input.key_pressed(VirtualKeyCode::F9) && movie_mode.is_none() {
+                let restored = MachineState::load_from_file(&state_path)
+                    .and_then(|state| chip.restore_state(&state));
+                match restored {
+                    Ok(()) => stopped = false,
+                    Err(error) => eprintln!("{}", error),
+                }
+            }
+
+            let held = held_keys(&input);
+            // holding backspace plays the frames backwards
+            let rewinding = input.key_held(VirtualKeyCode::Back) && movie_mode.is_none();
+            for _ in 0..clock.frames_due() {
+                if rewinding {
+                    chip.rewind(1);
+                    stopped = false;
+                    continue;
+                }
+                if stopped {
+                    break;
+                }
+                keys.set(movie_mode.as_ref().map_or(held, |mode| mode.keys(held)));
+                if let Err(error) = chip.run_frame() {
+                    eprintln!("{}", error);
+                    stopped = true;
+                    break;
+                }
+                if let Some(mode) = &mut movie_mode {
+                    stopped = !mode.frame_done(keys.get(), &chip);
+                }
+            }
+            for fault in chip.take_faults() {
+                eprintln!("{}", fault);
             }
+            window.request_redraw();
         }
     });
 }
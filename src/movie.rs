@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::chip8::CHIP8;
+use crate::chip8_error::Chip8Error;
+use crate::chip8_keypad::SharedCHIP8Keypad;
+use crate::fault_policy::{FaultAction, FaultPolicy};
+use crate::key_schedule::KeySchedule;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::random::RandomPreset;
+use crate::save_state::{crc32, quirks_from_byte, quirks_to_byte};
+
+const MOVIE_VERSION: u32 = 1;
+pub const HASH_INTERVAL: u64 = 60; // a state hash every second of the recording
+const CUSTOM_RANDOM: &str = "custom";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MovieError {
+    RomMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    // the machine is not where it was in the recording after the frame
+    Desync {
+        frame: u64,
+        expected: u32,
+        actual: u32,
+    },
+    Stopped {
+        frame: u64,
+        error: Chip8Error,
+    },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::RomMismatch { expected, actual } => write!(
+                f,
+                "The movie was recorded with the ROM {:08X}, this one is {:08X}",
+                expected, actual
+            ),
+            MovieError::Desync {
+                frame,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Desync after frame {}: the state hash is {:08X}, the movie has {:08X}",
+                frame, actual, expected
+            ),
+            MovieError::Stopped { frame, error } => {
+                write!(f, "Stopped in frame {}: {}", frame, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+// a recorded session: the keys held in every frame and what the machine started with,
+// which is all it takes to run the same session again,
+// the state hashes of the recording tell where a replay stops matching it
+// in the text form a line has a name and values, # starts a comment:
+// movie 1              the version of the format
+// rom 1A2B3C4D         the CRC-32 of the ROM
+// platform chip8
+// quirks 53            the quirks like in the save states
+// random xorshift      the generator of CXNN and its seed, custom for a generator of the
+//                      program's own, which the replaying machine must have already
+// seed 42
+// faults halt halt halt halt halt   the fault actions, see below
+// speed 11             the instructions per frame
+// frames 1200          the length of the recording
+// keys 120 5           a line of the key schedule
+// hash 60 89ABCDEF     the CRC-32 of the save state after the frame
+// the fault actions are the ones for the stack, memory, jump, endless loop and illegal opcode
+// faults, see FaultAction::from_name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u32,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub random: Option<RandomPreset>,
+    pub seed: u64,
+    pub fault_policy: FaultPolicy,
+    pub instructions_per_frame: usize,
+    pub frames: u64,
+    pub keys: KeySchedule,
+    pub hashes: BTreeMap<u64, u32>, // frames run -> state hash
+}
+
+// the CRC-32 of everything the program can see, see CHIP8::save_state
+pub fn state_hash(chip: &CHIP8) -> u32 {
+    crc32(&chip.save_state().to_bytes())
+}
+
+fn fault_actions(policy: &FaultPolicy) -> [FaultAction; 5] {
+    [
+        policy.stack,
+        policy.memory,
+        policy.jump,
+        policy.endless_loop,
+        policy.illegal_opcode,
+    ]
+}
+
+impl Movie {
+    // an empty recording of the machine as it is before its first frame,
+    // with the ROM loaded and the generator seeded
+    pub fn new(rom: &[u8], chip: &CHIP8) -> Movie {
+        let state = chip.save_state();
+        Movie {
+            rom_hash: crc32(rom),
+            platform: state.platform,
+            quirks: state.quirks,
+            random: state.rng_preset,
+            seed: state.rng_state,
+            fault_policy: chip.fault_policy(),
+            instructions_per_frame: state.instructions_per_frame,
+            frames: 0,
+            keys: KeySchedule::new(),
+            hashes: BTreeMap::new(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut movie = Movie {
+            rom_hash: 0,
+            platform: Platform::Chip8,
+            quirks: Quirks::default(),
+            random: Some(RandomPreset::Xorshift),
+            seed: 0,
+            fault_policy: FaultPolicy::default(),
+            instructions_per_frame: 0,
+            frames: 0,
+            keys: KeySchedule::new(),
+            hashes: BTreeMap::new(),
+        };
+        let mut version = None;
+        let mut rom_hash = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let Some((name, value)) = line.split_once(' ') else {
+                if line.is_empty() {
+                    continue;
+                }
+                return Err(format!("Line {}: expected a name and a value", number + 1));
+            };
+            let error = || format!("Line {}: can't read {}", number + 1, line);
+            let hex = |value: &str| u32::from_str_radix(value, 16).map_err(|_| error());
+            let value = value.trim();
+            match name {
+                "movie" => version = Some(value.parse::<u32>().map_err(|_| error())?),
+                "rom" => rom_hash = Some(hex(value)?),
+                "platform" => movie.platform = Platform::from_name(value).ok_or_else(error)?,
+                "quirks" => {
+                    let byte = u8::from_str_radix(value, 16).map_err(|_| error())?;
+                    movie.quirks = quirks_from_byte(byte).ok_or_else(error)?;
+                }
+                "random" => {
+                    movie.random = match value {
+                        CUSTOM_RANDOM => None,
+                        _ => Some(RandomPreset::from_name(value).ok_or_else(error)?),
+                    }
+                }
+                "seed" => movie.seed = value.parse().map_err(|_| error())?,
+                "faults" => {
+                    let actions = value
+                        .split_whitespace()
+                        .map(FaultAction::from_name)
+                        .collect::<Option<Vec<FaultAction>>>()
+                        .ok_or_else(error)?;
+                    let [stack, memory, jump, endless_loop, illegal_opcode] = actions[..] else {
+                        return Err(error());
+                    };
+                    movie.fault_policy = FaultPolicy {
+                        stack,
+                        memory,
+                        jump,
+                        endless_loop,
+                        illegal_opcode,
+                    };
+                }
+                "speed" => {
+                    movie.instructions_per_frame = value.parse().map_err(|_| error())?;
+                }
+                "frames" => movie.frames = value.parse().map_err(|_| error())?,
+                "keys" => {
+                    let schedule = KeySchedule::parse(value).map_err(|_| error())?;
+                    for (frame, keys) in schedule.changes() {
+                        movie.keys.set_keys(frame, keys);
+                    }
+                }
+                "hash" => {
+                    let (frame, hash) = value.split_once(' ').ok_or_else(error)?;
+                    let frame = frame.parse().map_err(|_| error())?;
+                    movie.hashes.insert(frame, hex(hash.trim())?);
+                }
+                _ => return Err(error()),
+            }
+        }
+        match version {
+            Some(MOVIE_VERSION) => {}
+            Some(version) => return Err(format!("Unknown movie version {}", version)),
+            None => return Err("Not a movie".to_string()),
+        }
+        movie.rom_hash = rom_hash.ok_or("No ROM hash in the movie")?;
+        if movie.instructions_per_frame == 0 {
+            return Err("No speed in the movie".to_string());
+        }
+        Ok(movie)
+    }
+
+    // the text form, parse reads it back
+    pub fn format(&self) -> String {
+        let mut text = format!(
+            "movie {}\nrom {:08X}\nplatform {}\nquirks {:02X}\nrandom {}\nseed {}\nfaults {}\nspeed {}\nframes {}\n",
+            MOVIE_VERSION,
+            self.rom_hash,
+            self.platform.name(),
+            quirks_to_byte(&self.quirks),
+            self.random.map_or(CUSTOM_RANDOM, |preset| preset.name()),
+            self.seed,
+            fault_actions(&self.fault_policy).map(|action| action.name()).join(" "),
+            self.instructions_per_frame,
+            self.frames
+        );
+        for line in self.keys.format().lines() {
+            text.push_str(&format!("keys {}\n", line));
+        }
+        for (frame, hash) in &self.hashes {
+            text.push_str(&format!("hash {} {:08X}\n", frame, hash));
+        }
+        text
+    }
+
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), MovieError> {
+        let actual = crc32(rom);
+        if actual != self.rom_hash {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_hash,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    // sets the machine up like the recorded one, it is created with the platform and the quirks
+    // of the movie
+    pub fn start(&self, chip: &mut CHIP8) {
+        match self.random {
+            Some(preset) => chip.set_random_generator(preset.generator(self.seed)),
+            None => chip.set_random_seed(self.seed),
+        }
+        chip.set_fault_policy(self.fault_policy);
+        chip.set_instructions_per_frame(self.instructions_per_frame);
+    }
+
+    // adds a frame that ran with the keys held, the hash of the last frame stays until the next
+    // one, so a replay checks all of the recording wherever it ends, a frame that stopped the
+    // machine half-way through isn't recorded
+    pub fn record_frame(&mut self, keys: u16, chip: &CHIP8) {
+        if self.keys.keys_at(self.frames) != keys {
+            self.keys.set_keys(self.frames, keys);
+        }
+        if !self.frames.is_multiple_of(HASH_INTERVAL) {
+            self.hashes.remove(&self.frames);
+        }
+        self.frames += 1;
+        self.hashes.insert(self.frames, state_hash(chip));
+    }
+
+    // compares the machine with the recording after the given number of frames
+    pub fn check_frame(&self, frames: u64, chip: &CHIP8) -> Result<(), MovieError> {
+        let Some(&expected) = self.hashes.get(&frames) else {
+            return Ok(());
+        };
+        let actual = state_hash(chip);
+        if actual != expected {
+            return Err(MovieError::Desync {
+                frame: frames,
+                expected,
+                actual,
+            });
+        }
+        Ok(())
+    }
+
+    // plays the whole recording as fast as possible, the keypad of the machine plays the keys,
+    // stops at the first frame that doesn't match it
+    pub fn replay(&self, chip: &mut CHIP8, keypad: &SharedCHIP8Keypad) -> Result<(), MovieError> {
+        for frame in 0..self.frames {
+            keypad.set_keys(self.keys.keys_at(frame));
+            chip.run_frame()
+                .map_err(|error| MovieError::Stopped { frame, error })?;
+            self.check_frame(frame + 1, chip)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_audio::DummyCHIP8Audio;
+    use crate::chip8_display::DummyCHIP8Display;
+
+    const PROGRAM: [u8; 10] = [
+        0xF0, 0x0A, // 200: LD V0, K
+        0xC1, 0xFF, // 202: RND V1, #FF
+        0x81, 0x04, // 204: ADD V1, V0
+        0xA3, 0x00, // 206: LD I, #300
+        0x12, 0x00, // 208: JP #200
+    ];
+
+    // plays the recording on a new machine
+    fn replay(movie: &Movie) -> Result<(), MovieError> {
+        let mut display = DummyCHIP8Display::new();
+        let keypad = SharedCHIP8Keypad::new();
+        let mut chip_keypad = keypad.clone();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut chip_keypad,
+            &mut audio,
+            movie.platform,
+            movie.quirks,
+        );
+        movie.check_rom(&PROGRAM)?;
+        movie.start(&mut chip8);
+        chip8.load_from_memory(&PROGRAM);
+        movie.replay(&mut chip8, &keypad)
+    }
+
+    #[test]
+    fn test_movie() {
+        let mut display = DummyCHIP8Display::new();
+        let keypad = SharedCHIP8Keypad::new();
+        let mut chip_keypad = keypad.clone();
+        let mut audio = DummyCHIP8Audio::new();
+        let quirks = Quirks::cosmac_vip();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut chip_keypad,
+            &mut audio,
+            Platform::Chip8,
+            quirks,
+        );
+        chip8.set_random_generator(RandomPreset::Timing.generator(1234));
+        chip8.set_fault_policy(FaultPolicy::lenient());
+        chip8.set_instructions_per_frame(20);
+        chip8.load_from_memory(&PROGRAM);
+        let mut movie = Movie::new(&PROGRAM, &chip8);
+        // the keys a tester pressed, 3 and 7 for a few frames
+        for frame in 0..150 {
+            let keys = match frame {
+                10..=14 => 1 << 3,
+                70..=71 => 1 << 7,
+                _ => 0,
+            };
+            keypad.set_keys(keys);
+            chip8.run_frame().unwrap();
+            movie.record_frame(keys, &chip8);
+        }
+        assert_eq!(movie.frames, 150);
+        assert_eq!(
+            movie.keys.changes().collect::<Vec<_>>(),
+            vec![(10, 1 << 3), (15, 0), (70, 1 << 7), (72, 0)]
+        );
+        assert_eq!(
+            movie.hashes.keys().collect::<Vec<_>>(),
+            vec![&60, &120, &150]
+        );
+        assert_eq!(movie.hashes[&150], state_hash(&chip8));
+
+        let text = movie.format();
+        assert!(text.starts_with("movie 1\n"));
+        assert!(text.contains(
+            "\nquirks 53\nrandom timing\nseed 1234\nfaults wrap wrap wrap wrap skip\nspeed 20\n\
+             frames 150\nkeys 10 3\n"
+        ));
+        let parsed = Movie::parse(&text).unwrap();
+        assert_eq!(parsed, movie);
+        assert_eq!(replay(&parsed), Ok(()));
+
+        // a key pressed a frame late ends up elsewhere
+        let mut late = movie.clone();
+        late.keys = KeySchedule::parse("11 3\n16 -\n70 7\n72 -").unwrap();
+        assert!(matches!(
+            replay(&late),
+            Err(MovieError::Desync { frame: 60, .. })
+        ));
+        // so do other random numbers
+        let mut reseeded = movie.clone();
+        reseeded.seed = 4321;
+        assert!(matches!(
+            replay(&reseeded),
+            Err(MovieError::Desync { frame: 60, .. })
+        ));
+        // and another speed
+        let mut slower = movie.clone();
+        slower.instructions_per_frame = 11;
+        assert!(matches!(replay(&slower), Err(MovieError::Desync { .. })));
+        let mut other_rom = movie.clone();
+        other_rom.rom_hash ^= 1;
+        assert!(matches!(
+            replay(&other_rom),
+            Err(MovieError::RomMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_recording_stopped_by_fault() {
+        let mut display = DummyCHIP8Display::new();
+        let keypad = SharedCHIP8Keypad::new();
+        let mut chip_keypad = keypad.clone();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut chip_keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        let program = [
+            0xF0, 0x0A, // 200: LD V0, K
+            0x70, 0x01, // 202: ADD V0, 1
+            0x00, 0xEE, // 204: RET, there is nothing to return to
+        ];
+        chip8.load_from_memory(&program);
+        let mut movie = Movie::new(&program, &chip8);
+        // the key is released in frame 22 and the machine stops in the middle of it
+        for frame in 0..30 {
+            let keys = if frame == 20 { 1 << 5 } else { 0 };
+            keypad.set_keys(keys);
+            if chip8.run_frame().is_err() {
+                break;
+            }
+            movie.record_frame(keys, &chip8);
+        }
+        assert_eq!(movie.frames, 21);
+        assert_eq!(movie.hashes.keys().collect::<Vec<_>>(), vec![&21]);
+
+        let movie = Movie::parse(&movie.format()).unwrap();
+        let mut display = DummyCHIP8Display::new();
+        let keypad = SharedCHIP8Keypad::new();
+        let mut chip_keypad = keypad.clone();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut chip_keypad,
+            &mut audio,
+            movie.platform,
+            movie.quirks,
+        );
+        movie.start(&mut chip8);
+        chip8.load_from_memory(&program);
+        assert_eq!(movie.replay(&mut chip8, &keypad), Ok(()));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Movie::parse("rom 12345678"), Err("Not a movie".to_string()));
+        assert_eq!(
+            Movie::parse("movie 2\nrom 12345678"),
+            Err("Unknown movie version 2".to_string())
+        );
+        assert_eq!(
+            Movie::parse("movie 1"),
+            Err("No ROM hash in the movie".to_string())
+        );
+        assert_eq!(
+            Movie::parse("movie 1\nrom 12345678\nplatform pdp11"),
+            Err("Line 3: can't read platform pdp11".to_string())
+        );
+        assert!(Movie::parse("movie 1\nrom 12345678\nhash 60").is_err());
+        assert!(Movie::parse("movie 1\nrom 12345678\nkeys 10 G").is_err());
+        assert_eq!(
+            Movie::parse("movie 1\nrom 12345678"),
+            Err("No speed in the movie".to_string())
+        );
+        assert!(Movie::parse("movie 1\nrom 12345678\nspeed 11\nfaults halt wrap").is_err());
+        assert!(Movie::parse("movie 1\nrom 12345678\nspeed 11\nfaults a b c d e").is_err());
+        let movie =
+            Movie::parse("# a comment\nmovie 1\nrom 0000ABCD\nspeed 11\n\nkeys 5 A\n").unwrap();
+        assert_eq!(movie.fault_policy, FaultPolicy::strict());
+        assert_eq!(movie.rom_hash, 0xABCD);
+        assert_eq!(movie.keys.keys_at(5), 1 << 0xA);
+        // the replaying machine has the generator already
+        let custom = Movie::parse("movie 1\nrom 12345678\nspeed 11\nrandom custom").unwrap();
+        assert_eq!(custom.random, None);
+        assert!(custom.format().contains("\nrandom custom\n"));
+    }
+}
@@ -0,0 +1,53 @@
+use crate::quirks::Quirks;
+
+const MEMORY_SIZE: usize = 0x1000; // 4KB
+const XO_CHIP_MEMORY_SIZE: usize = 0x10000; // 64KB
+
+// the instruction set the machine understands
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    SuperChip, // SUPER-CHIP 1.1
+    XoChip,    // SUPER-CHIP plus the XO-CHIP extensions of Octo
+}
+
+impl Platform {
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "schip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    // the name from_name reads
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    // the quirks the programs written for the platform usually expect
+    pub fn default_quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::default(),
+            Platform::SuperChip => Quirks::superchip(),
+            Platform::XoChip => Quirks::xochip(),
+        }
+    }
+
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => MEMORY_SIZE,
+            Platform::XoChip => XO_CHIP_MEMORY_SIZE,
+        }
+    }
+
+    // whether the SUPER-CHIP instructions are available
+    pub(crate) fn has_superchip(&self) -> bool {
+        matches!(self, Platform::SuperChip | Platform::XoChip)
+    }
+}
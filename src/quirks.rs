@@ -0,0 +1,93 @@
+// how far FX55/FX65 move I
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadStoreIncrement {
+    None,     // I stays where it was
+    X,        // I points at the last register
+    XPlusOne, // I points past the last register
+}
+
+// behaviour of the opcodes that differ between the CHIP-8 interpreters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift_uses_vy: bool, // 8XY6/8XYE shift Vy into Vx instead of shifting Vx in place
+    pub load_store_increment: LoadStoreIncrement,
+    pub jump_uses_vx: bool, // BXNN jumps to XNN + Vx instead of BNNN jumping to NNN + V0
+    pub logic_resets_vf: bool, // 8XY1/8XY2/8XY3 set VF to 0
+    pub wrap_sprites: bool, // sprites wrap around the screen edges instead of being clipped
+    pub display_wait: bool, // drawing waits for the vertical blank, one sprite per frame
+}
+
+impl Quirks {
+    // the original interpreter of RCA COSMAC VIP
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::XPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            wrap_sprites: false,
+            display_wait: true,
+        }
+    }
+
+    // CHIP-48 for HP-48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::X,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1, the successor of CHIP-48
+    pub fn superchip() -> Quirks {
+        Quirks {
+            shift_uses_vy: false,
+            load_store_increment: LoadStoreIncrement::None,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::XPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "default" => Some(Quirks::default()),
+            "vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "schip" => Some(Quirks::superchip()),
+            "xochip" => Some(Quirks::xochip()),
+            _ => None,
+        }
+    }
+}
+
+// the behaviour this emulator has always had
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            shift_uses_vy: true,
+            load_store_increment: LoadStoreIncrement::None,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            wrap_sprites: false,
+            display_wait: false,
+        }
+    }
+}
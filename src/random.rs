@@ -0,0 +1,205 @@
+// where CXNN gets its numbers from, every generator is seedable,
+// so runs with the same seed and input are the same
+pub trait RandomGenerator {
+    fn next_byte(&mut self) -> u8;
+
+    // called on every 60 Hz timer tick, for generators that depend on the timing
+    fn tick(&mut self) {}
+
+    // the whole state as a number, save states keep it and restore puts it back,
+    // every number is a valid state, so a seed is a state too
+    fn state(&self) -> u64;
+    fn restore(&mut self, state: u64);
+
+    // the preset that makes a generator like this one, so save states can make it again,
+    // none for a generator of the program's own, which only gets its state restored
+    fn preset(&self) -> Option<RandomPreset> {
+        None
+    }
+}
+
+// the built-in generators
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RandomPreset {
+    Xorshift,
+    Timing,
+}
+
+impl RandomPreset {
+    pub fn from_name(name: &str) -> Option<RandomPreset> {
+        match name {
+            "xorshift" => Some(RandomPreset::Xorshift),
+            "timing" => Some(RandomPreset::Timing),
+            _ => None,
+        }
+    }
+
+    // the name from_name reads
+    pub fn name(&self) -> &'static str {
+        match self {
+            RandomPreset::Xorshift => "xorshift",
+            RandomPreset::Timing => "timing",
+        }
+    }
+
+    pub fn generator(&self, seed: u64) -> Box<dyn RandomGenerator> {
+        match self {
+            RandomPreset::Xorshift => Box::new(Xorshift::new(seed)),
+            RandomPreset::Timing => Box::new(TimingRandom::new(seed)),
+        }
+    }
+}
+
+const XORSHIFT_ZERO_SEED: u64 = 0x9E37_79B9_7F4A_7C15; // xorshift is stuck at zero
+
+// xorshift64, the default, the numbers don't depend on the timing
+pub struct Xorshift {
+    state: u64, // never zero
+}
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Xorshift {
+        let mut xorshift = Xorshift { state: 0 };
+        xorshift.restore(seed);
+        xorshift
+    }
+}
+
+impl RandomGenerator for Xorshift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 32) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn restore(&mut self, state: u64) {
+        self.state = if state == 0 {
+            XORSHIFT_ZERO_SEED
+        } else {
+            state
+        };
+    }
+
+    fn preset(&self) -> Option<RandomPreset> {
+        Some(RandomPreset::Xorshift)
+    }
+}
+
+// a table of bytes without a pattern, made by xorshift32 at compile time
+const fn random_table() -> [u8; 256] {
+    let mut table = [0; 256];
+    let mut state: u32 = 0x2F6B_1C5D;
+    let mut i = 0;
+    while i < table.len() {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        table[i] = (state >> 24) as u8;
+        i += 1;
+    }
+    table
+}
+
+const TIMING_TABLE: [u8; 256] = random_table();
+
+// the numbers depend on when CXNN runs: a counter that the 60 Hz timer advances
+// picks a byte of a table, which is added to the last number,
+// the scheme of the COSMAC VIP interpreter but not its numbers, the table is made up
+pub struct TimingRandom {
+    counter: u8,
+    last: u8,
+}
+
+impl TimingRandom {
+    pub fn new(seed: u64) -> TimingRandom {
+        let mut generator = TimingRandom {
+            counter: 0,
+            last: 0,
+        };
+        generator.restore(seed);
+        generator
+    }
+}
+
+impl RandomGenerator for TimingRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.counter = self.counter.wrapping_add(1);
+        self.last = self.last.wrapping_add(TIMING_TABLE[self.counter as usize]);
+        self.last
+    }
+
+    fn tick(&mut self) {
+        self.counter = self.counter.wrapping_add(1);
+    }
+
+    fn state(&self) -> u64 {
+        (self.last as u64) << 8 | self.counter as u64
+    }
+
+    fn restore(&mut self, state: u64) {
+        self.counter = state as u8;
+        self.last = (state >> 8) as u8;
+    }
+
+    fn preset(&self) -> Option<RandomPreset> {
+        Some(RandomPreset::Timing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(generator: &mut dyn RandomGenerator, count: usize) -> Vec<u8> {
+        (0..count).map(|_| generator.next_byte()).collect()
+    }
+
+    #[test]
+    fn test_xorshift() {
+        let mut generator = RandomPreset::Xorshift.generator(42);
+        let first = bytes(generator.as_mut(), 16);
+        assert_eq!(first, bytes(&mut Xorshift::new(42), 16));
+        assert_ne!(first, bytes(&mut Xorshift::new(43), 16));
+        // every byte value comes up
+        let mut seen = [false; 256];
+        for byte in bytes(generator.as_mut(), 4096) {
+            seen[byte as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+
+        // the ticks don't matter
+        let state = generator.state();
+        let next = bytes(generator.as_mut(), 8);
+        generator.restore(state);
+        generator.tick();
+        assert_eq!(bytes(generator.as_mut(), 8), next);
+
+        assert_ne!(Xorshift::new(0).state(), 0);
+        assert_ne!(bytes(&mut Xorshift::new(0), 8), vec![0; 8]);
+    }
+
+    #[test]
+    fn test_timing() {
+        let mut generator = TimingRandom::new(0x1234);
+        assert_eq!(generator.state(), 0x1234);
+        let state = generator.state();
+        let next = bytes(&mut generator, 8);
+        generator.restore(state);
+        assert_eq!(bytes(&mut generator, 8), next);
+
+        // a tick between the numbers changes them
+        generator.restore(state);
+        generator.next_byte();
+        generator.tick();
+        assert_ne!(generator.next_byte(), next[1]);
+        assert_eq!(
+            RandomPreset::from_name("timing"),
+            Some(RandomPreset::Timing)
+        );
+    }
+}
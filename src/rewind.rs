@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+
+use crate::save_state::MachineState;
+
+const RUN_OVERHEAD: usize = 16; // what a run costs besides its bytes
+const MERGE_GAP: usize = RUN_OVERHEAD; // runs closer than that are cheaper as one run
+
+// the bytes that turn one encoded snapshot into the one before it,
+// most frames change only a few bytes of the memory and the screen
+struct Delta {
+    length: usize,               // the size of the older snapshot
+    runs: Vec<(usize, Vec<u8>)>, // the older bytes at the offsets where they differ
+}
+
+impl Delta {
+    // the delta that turns newer back into older
+    fn between(newer: &[u8], older: &[u8]) -> Delta {
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        for (offset, &byte) in older.iter().enumerate() {
+            if newer.get(offset) == Some(&byte) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some((start, bytes)) if offset - (*start + bytes.len()) <= MERGE_GAP => {
+                    let end = *start + bytes.len();
+                    bytes.extend_from_slice(&older[end..=offset]);
+                }
+                _ => runs.push((offset, vec![byte])),
+            }
+        }
+        Delta {
+            length: older.len(),
+            runs,
+        }
+    }
+
+    fn apply(&self, snapshot: &mut Vec<u8>) {
+        snapshot.resize(self.length, 0);
+        for (offset, bytes) in &self.runs {
+            snapshot[*offset..*offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.runs
+            .iter()
+            .map(|(_, bytes)| bytes.len() + RUN_OVERHEAD)
+            .sum::<usize>()
+            + RUN_OVERHEAD
+    }
+}
+
+// the last snapshots of a machine, one per frame, for going back in time
+// the newest snapshot is kept whole and the older ones as deltas from the next newer one,
+// the oldest snapshots are dropped when the deltas outgrow the memory budget
+pub struct RewindBuffer {
+    budget: usize,           // bytes
+    latest: Option<Vec<u8>>, // the newest snapshot, encoded with MachineState::to_bytes
+    deltas: VecDeque<Delta>, // the first delta goes from the newest snapshot to the one before it
+    size: usize,             // bytes taken by the snapshots
+}
+
+impl RewindBuffer {
+    pub fn new(budget: usize) -> RewindBuffer {
+        RewindBuffer {
+            budget,
+            latest: None,
+            deltas: VecDeque::new(),
+            size: 0,
+        }
+    }
+
+    // the number of snapshots
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    // the bytes the snapshots take, never much more than the budget
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.size = 0;
+    }
+
+    pub fn push(&mut self, state: &MachineState) {
+        let snapshot = state.to_bytes();
+        if let Some(latest) = self.latest.take() {
+            let delta = Delta::between(&snapshot, &latest);
+            self.size -= latest.len();
+            self.size += delta.size();
+            self.deltas.push_front(delta);
+        }
+        self.size += snapshot.len();
+        self.latest = Some(snapshot);
+        while self.size > self.budget {
+            match self.deltas.pop_back() {
+                Some(delta) => self.size -= delta.size(),
+                None => break,
+            }
+        }
+    }
+
+    // drops the given number of the newest snapshots, fewer if there aren't enough,
+    // and returns the newest one left, which becomes the current one
+    pub fn rewind(&mut self, frames: usize) -> Option<MachineState> {
+        let latest = self.latest.as_mut()?;
+        for _ in 0..frames {
+            let Some(delta) = self.deltas.pop_front() else {
+                break;
+            };
+            self.size -= latest.len() + delta.size();
+            delta.apply(latest);
+            self.size += latest.len();
+        }
+        let state = MachineState::from_bytes(latest).expect("rewind snapshots are valid");
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::CHIP8;
+    use crate::chip8_audio::DummyCHIP8Audio;
+    use crate::chip8_display::DummyCHIP8Display;
+    use crate::chip8_keypad::DummyCHIP8Keypad;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_delta() {
+        let newer = [1, 2, 3, 4, 5, 6, 7, 8];
+        let older = [1, 0, 3, 4, 5, 6, 7, 8, 9, 10];
+        let delta = Delta::between(&newer, &older);
+        // the changes are close enough to become one run
+        assert_eq!(delta.runs, vec![(1, vec![0, 3, 4, 5, 6, 7, 8, 9, 10])]);
+        let mut snapshot = newer.to_vec();
+        delta.apply(&mut snapshot);
+        assert_eq!(snapshot, older);
+
+        let mut snapshot = older.to_vec();
+        Delta::between(&older, &newer).apply(&mut snapshot);
+        assert_eq!(snapshot, newer);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut chip8 = CHIP8::new(
+            &mut display,
+            &mut keypad,
+            &mut audio,
+            Platform::Chip8,
+            Quirks::default(),
+        );
+        chip8.load_from_memory(&[
+            0x70, 0x01, // 200: v0 += 1
+            0xA3, 0x00, // 202: i := 300
+            0xF0, 0x33, // 204: bcd v0
+            0x12, 0x00, // 206: jump 200
+        ]);
+        chip8.set_instructions_per_frame(4);
+
+        let mut buffer = RewindBuffer::new(usize::MAX);
+        assert_eq!(buffer.rewind(1), None);
+        let mut states = Vec::new();
+        for _ in 0..10 {
+            chip8.run_frame().unwrap();
+            states.push(chip8.save_state());
+            buffer.push(&states[states.len() - 1]);
+        }
+        assert_eq!(buffer.len(), 10);
+        // a frame changes a few registers and bytes, far less than a whole snapshot
+        let whole = states[0].to_bytes().len();
+        assert!(buffer.size() < whole + 9 * whole / 4);
+
+        assert_eq!(buffer.rewind(0).as_ref(), Some(&states[9]));
+        assert_eq!(buffer.rewind(3).as_ref(), Some(&states[6]));
+        assert_eq!(buffer.len(), 7);
+        assert_eq!(buffer.rewind(100).as_ref(), Some(&states[0]));
+        assert_eq!(buffer.len(), 1);
+
+        // the oldest snapshots go when the budget runs out
+        let mut buffer = RewindBuffer::new(whole + 200);
+        for state in &states {
+            buffer.push(state);
+        }
+        assert!(buffer.len() < 10);
+        assert!(buffer.size() <= whole + 200);
+        let oldest = 10 - buffer.len();
+        assert_eq!(buffer.rewind(10).as_ref(), Some(&states[oldest]));
+    }
+}
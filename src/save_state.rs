@@ -0,0 +1,714 @@
+use std::fmt;
+
+use crate::chip8_audio::AUDIO_PATTERN_SIZE;
+use crate::chip8_display::VideoMemory;
+use crate::platform::Platform;
+use crate::quirks::{LoadStoreIncrement, Quirks};
+use crate::random::RandomPreset;
+
+// the file starts with a header:
+//   magic "C8ST", format version u16, oldest version able to read the file u16,
+//   payload size u32, CRC-32 of the payload u32
+// the payload is a list of sections: tag u16, size u32, data
+// everything is little endian
+//
+// readers skip the sections they don't know and ignore the bytes past the end of the known ones,
+// so newer versions can add sections and fields without breaking older readers,
+// only the changes the older readers can't handle bump the oldest readable version
+const MAGIC: &[u8; 4] = b"C8ST";
+pub const FORMAT_VERSION: u16 = 1;
+const OLDEST_READABLE_VERSION: u16 = 1; // the oldest reader that understands what we write
+const HEADER_SIZE: usize = 16;
+
+const CPU_SECTION: u16 = 1;
+const TIMERS_SECTION: u16 = 2;
+const MEMORY_SECTION: u16 = 3;
+const VIDEO_SECTION: u16 = 4;
+const MACHINE_SECTION: u16 = 5;
+const EXTENSIONS_SECTION: u16 = 6;
+const RNG_SECTION: u16 = 7;
+const INPUT_SECTION: u16 = 8;
+
+const NO_KEY: u8 = 0xFF;
+const CUSTOM_RANDOM: u8 = 0xFF; // a generator that is not one of the presets
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SaveStateError {
+    Io { path: String, message: String },
+    BadMagic,
+    // the file needs a reader of at least the given version
+    UnsupportedVersion { version: u16, oldest_readable: u16 },
+    Truncated,
+    ChecksumMismatch { expected: u32, actual: u32 },
+    MissingSection { tag: u16 },
+    Invalid { message: String },
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveStateError::Io { path, message } => {
+                write!(f, "Cannot access {}: {}", path, message)
+            }
+            SaveStateError::BadMagic => write!(f, "Not a save state"),
+            SaveStateError::UnsupportedVersion {
+                version,
+                oldest_readable,
+            } => write!(
+                f,
+                "Save state version {} needs a reader of version {}, this is version {}",
+                version, oldest_readable, FORMAT_VERSION
+            ),
+            SaveStateError::Truncated => write!(f, "Save state is truncated"),
+            SaveStateError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Save state checksum {:08X} doesn't match its contents {:08X}",
+                expected, actual
+            ),
+            SaveStateError::MissingSection { tag } => {
+                write!(f, "Save state has no section {}", tag)
+            }
+            SaveStateError::Invalid { message } => write!(f, "Invalid save state: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+// everything a running machine needs to continue from where it was,
+// the backends and the fault policy belong to the frontend and are not part of it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MachineState {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub stack: [usize; 16],
+    pub sp: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub ram: Vec<u8>,
+    pub ca: usize,
+    pub video_memory: VideoMemory,
+    pub planes: u8,
+    pub rpl_flags: [u8; 16],
+    pub audio_pattern: [u8; AUDIO_PATTERN_SIZE],
+    pub audio_pitch: u8,
+    pub halted: bool,
+    pub pressed_key: Option<u8>, // the key FX0A waits to be released
+    pub instructions_per_frame: usize,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub rng_preset: Option<RandomPreset>, // the generator of the machine, see RandomGenerator::preset
+    pub rng_state: u64,                   // see RandomGenerator::state
+}
+
+impl MachineState {
+    // the checks that keep a restored machine from panicking
+    pub fn validate(&self) -> Result<(), SaveStateError> {
+        let invalid = |message: &str| {
+            Err(SaveStateError::Invalid {
+                message: message.to_string(),
+            })
+        };
+        if self.ram.len() != self.platform.memory_size() {
+            return invalid("memory size doesn't match the platform");
+        }
+        if self.sp > self.stack.len() {
+            return invalid("stack pointer is past the stack");
+        }
+        if self.ca >= self.ram.len() {
+            return invalid("program counter is past the memory");
+        }
+        if self.stack[..self.sp]
+            .iter()
+            .any(|&address| address >= self.ram.len())
+        {
+            return invalid("return address is past the memory");
+        }
+        if self.planes > 0b11 {
+            return invalid("unknown bitplanes");
+        }
+        if self.pressed_key.is_some_and(|key| key > 0xF) {
+            return invalid("unknown key");
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+
+        let mut cpu = Vec::new();
+        cpu.extend_from_slice(&self.v);
+        cpu.extend_from_slice(&self.i.to_le_bytes());
+        cpu.extend_from_slice(&(self.ca as u32).to_le_bytes());
+        cpu.push(self.sp as u8);
+        for address in self.stack {
+            cpu.extend_from_slice(&(address as u32).to_le_bytes());
+        }
+        write_section(&mut payload, CPU_SECTION, &cpu);
+
+        write_section(
+            &mut payload,
+            TIMERS_SECTION,
+            &[self.delay_timer, self.sound_timer],
+        );
+
+        // the memory is mostly zeros past the program, it is stored up to the last non-zero byte
+        let used = self
+            .ram
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| last + 1);
+        let mut memory = Vec::new();
+        memory.extend_from_slice(&(self.ram.len() as u32).to_le_bytes());
+        memory.extend_from_slice(&self.ram[..used]);
+        write_section(&mut payload, MEMORY_SECTION, &memory);
+
+        // the pixels of the current resolution, 2 bits per pixel, 4 pixels per byte
+        let mut video = vec![self.video_memory.is_hires() as u8];
+        let (width, height) = (self.video_memory.width(), self.video_memory.height());
+        let mut packed = vec![0; width * height / 4];
+        for y in 0..height {
+            for x in 0..width {
+                let n = y * width + x;
+                packed[n / 4] |= (self.video_memory[y][x] & 0b11) << (n % 4 * 2);
+            }
+        }
+        video.extend_from_slice(&packed);
+        write_section(&mut payload, VIDEO_SECTION, &video);
+
+        let mut machine = vec![
+            platform_to_byte(self.platform),
+            quirks_to_byte(&self.quirks),
+        ];
+        machine.extend_from_slice(&(self.instructions_per_frame as u32).to_le_bytes());
+        machine.push(self.halted as u8);
+        write_section(&mut payload, MACHINE_SECTION, &machine);
+
+        let mut extensions = vec![self.planes];
+        extensions.extend_from_slice(&self.rpl_flags);
+        extensions.extend_from_slice(&self.audio_pattern);
+        extensions.push(self.audio_pitch);
+        write_section(&mut payload, EXTENSIONS_SECTION, &extensions);
+
+        let mut rng = self.rng_state.to_le_bytes().to_vec();
+        rng.push(random_preset_to_byte(self.rng_preset));
+        write_section(&mut payload, RNG_SECTION, &rng);
+
+        write_section(
+            &mut payload,
+            INPUT_SECTION,
+            &[self.pressed_key.unwrap_or(NO_KEY)],
+        );
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&OLDEST_READABLE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<MachineState, SaveStateError> {
+        let mut header = Reader::new(bytes);
+        if header.bytes(MAGIC.len())? != MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let version = header.u16()?;
+        let oldest_readable = header.u16()?;
+        if oldest_readable > FORMAT_VERSION {
+            return Err(SaveStateError::UnsupportedVersion {
+                version,
+                oldest_readable,
+            });
+        }
+        let size = header.u32()? as usize;
+        let expected = header.u32()?;
+        let payload = header.bytes(size)?;
+        let actual = crc32(payload);
+        if actual != expected {
+            return Err(SaveStateError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut sections: Vec<(u16, &[u8])> = Vec::new();
+        let mut reader = Reader::new(payload);
+        while !reader.is_empty() {
+            let tag = reader.u16()?;
+            let size = reader.u32()? as usize;
+            sections.push((tag, reader.bytes(size)?));
+        }
+        let section = |tag: u16| {
+            sections
+                .iter()
+                .find(|(section_tag, _)| *section_tag == tag)
+                .map(|(_, data)| Reader::new(data))
+                .ok_or(SaveStateError::MissingSection { tag })
+        };
+
+        let mut machine = section(MACHINE_SECTION)?;
+        let platform = platform_from_byte(machine.u8()?)?;
+        let quirks = quirks_from_byte(machine.u8()?).ok_or(SaveStateError::Invalid {
+            message: "unknown quirks".to_string(),
+        })?;
+        let instructions_per_frame = machine.u32()? as usize;
+        let halted = machine.u8()? != 0;
+
+        let mut cpu = section(CPU_SECTION)?;
+        let mut v = [0; 16];
+        v.copy_from_slice(cpu.bytes(16)?);
+        let i = cpu.u16()?;
+        let ca = cpu.u32()? as usize;
+        let sp = cpu.u8()? as usize;
+        let mut stack = [0; 16];
+        for address in stack.iter_mut() {
+            *address = cpu.u32()? as usize;
+        }
+
+        let mut timers = section(TIMERS_SECTION)?;
+        let delay_timer = timers.u8()?;
+        let sound_timer = timers.u8()?;
+
+        let mut memory = section(MEMORY_SECTION)?;
+        // the size comes from the file, it is checked before anything is allocated
+        let size = memory.u32()? as usize;
+        if size != platform.memory_size() {
+            return Err(SaveStateError::Invalid {
+                message: "memory size doesn't match the platform".to_string(),
+            });
+        }
+        let mut ram = vec![0; size];
+        let used = memory.rest();
+        if used.len() > ram.len() {
+            return Err(SaveStateError::Invalid {
+                message: "memory contents are larger than the memory".to_string(),
+            });
+        }
+        ram[..used.len()].copy_from_slice(used);
+
+        let mut video = section(VIDEO_SECTION)?;
+        let mut video_memory = VideoMemory::new();
+        video_memory.set_hires(video.u8()? != 0);
+        let (width, height) = (video_memory.width(), video_memory.height());
+        let packed = video.bytes(width * height / 4)?;
+        for y in 0..height {
+            for x in 0..width {
+                let n = y * width + x;
+                video_memory[y][x] = packed[n / 4] >> (n % 4 * 2) & 0b11;
+            }
+        }
+
+        let mut extensions = section(EXTENSIONS_SECTION)?;
+        let planes = extensions.u8()?;
+        let mut rpl_flags = [0; 16];
+        rpl_flags.copy_from_slice(extensions.bytes(16)?);
+        let mut audio_pattern = [0; AUDIO_PATTERN_SIZE];
+        audio_pattern.copy_from_slice(extensions.bytes(AUDIO_PATTERN_SIZE)?);
+        let audio_pitch = extensions.u8()?;
+
+        let mut rng = section(RNG_SECTION)?;
+        let rng_state = rng.u64()?;
+        // the first states had only xorshift and didn't store the generator
+        let rng_preset = if rng.is_empty() {
+            Some(RandomPreset::Xorshift)
+        } else {
+            random_preset_from_byte(rng.u8()?)?
+        };
+
+        let pressed_key = match section(INPUT_SECTION)?.u8()? {
+            NO_KEY => None,
+            key => Some(key),
+        };
+
+        let state = MachineState {
+            v,
+            i,
+            stack,
+            sp,
+            delay_timer,
+            sound_timer,
+            ram,
+            ca,
+            video_memory,
+            planes,
+            rpl_flags,
+            audio_pattern,
+            audio_pitch,
+            halted,
+            pressed_key,
+            instructions_per_frame,
+            platform,
+            quirks,
+            rng_preset,
+            rng_state,
+        };
+        state.validate()?;
+        Ok(state)
+    }
+
+    pub fn save_to_file(&self, path: &str) -> Result<(), SaveStateError> {
+        std::fs::write(path, self.to_bytes()).map_err(|error| SaveStateError::Io {
+            path: path.to_string(),
+            message: error.to_string(),
+        })
+    }
+
+    pub fn load_from_file(path: &str) -> Result<MachineState, SaveStateError> {
+        let bytes = std::fs::read(path).map_err(|error| SaveStateError::Io {
+            path: path.to_string(),
+            message: error.to_string(),
+        })?;
+        MachineState::from_bytes(&bytes)
+    }
+}
+
+fn write_section(payload: &mut Vec<u8>, tag: u16, data: &[u8]) {
+    payload.extend_from_slice(&tag.to_le_bytes());
+    payload.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    payload.extend_from_slice(data);
+}
+
+fn platform_to_byte(platform: Platform) -> u8 {
+    match platform {
+        Platform::Chip8 => 0,
+        Platform::SuperChip => 1,
+        Platform::XoChip => 2,
+    }
+}
+
+fn platform_from_byte(byte: u8) -> Result<Platform, SaveStateError> {
+    match byte {
+        0 => Ok(Platform::Chip8),
+        1 => Ok(Platform::SuperChip),
+        2 => Ok(Platform::XoChip),
+        _ => Err(SaveStateError::Invalid {
+            message: format!("unknown platform {}", byte),
+        }),
+    }
+}
+
+fn random_preset_to_byte(preset: Option<RandomPreset>) -> u8 {
+    match preset {
+        Some(RandomPreset::Xorshift) => 0,
+        Some(RandomPreset::Timing) => 1,
+        None => CUSTOM_RANDOM,
+    }
+}
+
+fn random_preset_from_byte(byte: u8) -> Result<Option<RandomPreset>, SaveStateError> {
+    match byte {
+        0 => Ok(Some(RandomPreset::Xorshift)),
+        1 => Ok(Some(RandomPreset::Timing)),
+        CUSTOM_RANDOM => Ok(None),
+        _ => Err(SaveStateError::Invalid {
+            message: format!("unknown random generator {}", byte),
+        }),
+    }
+}
+
+// the quirks in the order of the fields, a bit each, except for the two bits of how far
+// FX55/FX65 move I
+pub(crate) fn quirks_to_byte(quirks: &Quirks) -> u8 {
+    let load_store_increment = match quirks.load_store_increment {
+        LoadStoreIncrement::None => 0,
+        LoadStoreIncrement::XPlusOne => 1,
+        LoadStoreIncrement::X => 2,
+    };
+    quirks.shift_uses_vy as u8
+        | load_store_increment << 1
+        | (quirks.jump_uses_vx as u8) << 3
+        | (quirks.logic_resets_vf as u8) << 4
+        | (quirks.wrap_sprites as u8) << 5
+        | (quirks.display_wait as u8) << 6
+}
+
+// none when the bits mean nothing
+pub(crate) fn quirks_from_byte(byte: u8) -> Option<Quirks> {
+    let quirk = |n: u8| byte & (1 << n) != 0;
+    let load_store_increment = match (byte >> 1) & 0b11 {
+        0 => LoadStoreIncrement::None,
+        1 => LoadStoreIncrement::XPlusOne,
+        2 => LoadStoreIncrement::X,
+        _ => return None,
+    };
+    if quirk(7) {
+        return None;
+    }
+    Some(Quirks {
+        shift_uses_vy: quirk(0),
+        load_store_increment,
+        jump_uses_vx: quirk(3),
+        logic_resets_vf: quirk(4),
+        wrap_sprites: quirk(5),
+        display_wait: quirk(6),
+    })
+}
+
+// CRC-32 as used by zip and png
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB88320 & mask);
+        }
+    }
+    !crc
+}
+
+// reads little endian values, running out of data means the file is truncated
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Reader<'a> {
+        Reader { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], SaveStateError> {
+        if count > self.data.len() {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> MachineState {
+        let mut video_memory = VideoMemory::new();
+        video_memory.set_hires(true);
+        video_memory[0][0] = 1;
+        video_memory[63][127] = 3;
+        video_memory[10][5] = 2;
+        let mut ram = vec![0; Platform::XoChip.memory_size()];
+        ram[0x200..0x204].copy_from_slice(&[0x60, 0x2A, 0x12, 0x02]);
+        MachineState {
+            v: [7; 16],
+            i: 0x345,
+            stack: [0x202; 16],
+            sp: 3,
+            delay_timer: 10,
+            sound_timer: 20,
+            ram,
+            ca: 0x206,
+            video_memory,
+            planes: 2,
+            rpl_flags: [9; 16],
+            audio_pattern: [0xAA; AUDIO_PATTERN_SIZE],
+            audio_pitch: 100,
+            halted: false,
+            pressed_key: Some(0xB),
+            instructions_per_frame: 1000,
+            platform: Platform::XoChip,
+            quirks: Quirks::xochip(),
+            rng_preset: Some(RandomPreset::Timing),
+            rng_state: 0x1234_5678_9ABC_DEF0,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let state = state();
+        let bytes = state.to_bytes();
+        assert_eq!(&bytes[..4], b"C8ST");
+        // the unused part of the 64 KB memory isn't stored
+        assert!(bytes.len() < 0x1000);
+        assert_eq!(MachineState::from_bytes(&bytes), Ok(state));
+    }
+
+    #[test]
+    fn test_quirks_byte() {
+        for name in ["default", "vip", "chip48", "schip", "xochip"] {
+            let quirks = Quirks::from_name(name).unwrap();
+            assert_eq!(quirks_from_byte(quirks_to_byte(&quirks)), Some(quirks));
+        }
+        assert_eq!(quirks_from_byte(0b0000_0110), None);
+        assert_eq!(quirks_from_byte(0b1000_0000), None);
+    }
+
+    #[test]
+    fn test_corrupted_files() {
+        let bytes = state().to_bytes();
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(
+            MachineState::from_bytes(&bad_magic),
+            Err(SaveStateError::BadMagic)
+        );
+
+        assert_eq!(
+            MachineState::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SaveStateError::Truncated)
+        );
+
+        let mut flipped = bytes.clone();
+        flipped[HEADER_SIZE + 10] ^= 1;
+        assert!(matches!(
+            MachineState::from_bytes(&flipped),
+            Err(SaveStateError::ChecksumMismatch { .. })
+        ));
+
+        let mut invalid = state();
+        invalid.ram.truncate(0x1000);
+        assert!(matches!(
+            MachineState::from_bytes(&invalid.to_bytes()),
+            Err(SaveStateError::Invalid { .. })
+        ));
+        // the program would run or return past the end of the memory
+        let mut invalid = state();
+        invalid.ca = 0x10000;
+        assert_eq!(
+            MachineState::from_bytes(&invalid.to_bytes()),
+            Err(SaveStateError::Invalid {
+                message: "program counter is past the memory".to_string()
+            })
+        );
+        let mut invalid = state();
+        invalid.stack[2] = 0x10000;
+        assert_eq!(
+            MachineState::from_bytes(&invalid.to_bytes()),
+            Err(SaveStateError::Invalid {
+                message: "return address is past the memory".to_string()
+            })
+        );
+        // the entries past the stack pointer don't matter
+        let mut unused = state();
+        unused.stack[3] = 0x10000;
+        assert_eq!(MachineState::from_bytes(&unused.to_bytes()), Ok(unused));
+        // a memory of 4 GB is rejected before it is allocated
+        let mut huge = state();
+        huge.ram = vec![0; 0x300];
+        let mut payload = Vec::new();
+        for (tag, data) in sections(&huge.to_bytes()[HEADER_SIZE..]) {
+            let mut data = data.to_vec();
+            if tag == MEMORY_SECTION {
+                data[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+            }
+            write_section(&mut payload, tag, &data);
+        }
+        assert_eq!(
+            MachineState::from_bytes(&with_header(&payload, FORMAT_VERSION, FORMAT_VERSION)),
+            Err(SaveStateError::Invalid {
+                message: "memory size doesn't match the platform".to_string()
+            })
+        );
+    }
+
+    // rewrites the header the way a newer version would
+    fn with_header(payload: &[u8], version: u16, oldest_readable: u16) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(&oldest_readable.to_le_bytes());
+        bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&crc32(payload).to_le_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn sections(payload: &[u8]) -> Vec<(u16, &[u8])> {
+        let mut sections = Vec::new();
+        let mut reader = Reader::new(payload);
+        while !reader.is_empty() {
+            let tag = reader.u16().unwrap();
+            let size = reader.u32().unwrap() as usize;
+            sections.push((tag, reader.bytes(size).unwrap()));
+        }
+        sections
+    }
+
+    #[test]
+    fn test_versions() {
+        let state = state();
+        let mut payload = state.to_bytes()[HEADER_SIZE..].to_vec();
+
+        // a newer version with a section this one doesn't know
+        write_section(&mut payload, 0x100, &[1, 2, 3]);
+        let newer = with_header(&payload, FORMAT_VERSION + 1, FORMAT_VERSION);
+        assert_eq!(MachineState::from_bytes(&newer), Ok(state.clone()));
+
+        // a newer version older readers can't understand
+        let incompatible = with_header(&payload, FORMAT_VERSION + 1, FORMAT_VERSION + 1);
+        assert_eq!(
+            MachineState::from_bytes(&incompatible),
+            Err(SaveStateError::UnsupportedVersion {
+                version: FORMAT_VERSION + 1,
+                oldest_readable: FORMAT_VERSION + 1
+            })
+        );
+
+        // the generator is unknown, or from before the generators were stored
+        let mut rng = state.rng_state.to_le_bytes().to_vec();
+        let rng_payload = |rng: &[u8]| {
+            let mut payload = Vec::new();
+            for (tag, data) in sections(&state.to_bytes()[HEADER_SIZE..]) {
+                write_section(
+                    &mut payload,
+                    tag,
+                    if tag == RNG_SECTION { rng } else { data },
+                );
+            }
+            with_header(&payload, FORMAT_VERSION, FORMAT_VERSION)
+        };
+        let old = MachineState::from_bytes(&rng_payload(&rng)).unwrap();
+        assert_eq!(old.rng_preset, Some(RandomPreset::Xorshift));
+        assert_eq!(old.rng_state, state.rng_state);
+        rng.push(2);
+        assert_eq!(
+            MachineState::from_bytes(&rng_payload(&rng)),
+            Err(SaveStateError::Invalid {
+                message: "unknown random generator 2".to_string()
+            })
+        );
+        let mut custom = state.clone();
+        custom.rng_preset = None;
+        assert_eq!(MachineState::from_bytes(&custom.to_bytes()), Ok(custom));
+
+        // a required section is gone
+        let mut payload = Vec::new();
+        write_section(&mut payload, CPU_SECTION, &[0; 16]);
+        assert_eq!(
+            MachineState::from_bytes(&with_header(&payload, 1, 1)),
+            Err(SaveStateError::MissingSection {
+                tag: MACHINE_SECTION
+            })
+        );
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::chip8_display::VideoMemory;
+use crate::save_state::crc32;
+
+// the window's colors as gray levels, indexed by the pixel value
+const GRAY_LEVELS: [u8; 4] = [0x00, 0xFF, 0xAA, 0x55];
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+const MAX_STORED_BLOCK: usize = 0xFFFF; // bytes of an uncompressed deflate block
+
+// the screen as an image file, a pixel per CHIP-8 pixel in the current resolution
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Pbm, // black and white, the lit pixels are black
+    Png, // gray levels, the XO-CHIP planes too
+}
+
+impl ImageFormat {
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "pbm" => Some(ImageFormat::Pbm),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+pub fn encode_image(video_memory: &VideoMemory, format: ImageFormat) -> Vec<u8> {
+    match format {
+        ImageFormat::Pbm => encode_pbm(video_memory),
+        ImageFormat::Png => encode_png(video_memory),
+    }
+}
+
+// the binary P4 variant, a bit per pixel and every row padded to whole bytes
+fn encode_pbm(video_memory: &VideoMemory) -> Vec<u8> {
+    let (width, height) = (video_memory.width(), video_memory.height());
+    let mut image = format!("P4\n{} {}\n", width, height).into_bytes();
+    for y in 0..height {
+        for x in (0..width).step_by(8) {
+            let byte = (0..8)
+                .filter(|bit| x + bit < width && video_memory[y][x + bit] != 0)
+                .fold(0u8, |byte, bit| byte | 0x80 >> bit);
+            image.push(byte);
+        }
+    }
+    image
+}
+
+// 8 bit grayscale, the deflate stream is stored without compression,
+// the images are small and it keeps the encoder short
+fn encode_png(video_memory: &VideoMemory) -> Vec<u8> {
+    let (width, height) = (video_memory.width(), video_memory.height());
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 0, 0, 0, 0]); // bit depth, grayscale, compression, filter, interlace
+
+    // every row starts with the filter type, 0 for none
+    let mut rows = Vec::new();
+    for y in 0..height {
+        rows.push(0);
+        rows.extend((0..width).map(|x| GRAY_LEVELS[video_memory[y][x] as usize & 0b11]));
+    }
+    let mut data = vec![0x78, 0x01]; // zlib header, deflate with a 32 KB window
+    let blocks = rows.chunks(MAX_STORED_BLOCK).count();
+    for (index, block) in rows.chunks(MAX_STORED_BLOCK).enumerate() {
+        data.push((index + 1 == blocks) as u8); // the last block, stored
+        data.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(&rows).to_be_bytes());
+
+    let mut image = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut image, b"IHDR", &header);
+    write_chunk(&mut image, b"IDAT", &data);
+    write_chunk(&mut image, b"IEND", &[]);
+    image
+}
+
+fn write_chunk(image: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    image.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = image.len();
+    image.extend_from_slice(kind);
+    image.extend_from_slice(data);
+    let crc = crc32(&image[start..]);
+    image.extend_from_slice(&crc.to_be_bytes());
+}
+
+// the checksum of zlib streams
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn video_memory() -> VideoMemory {
+        let mut video_memory = VideoMemory::new();
+        video_memory[0][0] = 1;
+        video_memory[0][9] = 1;
+        video_memory[31][63] = 3;
+        video_memory
+    }
+
+    #[test]
+    fn test_pbm() {
+        let image = encode_image(&video_memory(), ImageFormat::Pbm);
+        let header = b"P4\n64 32\n";
+        assert_eq!(&image[..header.len()], header);
+        let pixels = &image[header.len()..];
+        assert_eq!(pixels.len(), 8 * 32);
+        assert_eq!(&pixels[..2], &[0x80, 0x40]);
+        assert!(pixels[2..8 * 31 + 7].iter().all(|&byte| byte == 0));
+        assert_eq!(pixels[8 * 31 + 7], 0x01);
+    }
+
+    #[test]
+    fn test_png() {
+        let image = encode_image(&video_memory(), ImageFormat::Png);
+        assert_eq!(&image[..8], &PNG_SIGNATURE);
+
+        // the chunks with their checksums
+        let mut chunks = Vec::new();
+        let mut rest = &image[8..];
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().unwrap());
+            assert_eq!(crc32(&rest[4..8 + length]), crc);
+            chunks.push((rest[4..8].to_vec(), rest[8..8 + length].to_vec()));
+            rest = &rest[12 + length..];
+        }
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].0, b"IHDR");
+        assert_eq!(chunks[0].1, [0, 0, 0, 64, 0, 0, 0, 32, 8, 0, 0, 0, 0]);
+        assert_eq!(chunks[2], (b"IEND".to_vec(), Vec::new()));
+
+        // a single stored block with the rows
+        let data = &chunks[1].1;
+        let rows = &data[7..data.len() - 4];
+        assert_eq!(&data[..3], &[0x78, 0x01, 1]);
+        assert_eq!(u16::from_le_bytes([data[3], data[4]]) as usize, 65 * 32);
+        assert_eq!(rows.len(), 65 * 32);
+        assert_eq!(&rows[..3], &[0, 0xFF, 0]);
+        assert_eq!(rows[10], 0xFF);
+        assert_eq!(rows[65 * 32 - 1], 0x55);
+        assert_eq!(&data[data.len() - 4..], &adler32(rows).to_be_bytes());
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::disassembler::{disassemble, format_instruction, Syntax};
+
+const DISASSEMBLY_WIDTH: usize = 20; // the widest instructions still line up
+
+// the machine right before it executes an instruction
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub cycle: u64,     // instructions executed before this one
+    pub address: usize, // the program counter
+    pub bytes: Vec<u8>, // the instruction, 4 bytes for F000 NNNN
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl TraceEntry {
+    pub fn opcode(&self) -> u16 {
+        (self.bytes[0] as u16) << 8 | self.bytes[1] as u16
+    }
+
+    // one line in the layout of the usual CHIP-8 traces, the state before the instruction:
+    // 00000042 0206 6005 LD V0, #05           V:00 01 .. 0F I:0300 SP:1 DT:00 ST:00
+    // the columns have fixed widths, so traces of two emulators diff line by line
+    pub fn format(&self, syntax: Syntax) -> String {
+        let text = match disassemble(&self.bytes, self.address).first() {
+            Some(line) => format_instruction(line, &BTreeMap::new(), syntax),
+            None => String::new(),
+        };
+        let v = self
+            .v
+            .iter()
+            .map(|value| format!("{:02X}", value))
+            .collect::<Vec<String>>()
+            .join(" ");
+        format!(
+            "{:08} {:04X} {:04X} {:<width$} V:{} I:{:04X} SP:{:X} DT:{:02X} ST:{:02X}",
+            self.cycle,
+            self.address,
+            self.opcode(),
+            text,
+            v,
+            self.i,
+            self.sp,
+            self.delay_timer,
+            self.sound_timer,
+            width = DISASSEMBLY_WIDTH,
+        )
+    }
+
+    // reads a line written by format, the disassembly is skipped,
+    // so traces with different syntaxes compare the same way
+    pub fn parse(line: &str) -> Option<TraceEntry> {
+        let mut columns = line.split_whitespace();
+        let cycle = columns.next()?.parse().ok()?;
+        let address = usize::from_str_radix(columns.next()?, 16).ok()?;
+        let opcode = u16::from_str_radix(columns.next()?, 16).ok()?;
+        let (_, registers) = line.split_once(" V:")?;
+        let mut columns = registers.split_whitespace();
+        let mut v = [0; 16];
+        for register in v.iter_mut() {
+            *register = u8::from_str_radix(columns.next()?, 16).ok()?;
+        }
+        let mut field = |name: &str| {
+            let value = columns.next()?.strip_prefix(name)?;
+            u16::from_str_radix(value, 16).ok()
+        };
+        Some(TraceEntry {
+            cycle,
+            address,
+            bytes: opcode.to_be_bytes().to_vec(),
+            v,
+            i: field("I:")?,
+            sp: field("SP:")? as usize,
+            delay_timer: field("DT:")? as u8,
+            sound_timer: field("ST:")? as u8,
+        })
+    }
+}
+
+// sees every instruction before the machine executes it, see CHIP8::set_tracer
+pub trait Tracer {
+    fn trace(&mut self, entry: &TraceEntry);
+}
+
+// which instructions get traced, everything by default
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<usize>>,
+    pub cycles: Option<RangeInclusive<u64>>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, entry: &TraceEntry) -> bool {
+        self.addresses
+            .as_ref()
+            .is_none_or(|addresses| addresses.contains(&entry.address))
+            && self
+                .cycles
+                .as_ref()
+                .is_none_or(|cycles| cycles.contains(&entry.cycle))
+    }
+}
+
+// hex addresses like 200-2FF, both ends included
+pub fn parse_address_range(text: &str) -> Option<RangeInclusive<usize>> {
+    let (start, end) = text.split_once('-')?;
+    let start = usize::from_str_radix(start.trim_start_matches("0x"), 16).ok()?;
+    let end = usize::from_str_radix(end.trim_start_matches("0x"), 16).ok()?;
+    Some(start..=end)
+}
+
+// decimal cycles like 1000-2000, both ends included
+pub fn parse_cycle_range(text: &str) -> Option<RangeInclusive<u64>> {
+    let (start, end) = text.split_once('-')?;
+    Some(start.parse().ok()?..=end.parse().ok()?)
+}
+
+// writes the trace as text, a line per instruction
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    syntax: Syntax,
+    filter: TraceFilter,
+    error: Option<io::Error>, // the first failed write, tracing stops there
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W, syntax: Syntax, filter: TraceFilter) -> TraceWriter<W> {
+        TraceWriter {
+            writer,
+            syntax,
+            filter,
+            error: None,
+        }
+    }
+
+    // flushes the trace and returns the writer, or the error that stopped the tracing
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, entry: &TraceEntry) {
+        if self.error.is_some() || !self.filter.matches(entry) {
+            return;
+        }
+        if let Err(error) = writeln!(self.writer, "{}", entry.format(self.syntax)) {
+            self.error = Some(error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::CHIP8;
+    use crate::chip8_audio::DummyCHIP8Audio;
+    use crate::chip8_display::DummyCHIP8Display;
+    use crate::chip8_keypad::DummyCHIP8Keypad;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    fn trace(filter: TraceFilter) -> String {
+        let mut display = DummyCHIP8Display::new();
+        let mut keypad = DummyCHIP8Keypad::new();
+        let mut audio = DummyCHIP8Audio::new();
+        let mut tracer = TraceWriter::new(Vec::new(), Syntax::Cowgod, filter);
+        {
+            let mut chip8 = CHIP8::new(
+                &mut display,
+                &mut keypad,
+                &mut audio,
+                Platform::SuperChip,
+                Quirks::default(),
+            );
+            chip8.set_tracer(&mut tracer);
+            chip8.load_from_memory(&[
+                0x60, 0x05, // 200: LD V0, #05
+                0x22, 0x08, // 202: CALL #208
+                0xF0, 0x15, // 204: LD DT, V0
+                0x00, 0xFD, // 206: EXIT
+                0x80, 0x0E, // 208: SHL V0, V0
+                0x00, 0xEE, // 20A: RET
+            ]);
+            chip8.run_cycles(10).unwrap();
+        }
+        String::from_utf8(tracer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_trace() {
+        let zeros = "00 00 00 00 00 00 00 00 00 00 00 00 00 00 00";
+        assert_eq!(
+            trace(TraceFilter::default()),
+            [
+                format!(
+                    "00000000 0200 6005 LD V0, #05           V:00 {zeros} I:0000 SP:0 DT:00 ST:00"
+                ),
+                format!(
+                    "00000001 0202 2208 CALL #208            V:05 {zeros} I:0000 SP:0 DT:00 ST:00"
+                ),
+                format!(
+                    "00000002 0208 800E SHL V0, V0           V:05 {zeros} I:0000 SP:1 DT:00 ST:00"
+                ),
+                format!(
+                    "00000003 020A 00EE RET                  V:0A {zeros} I:0000 SP:1 DT:00 ST:00"
+                ),
+                format!(
+                    "00000004 0204 F015 LD DT, V0            V:0A {zeros} I:0000 SP:0 DT:00 ST:00"
+                ),
+                format!(
+                    "00000005 0206 00FD EXIT                 V:0A {zeros} I:0000 SP:0 DT:0A ST:00"
+                ),
+                String::new(),
+            ]
+            .join("\n")
+        );
+
+        // the subroutine only
+        let lines = trace(TraceFilter {
+            addresses: parse_address_range("208-20B"),
+            cycles: None,
+        });
+        assert_eq!(lines.lines().count(), 2);
+        assert!(lines.starts_with("00000002 0208"));
+
+        // the cycle window is applied together with the addresses
+        let lines = trace(TraceFilter {
+            addresses: parse_address_range("200-2FF"),
+            cycles: parse_cycle_range("3-4"),
+        });
+        assert_eq!(lines.lines().count(), 2);
+        assert!(lines.starts_with("00000003 020A"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let entry = TraceEntry {
+            cycle: 42,
+            address: 0x206,
+            bytes: vec![0xF0, 0x00, 0x12, 0x34],
+            v: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 0xFF],
+            i: 0x1234,
+            sp: 3,
+            delay_timer: 0x20,
+            sound_timer: 1,
+        };
+        // the operand of a long instruction isn't in the line
+        let parsed = TraceEntry::parse(&entry.format(Syntax::Octo)).unwrap();
+        assert_eq!(parsed.bytes, vec![0xF0, 0x00]);
+        assert_eq!(
+            parsed,
+            TraceEntry {
+                bytes: vec![0xF0, 0x00],
+                ..entry
+            }
+        );
+        assert_eq!(TraceEntry::parse("00000001 0200 6005 LD V0, #05"), None);
+        assert_eq!(TraceEntry::parse(""), None);
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(parse_address_range("200-2FF"), Some(0x200..=0x2FF));
+        assert_eq!(parse_address_range("0x200-0x2ff"), Some(0x200..=0x2FF));
+        assert_eq!(parse_address_range("200"), None);
+        assert_eq!(parse_cycle_range("1000-2000"), Some(1000..=2000));
+        assert_eq!(parse_cycle_range("10-x"), None);
+    }
+}
@@ -0,0 +1,547 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, ErrorKind};
+
+use crate::chip8::CHIP8;
+use crate::chip8_error::Chip8Error;
+use crate::chip8_keypad::SharedCHIP8Keypad;
+use crate::disassembler::Syntax;
+use crate::key_schedule::KeySchedule;
+use crate::trace::TraceEntry;
+
+// where two runs of a program stop agreeing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub cycle: u64,               // the first cycle the runs disagree on
+    pub context: Vec<String>,     // the trace lines before it, which both runs agree on
+    pub left: String,             // the trace line of the left run at that cycle, or (end)
+    pub right: String,            // the same for the right run
+    pub differences: Vec<String>, // what differs, like V3: 05 != 07
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "First divergence at cycle {}", self.cycle)?;
+        for line in &self.context {
+            write!(f, "\n  {}", line)?;
+        }
+        write!(f, "\n< {}\n> {}", self.left, self.right)?;
+        for difference in &self.differences {
+            write!(f, "\n  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+// the last lines both runs agree on, a lockstep run keeps the entries and only formats the ones
+// it shows
+struct Context<T> {
+    lines: VecDeque<T>,
+    size: usize,
+}
+
+impl<T> Context<T> {
+    fn new(size: usize) -> Context<T> {
+        Context {
+            lines: VecDeque::new(),
+            size,
+        }
+    }
+
+    fn push(&mut self, line: T) {
+        self.lines.push_back(line);
+        if self.lines.len() > self.size {
+            self.lines.pop_front();
+        }
+    }
+
+    fn divergence(
+        self,
+        format: impl Fn(T) -> String,
+        cycle: u64,
+        left: Option<String>,
+        right: Option<String>,
+        differences: Vec<String>,
+    ) -> Divergence {
+        Divergence {
+            cycle,
+            context: self.lines.into_iter().map(format).collect(),
+            left: left.unwrap_or("(end)".to_string()),
+            right: right.unwrap_or("(end)".to_string()),
+            differences,
+        }
+    }
+}
+
+fn compare(differences: &mut Vec<String>, name: &str, left: String, right: String) {
+    if left != right {
+        differences.push(format!("{}: {} != {}", name, left, right));
+    }
+}
+
+// the disassembly doesn't count, the opcode has it all
+fn entry_differences(left: &TraceEntry, right: &TraceEntry) -> Vec<String> {
+    let mut differences = Vec::new();
+    let d = &mut differences;
+    compare(d, "cycle", left.cycle.to_string(), right.cycle.to_string());
+    compare(
+        d,
+        "PC",
+        format!("{:04X}", left.address),
+        format!("{:04X}", right.address),
+    );
+    compare(
+        d,
+        "opcode",
+        format!("{:04X}", left.opcode()),
+        format!("{:04X}", right.opcode()),
+    );
+    for x in 0..16 {
+        compare(
+            d,
+            &format!("V{:X}", x),
+            format!("{:02X}", left.v[x]),
+            format!("{:02X}", right.v[x]),
+        );
+    }
+    compare(
+        d,
+        "I",
+        format!("{:04X}", left.i),
+        format!("{:04X}", right.i),
+    );
+    compare(d, "SP", left.sp.to_string(), right.sp.to_string());
+    compare(
+        d,
+        "DT",
+        format!("{:02X}", left.delay_timer),
+        format!("{:02X}", right.delay_timer),
+    );
+    compare(
+        d,
+        "ST",
+        format!("{:02X}", left.sound_timer),
+        format!("{:02X}", right.sound_timer),
+    );
+    differences
+}
+
+fn read_entry<B: BufRead>(
+    lines: &mut io::Lines<B>,
+    number: usize,
+) -> io::Result<Option<(String, TraceEntry)>> {
+    let Some(line) = lines.next().transpose()? else {
+        return Ok(None);
+    };
+    match TraceEntry::parse(&line) {
+        Some(entry) => Ok(Some((line, entry))),
+        None => Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Line {} isn't a trace line", number),
+        )),
+    }
+}
+
+// compares two traces written by TraceWriter line by line,
+// the same program with the same input gives the same trace until something differs
+pub fn compare_traces<L: BufRead, R: BufRead>(
+    left: L,
+    right: R,
+    context: usize,
+) -> io::Result<Option<Divergence>> {
+    let mut left = left.lines();
+    let mut right = right.lines();
+    let mut context = Context::new(context);
+    let mut number = 1;
+    loop {
+        let left_line = read_entry(&mut left, number)?;
+        let right_line = read_entry(&mut right, number)?;
+        let (cycle, differences) = match (&left_line, &right_line) {
+            (None, None) => return Ok(None),
+            (Some((_, left)), Some((_, right))) => (left.cycle, entry_differences(left, right)),
+            (Some((_, left)), None) => (left.cycle, vec!["the right trace ends".to_string()]),
+            (None, Some((_, right))) => (right.cycle, vec!["the left trace ends".to_string()]),
+        };
+        if !differences.is_empty() {
+            return Ok(Some(context.divergence(
+                |line| line,
+                cycle,
+                left_line.map(|(line, _)| line),
+                right_line.map(|(line, _)| line),
+                differences,
+            )));
+        }
+        if let Some((line, _)) = left_line {
+            context.push(line);
+        }
+        number += 1;
+    }
+}
+
+// everything the two machines can see besides the registers of the trace,
+// the memory and the screen are large, so only their first difference is named
+fn machine_differences(left: &CHIP8, right: &CHIP8) -> Vec<String> {
+    let mut differences = match (left.trace_entry(), right.trace_entry()) {
+        (Some(left), Some(right)) if left == right => Vec::new(),
+        (Some(left), Some(right)) => entry_differences(&left, &right),
+        (Some(_), None) => vec!["the right machine has stopped".to_string()],
+        (None, Some(_)) => vec!["the left machine has stopped".to_string()],
+        (None, None) => Vec::new(),
+    };
+    let d = &mut differences;
+    if left.stack() != right.stack() {
+        compare(
+            d,
+            "stack",
+            format!("{:03X?}", left.stack()),
+            format!("{:03X?}", right.stack()),
+        );
+    }
+
+    let (left_memory, right_memory) = (left.memory(), right.memory());
+    if left_memory == right_memory && left.video_memory() == right.video_memory() {
+        return differences;
+    }
+    compare(
+        d,
+        "memory size",
+        format!("{:#X}", left_memory.len()),
+        format!("{:#X}", right_memory.len()),
+    );
+    let differing = left_memory
+        .iter()
+        .zip(right_memory)
+        .position(|(left, right)| left != right);
+    if let Some(address) = differing {
+        compare(
+            d,
+            &format!("memory at {:04X}", address),
+            format!("{:02X}", left_memory[address]),
+            format!("{:02X}", right_memory[address]),
+        );
+    }
+
+    let (left_screen, right_screen) = (left.video_memory(), right.video_memory());
+    compare(
+        d,
+        "resolution",
+        format!("{}x{}", left_screen.width(), left_screen.height()),
+        format!("{}x{}", right_screen.width(), right_screen.height()),
+    );
+    if left_screen.width() == right_screen.width() {
+        let (width, height) = (left_screen.width(), left_screen.height());
+        let differing = (0..width * height)
+            .map(|i| (i % width, i / width))
+            .find(|&(x, y)| left_screen[y][x] != right_screen[y][x]);
+        if let Some((x, y)) = differing {
+            compare(
+                d,
+                &format!("pixel at {},{}", x, y),
+                left_screen[y][x].to_string(),
+                right_screen[y][x].to_string(),
+            );
+        }
+    }
+    differences
+}
+
+// runs frames an instruction at a time, like run_frame would,
+// so both machines are compared after every instruction with the timers still ticking,
+// the keypad of the machine plays the key schedule like in a headless run
+struct FrameStepper<'k> {
+    keypad: &'k SharedCHIP8Keypad,
+    instructions: usize, // executed in the current frame
+    frame: u64,
+}
+
+impl FrameStepper<'_> {
+    fn new(keypad: &SharedCHIP8Keypad) -> FrameStepper<'_> {
+        FrameStepper {
+            keypad,
+            instructions: 0,
+            frame: 0,
+        }
+    }
+
+    fn step(&mut self, chip: &mut CHIP8, schedule: &KeySchedule) -> Result<(), Chip8Error> {
+        if self.instructions == 0 {
+            self.keypad.set_keys(schedule.keys_at(self.frame));
+        }
+        chip.step()?;
+        self.instructions += 1;
+        if self.instructions >= chip.instructions_per_frame()
+            || chip.is_waiting_for_vblank()
+            || chip.is_halted()
+        {
+            chip.end_frame();
+            self.instructions = 0;
+            self.frame += 1;
+        }
+        Ok(())
+    }
+}
+
+// runs two machines loaded with the same program side by side for at most the given number of
+// instructions and compares everything after each one, they need the same random numbers,
+// the keypads are the ones of the machines, both play the key schedule
+// an error of only one machine is a divergence, the same error of both ends the comparison
+pub fn run_lockstep(
+    left: &mut CHIP8,
+    right: &mut CHIP8,
+    keypads: (&SharedCHIP8Keypad, &SharedCHIP8Keypad),
+    schedule: &KeySchedule,
+    cycles: u64,
+    context: usize,
+) -> Result<Option<Divergence>, Chip8Error> {
+    let mut context = Context::new(context);
+    let mut left_stepper = FrameStepper::new(keypads.0);
+    let mut right_stepper = FrameStepper::new(keypads.1);
+    let cowgod = |entry: TraceEntry| entry.format(Syntax::Cowgod);
+    let line = |entry: Option<TraceEntry>| entry.map(cowgod);
+    for cycle in 0..=cycles {
+        let differences = machine_differences(left, right);
+        if !differences.is_empty() {
+            return Ok(Some(context.divergence(
+                cowgod,
+                cycle,
+                line(left.trace_entry()),
+                line(right.trace_entry()),
+                differences,
+            )));
+        }
+        let Some(entry) = left.trace_entry() else {
+            return Ok(None);
+        };
+        if cycle == cycles {
+            break;
+        }
+        context.push(entry.clone());
+        match (
+            left_stepper.step(left, schedule),
+            right_stepper.step(right, schedule),
+        ) {
+            (Ok(_), Ok(_)) => {}
+            (Err(left_error), Err(right_error)) if left_error == right_error => {
+                let differences = machine_differences(left, right);
+                if differences.is_empty() {
+                    return Err(left_error);
+                }
+                // the failed instruction counts like in the trace lines
+                return Ok(Some(context.divergence(
+                    cowgod,
+                    left.cycles(),
+                    line(left.trace_entry()),
+                    line(right.trace_entry()),
+                    differences,
+                )));
+            }
+            (left_result, right_result) => {
+                let outcome = |result: Result<(), Chip8Error>| match result {
+                    Ok(_) => "no error".to_string(),
+                    Err(error) => error.to_string(),
+                };
+                let differences = vec![format!(
+                    "error: {} != {}",
+                    outcome(left_result),
+                    outcome(right_result)
+                )];
+                return Ok(Some(context.divergence(
+                    cowgod,
+                    cycle,
+                    line(Some(entry.clone())),
+                    line(Some(entry)),
+                    differences,
+                )));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8_audio::DummyCHIP8Audio;
+    use crate::chip8_display::DummyCHIP8Display;
+    use crate::platform::Platform;
+    use crate::quirks::Quirks;
+
+    #[test]
+    fn test_compare_traces() {
+        let zeros = "00 00 00 00 00 00 00 00 00 00 00 00 00 00";
+        let left = [
+            format!(
+                "00000000 0200 6005 LD V0, #05          V:00 00 {zeros} I:0000 SP:0 DT:00 ST:00"
+            ),
+            format!(
+                "00000001 0202 6107 LD V1, #07          V:05 00 {zeros} I:0000 SP:0 DT:00 ST:00"
+            ),
+            format!(
+                "00000002 0204 8016 SHR V0, V1          V:05 07 {zeros} I:0000 SP:0 DT:00 ST:00"
+            ),
+            format!(
+                "00000003 0206 1206 JP #206             V:03 07 {zeros} I:0000 SP:0 DT:00 ST:00"
+            ),
+        ]
+        .join("\n");
+        // the same program with the other shift quirk, in the other syntax
+        let right = [
+            format!(
+                "00000000 0200 6005 v0 := 0x05          V:00 00 {zeros} I:0000 SP:0 DT:00 ST:00"
+            ),
+            format!(
+                "00000001 0202 6107 v1 := 0x07          V:05 00 {zeros} I:0000 SP:0 DT:00 ST:00"
+            ),
+            format!(
+                "00000002 0204 8016 v0 >>= v1           V:05 07 {zeros} I:0000 SP:0 DT:00 ST:00"
+            ),
+            format!(
+                "00000003 0206 1206 jump 0x206          V:02 07 {zeros} I:0000 SP:0 DT:00 ST:00"
+            ),
+        ]
+        .join("\n");
+        let divergence = compare_traces(left.as_bytes(), right.as_bytes(), 2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.cycle, 3);
+        assert_eq!(divergence.context.len(), 2);
+        assert!(divergence.context[1].starts_with("00000002 0204 8016 SHR"));
+        assert!(divergence.right.contains("jump 0x206"));
+        assert_eq!(divergence.differences, vec!["V0: 03 != 02"]);
+
+        assert_eq!(
+            compare_traces(left.as_bytes(), left.as_bytes(), 2).unwrap(),
+            None
+        );
+        let shorter = left.lines().take(3).collect::<Vec<&str>>().join("\n");
+        let divergence = compare_traces(left.as_bytes(), shorter.as_bytes(), 2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.right, "(end)");
+        assert_eq!(divergence.differences, vec!["the right trace ends"]);
+        assert!(compare_traces(left.as_bytes(), "garbage".as_bytes(), 2).is_err());
+    }
+
+    // two machines loaded with the program, for run_lockstep
+    fn with_machines<T>(
+        program: &[u8],
+        left: (Platform, Quirks),
+        right: (Platform, Quirks),
+        run: impl FnOnce(&mut CHIP8, &mut CHIP8, (&SharedCHIP8Keypad, &SharedCHIP8Keypad)) -> T,
+    ) -> T {
+        let mut displays = [DummyCHIP8Display::new(), DummyCHIP8Display::new()];
+        let keypads = [SharedCHIP8Keypad::new(), SharedCHIP8Keypad::new()];
+        let mut chip_keypads = keypads.clone();
+        let mut audios = [DummyCHIP8Audio::new(), DummyCHIP8Audio::new()];
+        let [left_display, right_display] = &mut displays;
+        let [left_keypad, right_keypad] = &mut chip_keypads;
+        let [left_audio, right_audio] = &mut audios;
+        let mut left = CHIP8::new(left_display, left_keypad, left_audio, left.0, left.1);
+        let mut right = CHIP8::new(right_display, right_keypad, right_audio, right.0, right.1);
+        left.load_from_memory(program);
+        right.load_from_memory(program);
+        run(&mut left, &mut right, (&keypads[0], &keypads[1]))
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let program = [
+            0x60, 0x05, // 200: LD V0, #05
+            0x61, 0x07, // 202: LD V1, #07
+            0xA3, 0x00, // 204: LD I, #300
+            0xF1, 0x55, // 206: LD [I], V1
+            0x80, 0x16, // 208: SHR V0, V1
+            0x00, 0xFD, // 20A: EXIT
+        ];
+        // the load quirk changes I first, the shift quirk V0 later
+        let divergence = with_machines(
+            &program,
+            (Platform::SuperChip, Quirks::cosmac_vip()),
+            (Platform::SuperChip, Quirks::superchip()),
+            |left, right, keys| run_lockstep(left, right, keys, &KeySchedule::new(), 100, 3),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(divergence.cycle, 4);
+        assert_eq!(divergence.context.len(), 3);
+        assert!(divergence.context[2].starts_with("00000003 0206 F155"));
+        assert_eq!(divergence.differences, vec!["I: 0302 != 0300"]);
+        assert!(divergence
+            .to_string()
+            .starts_with("First divergence at cycle 4\n  00000001 0202 6107 LD V1, #07"));
+    }
+
+    #[test]
+    fn test_lockstep_without_divergence() {
+        let program = [
+            0x60, 0x05, // 200: LD V0, #05
+            0xC1, 0xFF, // 202: RND V1, #FF
+            0xF0, 0x15, // 204: LD DT, V0
+            0xF2, 0x07, // 206: LD V2, DT
+            0x32, 0x00, // 208: SE V2, #00
+            0x12, 0x06, // 20A: JP #206
+            0x12, 0x0C, // 20C: JP #20C
+        ];
+        let config = (Platform::Chip8, Quirks::default());
+        with_machines(&program, config, config, |left, right, keys| {
+            left.set_random_seed(1234);
+            right.set_random_seed(1234);
+            let schedule = KeySchedule::new();
+            assert_eq!(run_lockstep(left, right, keys, &schedule, 20, 3), Ok(None));
+            // both wait for the timer, which ticks with the frames, and end in the same endless
+            // loop
+            assert!(run_lockstep(left, right, keys, &schedule, 1000, 3).is_err());
+            assert_eq!(left.program_counter(), 0x20C);
+        });
+    }
+
+    #[test]
+    fn test_lockstep_screen() {
+        let program = [
+            0x60, 0x3E, // 200: LD V0, #3E
+            0xF2, 0x29, // 202: LD F, V2
+            0xD0, 0x15, // 204: DRW V0, V1, 5
+            0x12, 0x06, // 206: JP #206
+        ];
+        let wrapping = Quirks {
+            wrap_sprites: true,
+            ..Quirks::default()
+        };
+        // only the right one wraps the 0 around the edge, right after drawing it
+        let divergence = with_machines(
+            &program,
+            (Platform::Chip8, Quirks::default()),
+            (Platform::Chip8, wrapping),
+            |left, right, keys| run_lockstep(left, right, keys, &KeySchedule::new(), 100, 3),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(divergence.cycle, 3);
+        assert!(divergence.left.starts_with("00000003 0206 1206"));
+        assert!(divergence.context[2].starts_with("00000002 0204 D015"));
+        assert_eq!(divergence.differences, vec!["pixel at 0,0: 0 != 1"]);
+    }
+
+    #[test]
+    fn test_lockstep_keys() {
+        let program = [
+            0xF0, 0x0A, // 200: LD V0, K
+            0x30, 0x05, // 202: SE V0, #05
+            0x12, 0x00, // 204: JP #200
+            0x12, 0x06, // 206: JP #206
+        ];
+        // 4 is pressed and released first, then 5, both machines get past the key wait
+        let schedule = KeySchedule::parse("2 4\n3 -\n5 5\n6 -").unwrap();
+        with_machines(
+            &program,
+            (Platform::Chip8, Quirks::default()),
+            (Platform::Chip8, Quirks::cosmac_vip()),
+            |left, right, keys| {
+                let result = run_lockstep(left, right, keys, &schedule, 100_000, 3);
+                assert!(matches!(result, Err(Chip8Error::EndlessLoop { .. })));
+                assert_eq!(left.registers()[0], 5);
+                assert_eq!(right.registers()[0], 5);
+            },
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use old_rusty_platforms::assembler::assemble;
+use old_rusty_platforms::chip8::CHIP8;
+use old_rusty_platforms::chip8_audio::DummyCHIP8Audio;
+use old_rusty_platforms::chip8_display::{DummyCHIP8Display, VideoMemory};
+use old_rusty_platforms::chip8_keypad::SharedCHIP8Keypad;
+use old_rusty_platforms::fault_policy::FaultPolicy;
+use old_rusty_platforms::headless::{HeadlessRun, StopReason};
+use old_rusty_platforms::key_schedule::KeySchedule;
+use old_rusty_platforms::platform::Platform;
+use old_rusty_platforms::quirks::Quirks;
+use old_rusty_platforms::random::RandomPreset;
+use old_rusty_platforms::screenshot::{encode_image, ImageFormat};
+use old_rusty_platforms::trace::parse_address_range;
+
+// every NAME.scenario in the directory runs a ROM headlessly and its outcome, the hash of
+// the screen and of the chosen memory is compared with NAME.golden
+// CHIP8_RECORD_GOLDENS=1 cargo test --test conformance records the golden files instead,
+// a mismatch leaves the screen it got in NAME.actual.pbm
+const SCENARIO_DIRECTORY: &str = "tests/conformance";
+const RECORD_VARIABLE: &str = "CHIP8_RECORD_GOLDENS";
+const DEFAULT_FRAMES: u64 = 60;
+const DEFAULT_SEED: u64 = 1;
+
+// a scenario has a name = value per line, # starts a comment:
+// rom = game.ch8       the ROM, or an Octo source that is assembled first, next to the scenario
+// platform = chip8     chip8, schip or xochip, chip8 by default
+// quirks = vip         the quirk profile, the platform's own by default
+// faults = lenient     the fault policy, strict by default
+// frames = 600         the most frames to run, 60 by default
+// until = loop key     what else ends the run, see StopReason::from_name
+// keys = 10 5, 12 -    the key schedule, its lines separated by commas
+// random = timing       the random number generator, xorshift by default
+// seed = 42            the seed of the random numbers, 1 by default
+// memory = 300-30F     the memory ranges in the hash, none by default
+struct Scenario {
+    rom: Vec<u8>,
+    platform: Platform,
+    quirks: Option<Quirks>,
+    fault_policy: FaultPolicy,
+    random: RandomPreset,
+    seed: u64,
+    run: HeadlessRun,
+    memory: Vec<RangeInclusive<usize>>,
+}
+
+fn load_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let error = |error: std::io::Error| format!("Can't load {}: {}", path.display(), error);
+    if path.extension().is_some_and(|extension| extension == "8o") {
+        let source = fs::read_to_string(path).map_err(error)?;
+        assemble(&source).map_err(|error| format!("{}:{}", path.display(), error))
+    } else {
+        fs::read(path).map_err(error)
+    }
+}
+
+fn parse_scenario(path: &Path) -> Result<Scenario, String> {
+    let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let mut rom = None;
+    let mut scenario = Scenario {
+        rom: Vec::new(),
+        platform: Platform::Chip8,
+        quirks: None,
+        fault_policy: FaultPolicy::default(),
+        random: RandomPreset::Xorshift,
+        seed: DEFAULT_SEED,
+        run: HeadlessRun::new(DEFAULT_FRAMES),
+        memory: Vec::new(),
+    };
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = || format!("Line {}: can't read {}", number + 1, line);
+        let (name, value) = line.split_once('=').ok_or_else(error)?;
+        let value = value.trim();
+        match name.trim() {
+            "rom" => rom = Some(path.with_file_name(value)),
+            "platform" => scenario.platform = Platform::from_name(value).ok_or_else(error)?,
+            "quirks" => scenario.quirks = Some(Quirks::from_name(value).ok_or_else(error)?),
+            "faults" => scenario.fault_policy = FaultPolicy::from_name(value).ok_or_else(error)?,
+            "frames" => scenario.run.frames = value.parse().map_err(|_| error())?,
+            "until" => {
+                for name in value.split_whitespace() {
+                    scenario
+                        .run
+                        .until
+                        .push(StopReason::from_name(name).ok_or_else(error)?);
+                }
+            }
+            "keys" => scenario.run.schedule = KeySchedule::parse(&value.replace(',', "\n"))?,
+            "random" => scenario.random = RandomPreset::from_name(value).ok_or_else(error)?,
+            "seed" => scenario.seed = value.parse().map_err(|_| error())?,
+            "memory" => {
+                for range in value.split_whitespace() {
+                    scenario
+                        .memory
+                        .push(parse_address_range(range).ok_or_else(error)?);
+                }
+            }
+            _ => return Err(error()),
+        }
+    }
+    let rom: PathBuf = rom.ok_or("No rom in the scenario")?;
+    scenario.rom = load_rom(&rom)?;
+    Ok(scenario)
+}
+
+// FNV-1a, stable across Rust versions unlike the std hashers
+fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF29CE484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+// the resolution is part of the screen
+fn screen_bytes(video_memory: &VideoMemory) -> Vec<u8> {
+    let (width, height) = (video_memory.width(), video_memory.height());
+    let mut bytes = vec![width as u8, height as u8];
+    for y in 0..height {
+        bytes.extend((0..width).map(|x| video_memory[y][x]));
+    }
+    bytes
+}
+
+// what a run of a scenario ends with
+struct Run {
+    outcome: String,
+    golden: String,  // the text of the golden file
+    screen: Vec<u8>, // the screen as a PBM image
+}
+
+fn run_scenario(scenario: &Scenario) -> Result<Run, String> {
+    let mut display = DummyCHIP8Display::new();
+    let keypad = SharedCHIP8Keypad::new();
+    let mut chip_keypad = keypad.clone();
+    let mut audio = DummyCHIP8Audio::new();
+    let platform = scenario.platform;
+    let quirks = scenario.quirks.unwrap_or(platform.default_quirks());
+    let mut chip = CHIP8::new(&mut display, &mut chip_keypad, &mut audio, platform, quirks);
+    chip.set_fault_policy(scenario.fault_policy);
+    chip.set_random_generator(scenario.random.generator(scenario.seed));
+    if scenario.rom.len() > platform.memory_size() - 0x200 {
+        return Err("The ROM doesn't fit into the memory".to_string());
+    }
+    chip.load_from_memory(&scenario.rom);
+
+    // a fault can be the expected outcome too
+    let outcome = match scenario.run.run(&mut chip, &keypad, |_, _| {}) {
+        Ok(outcome) => format!("{:?} after {} frames", outcome.reason, outcome.frames),
+        Err(error) => format!("{} after {} cycles", error, chip.cycles()),
+    };
+    let memory = chip.memory();
+    let mut bytes = Vec::new();
+    for range in &scenario.memory {
+        let bytes_in_range = memory
+            .get(range.clone())
+            .ok_or("The memory range is too large")?;
+        bytes.extend_from_slice(bytes_in_range);
+    }
+    let golden = format!(
+        "outcome {}\nscreen {:016x}\nmemory {:016x}\n",
+        outcome,
+        hash(&screen_bytes(chip.video_memory())),
+        hash(&bytes)
+    );
+    Ok(Run {
+        outcome,
+        golden,
+        screen: encode_image(chip.video_memory(), ImageFormat::Pbm),
+    })
+}
+
+#[test]
+fn conformance() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join(SCENARIO_DIRECTORY);
+    let record = std::env::var_os(RECORD_VARIABLE).is_some();
+    let mut scenarios: Vec<PathBuf> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "scenario")
+        })
+        .collect();
+    scenarios.sort();
+    assert!(!scenarios.is_empty());
+
+    let mut failures = Vec::new();
+    for path in scenarios {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let golden_path = path.with_extension("golden");
+        let screen_path = path.with_extension("actual.pbm");
+        let run = match parse_scenario(&path).and_then(|scenario| run_scenario(&scenario)) {
+            Ok(run) => run,
+            Err(error) => {
+                failures.push(format!("{}: {}", name, error));
+                continue;
+            }
+        };
+        if record {
+            fs::write(&golden_path, &run.golden).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&golden_path) {
+            Ok(expected) if expected == run.golden => {
+                // the screen of an earlier failure is stale now
+                let _ = fs::remove_file(&screen_path);
+            }
+            Ok(expected) => {
+                fs::write(&screen_path, &run.screen).unwrap();
+                failures.push(format!(
+                    "{}: ended with {}, expected\n{}but got\n{}the screen is in {}",
+                    name,
+                    run.outcome,
+                    expected,
+                    run.golden,
+                    screen_path.display()
+                ))
+            }
+            Err(_) => failures.push(format!(
+                "{}: no golden file, {}=1 records it",
+                name, RECORD_VARIABLE
+            )),
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}